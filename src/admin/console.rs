@@ -0,0 +1,43 @@
+use bevy::prelude::*;
+use std::io::BufRead;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Mutex;
+
+use crate::admin::{AdminCommand, AdminCommandEvent, AdminSource};
+
+/*Lines typed on the server terminal, read on their own thread so the game loop never waits for them.*/
+#[derive(Resource)]
+pub struct AdminConsole {
+    lines: Mutex<Receiver<String>>,
+}
+
+pub fn new_admin_console() -> AdminConsole {
+    let (sender, receiver) = channel();
+    std::thread::Builder::new()
+        .name("admin console".to_string())
+        .spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        })
+        .expect("can not start the admin console thread");
+    AdminConsole { lines: Mutex::new(receiver) }
+}
+
+pub fn admin_console_system(console: Res<AdminConsole>, mut admin_commands: EventWriter<AdminCommandEvent>) {
+    let lines = console.lines.lock().unwrap();
+    loop {
+        match lines.try_recv() {
+            Ok(line) if line.trim().is_empty() => {}
+            Ok(line) => match line.parse::<AdminCommand>() {
+                Ok(command) => admin_commands.send(AdminCommandEvent { source: AdminSource::Console, command }),
+                Err(e) => println!("{}", e),
+            },
+            // nothing typed, or no terminal at all and stdin closed right away
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+        }
+    }
+}
@@ -0,0 +1,78 @@
+mod console;
+mod server;
+
+pub use console::*;
+pub use server::*;
+
+use bevy::prelude::*;
+
+pub const ADMIN_HELP: &str = "admin commands:
+  players              list who is connected
+  kick ID              disconnect a client
+  ban ID / unban ID    refuse a client id until the server restarts
+  restart              back to the race lobby
+  maxplayers N         remote clients let in from now on
+  laps N               laps of the next race
+  say TEXT             chat to everybody as the server
+  help";
+
+/*Password a client needs for remote admin commands, the server checks it and a client sends it.*/
+#[derive(Debug, Clone, Default, Resource)]
+pub struct AdminConfig {
+    /*None on the server turns remote admin off.*/
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminCommand {
+    Players,
+    Kick(u64),
+    Ban(u64),
+    Unban(u64),
+    Restart,
+    MaxPlayers(usize),
+    Laps(i32),
+    Say(String),
+    Help,
+}
+
+impl std::str::FromStr for AdminCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+            value.parse().map_err(|_| format!("{} needs a number, got '{}'", name, value))
+        }
+        match name.to_ascii_lowercase().as_str() {
+            "players" => Ok(AdminCommand::Players),
+            "kick" => Ok(AdminCommand::Kick(number(name, rest)?)),
+            "ban" => Ok(AdminCommand::Ban(number(name, rest)?)),
+            "unban" => Ok(AdminCommand::Unban(number(name, rest)?)),
+            "restart" => Ok(AdminCommand::Restart),
+            "maxplayers" => Ok(AdminCommand::MaxPlayers(number(name, rest)?)),
+            "laps" => Ok(AdminCommand::Laps(number(name, rest)?)),
+            "say" if !rest.is_empty() => Ok(AdminCommand::Say(rest.to_string())),
+            "help" => Ok(AdminCommand::Help),
+            "say" => Err(format!("{} needs an argument", name)),
+            _ => Err(format!("unknown admin command '{}', try help", name)),
+        }
+    }
+}
+
+/*Who gave an admin command, replies go back there.*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminSource {
+    Console,
+    /*The player of a host, typing in its own chat.*/
+    Host,
+    Client(u64),
+}
+
+#[derive(Debug, Clone, Event)]
+pub struct AdminCommandEvent {
+    pub source: AdminSource,
+    pub command: AdminCommand,
+}
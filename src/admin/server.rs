@@ -0,0 +1,196 @@
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+
+use crate::admin::{AdminCommand, AdminCommandEvent, AdminConfig, AdminSource, ADMIN_HELP};
+use crate::car::{PlayerCarCommand, PlayerCarCommandEvent};
+use crate::chat::{broadcast_chat, speaks_chat, ChatMessage, MAX_CHAT_CHARS, SERVER_CHAT_ID};
+use crate::launch::LaunchConfig;
+use crate::race::{restart_race, RaceConfig, RaceSession};
+use crate::renet::NetConfig;
+use crate::renet::error::encode;
+use crate::renet::guard::TrafficGuard;
+use crate::renet::server::{HostPlayer, ServerChannel, ServerLobby, ServerMessages, HOST_CLIENT_ID};
+
+fn server_chat_message(text: &str) -> ChatMessage {
+    ChatMessage {
+        id: SERVER_CHAT_ID,
+        name: "Server".to_string(),
+        text: text.chars().take(MAX_CHAT_CHARS).collect(),
+    }
+}
+
+/*Console replies go to the terminal, the others come back as chat lines only the admin sees.*/
+fn reply(server: &mut RenetServer, lobby: &ServerLobby, chat_messages: &mut EventWriter<ChatMessage>, source: AdminSource, text: &str) {
+    for line in text.lines() {
+        match source {
+            AdminSource::Console => println!("{}", line),
+            AdminSource::Host => chat_messages.send(server_chat_message(line)),
+            AdminSource::Client(client_id) if !speaks_chat(lobby, client_id) => {}
+            AdminSource::Client(client_id) => match encode(&ServerMessages::Chat(server_chat_message(line))) {
+                Ok(bytes) => server.send_message(client_id, ServerChannel::ServerMessages, bytes),
+                Err(e) => error!("{}", e),
+            },
+        }
+    }
+}
+
+/*Compares every byte whatever the first difference, so timing tells nothing about the password.*/
+fn same_password(expected: &str, given: &str) -> bool {
+    let (expected, given) = (expected.as_bytes(), given.as_bytes());
+    expected.len() == given.len() && expected.iter().zip(given).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/*Admin commands typed in the chat after a slash. The host player is trusted, clients need the admin password.*/
+pub fn admin_remote_system(
+    config: Res<AdminConfig>,
+    host: Option<Res<HostPlayer>>,
+    lobby: Res<ServerLobby>,
+    mut server: ResMut<RenetServer>,
+    mut guard: ResMut<TrafficGuard>,
+    mut command_events: EventReader<PlayerCarCommandEvent>,
+    mut admin_commands: EventWriter<AdminCommandEvent>,
+    mut chat_messages: EventWriter<ChatMessage>,
+) {
+    for event in command_events.iter() {
+        let PlayerCarCommand::Admin { password, line } = &event.command else {
+            continue;
+        };
+        let source = if host.is_some() && event.client_id == HOST_CLIENT_ID {
+            AdminSource::Host
+        } else {
+            match &config.password {
+                None => {
+                    reply(&mut server, &lobby, &mut chat_messages, AdminSource::Client(event.client_id), "remote admin is off on this server");
+                    continue;
+                }
+                // a wrong guess costs the connection, so guessing takes a reconnect each time
+                Some(expected) if !same_password(expected, password) => {
+                    guard.kick(&mut server, event.client_id, "wrong admin password".to_string());
+                    continue;
+                }
+                Some(_) => AdminSource::Client(event.client_id),
+            }
+        };
+        match line.parse::<AdminCommand>() {
+            Ok(command) => {
+                info!("Admin command from {:?}: {}", source, line);
+                admin_commands.send(AdminCommandEvent { source, command });
+            }
+            Err(e) => reply(&mut server, &lobby, &mut chat_messages, source, &e),
+        }
+    }
+}
+
+pub fn admin_command_system(
+    time: Res<Time>,
+    host: Option<Res<HostPlayer>>,
+    launch: Res<LaunchConfig>,
+    mut lobby: ResMut<ServerLobby>,
+    mut server: ResMut<RenetServer>,
+    mut guard: ResMut<TrafficGuard>,
+    mut net_config: ResMut<NetConfig>,
+    mut session: ResMut<RaceSession>,
+    mut race_config: ResMut<RaceConfig>,
+    mut admin_commands: EventReader<AdminCommandEvent>,
+    mut chat_messages: EventWriter<ChatMessage>,
+) {
+    let now = time.elapsed_seconds_f64();
+    for AdminCommandEvent { source, command } in admin_commands.iter() {
+        let is_host = |client_id: u64| host.is_some() && client_id == HOST_CLIENT_ID;
+        let answer = match command {
+            AdminCommand::Players => {
+                let mut ids: Vec<&u64> = lobby.profiles.keys().collect();
+                ids.sort();
+                let lines: Vec<String> = ids
+                    .into_iter()
+                    .map(|id| {
+                        let profile = &lobby.profiles[id];
+                        let state = if profile.spectator {
+                            " (spectating)"
+                        } else if lobby.suspended.contains_key(id) {
+                            " (reconnecting)"
+                        } else {
+                            ""
+                        };
+                        format!("{} {}{}", id, profile.name, state)
+                    })
+                    .collect();
+                if lines.is_empty() {
+                    "nobody is connected".to_string()
+                } else {
+                    let clients = lobby.profiles.keys().filter(|id| !is_host(**id)).count();
+                    format!("{}/{} clients\n{}", clients, net_config.max_clients, lines.join("\n"))
+                }
+            }
+            AdminCommand::Kick(client_id) if is_host(*client_id) => "the host can not be kicked".to_string(),
+            AdminCommand::Kick(client_id) if !lobby.profiles.contains_key(client_id) => format!("no client {} connected", client_id),
+            // gone already, only the car waits for it
+            AdminCommand::Kick(client_id) if lobby.suspended.contains_key(client_id) => {
+                lobby.suspended.insert(*client_id, now);
+                format!("removed the car of {}", client_id)
+            }
+            AdminCommand::Kick(client_id) => {
+                guard.kick(&mut server, *client_id, "kicked by an admin".to_string());
+                format!("kicked {}", client_id)
+            }
+            AdminCommand::Ban(client_id) if is_host(*client_id) => "the host can not be banned".to_string(),
+            AdminCommand::Ban(client_id) => {
+                guard.ban(*client_id);
+                if lobby.suspended.contains_key(client_id) {
+                    lobby.suspended.insert(*client_id, now);
+                } else if lobby.profiles.contains_key(client_id) {
+                    guard.kick(&mut server, *client_id, "banned by an admin".to_string());
+                }
+                format!("banned {}", client_id)
+            }
+            AdminCommand::Unban(client_id) => {
+                if guard.unban(*client_id) {
+                    format!("unbanned {}", client_id)
+                } else {
+                    format!("{} was not banned", client_id)
+                }
+            }
+            AdminCommand::Restart => {
+                restart_race(&mut session, &mut server, now);
+                "race restarted".to_string()
+            }
+            // the transport was sized for the launch setting, it can only go down from there
+            AdminCommand::MaxPlayers(max_players) if *max_players == 0 || *max_players > launch.net.max_clients => {
+                format!("max players must be between 1 and {}", launch.net.max_clients)
+            }
+            AdminCommand::MaxPlayers(max_players) => {
+                net_config.max_clients = *max_players;
+                format!("max players is now {}, connected players stay", max_players)
+            }
+            AdminCommand::Laps(laps) if *laps < 1 => "laps must be at least 1".to_string(),
+            AdminCommand::Laps(laps) => {
+                race_config.laps = *laps;
+                format!("the next race has {} laps", laps)
+            }
+            AdminCommand::Say(text) => {
+                let message = server_chat_message(text);
+                info!("[chat] {}: {}", message.name, message.text);
+                broadcast_chat(&mut server, &lobby, &message);
+                chat_messages.send(message);
+                continue;
+            }
+            AdminCommand::Help => ADMIN_HELP.to_string(),
+        };
+        reply(&mut server, &lobby, &mut chat_messages, *source, &answer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_password_needs_every_byte() {
+        assert!(same_password("hunter2", "hunter2"));
+        assert!(!same_password("hunter2", "hunter3"));
+        assert!(!same_password("hunter2", "hunter"));
+        assert!(!same_password("hunter2", "hunter22"));
+        assert!(!same_password("hunter2", ""));
+        assert!(same_password("", ""));
+    }
+}
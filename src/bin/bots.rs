@@ -0,0 +1,177 @@
+use std::time::{Duration, Instant};
+use bevy_racing_redux::bot::{bot_app, BotDriving, BotStats};
+use bevy_racing_redux::launch::{LaunchConfig, USAGE as LAUNCH_USAGE};
+use bevy_racing_redux::renet::token::private_key_from_env;
+use bevy_racing_redux::renet::{unsecure_requested, NetworkMode};
+
+const USAGE: &str = "usage: bots [--bots N] [--drive random|weave] [--seconds S] [--report-every S] [client launch settings]
+
+Connect N headless bot clients to a server from one process, each driving its car on its own,
+and print what they receive. Bots sign their own connect tokens with RENET_PRIVATE_KEY, the key
+of the server, or connect without tokens when RENET_UNSECURE is set on both sides.
+--seconds 0, the default, runs until interrupted.";
+
+fn main() {
+        let mut bots: usize = 8;
+        let mut driving = BotDriving::RandomWalk;
+        let mut seconds: f64 = 0.;
+        let mut report_every: f64 = 5.;
+        let mut launch_args: Vec<String> = vec![];
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().unwrap_or_else(|| exit_with_usage(&format!("missing value for {}", arg)));
+            match arg.as_str() {
+                "--bots" => bots = value().parse().unwrap_or_else(|_| exit_with_usage("bots must be a number")),
+                "--drive" => driving = value().parse().unwrap_or_else(|e: String| exit_with_usage(&e)),
+                "--seconds" => seconds = value().parse().unwrap_or_else(|_| exit_with_usage("seconds must be a number")),
+                "--report-every" => report_every = value().parse().unwrap_or_else(|_| exit_with_usage("report-every must be a number")),
+                "--help" | "-h" => {
+                    println!("{}\n\n{}", USAGE, LAUNCH_USAGE);
+                    return;
+                }
+                _ => launch_args.push(arg),
+            }
+        }
+        if bots == 0 || report_every <= 0. || !report_every.is_finite() {
+            exit_with_usage("bots and report-every must be more than 0");
+        }
+        let private_key = if unsecure_requested() {
+            None
+        } else {
+            Some(private_key_from_env().unwrap_or_else(|e| exit_with_usage(&e.to_string())))
+        };
+        let launch = match LaunchConfig::parse(NetworkMode::Client, launch_args) {
+            Ok(Some(launch)) => launch,
+            Ok(None) => {
+                println!("{}\n\n{}", USAGE, LAUNCH_USAGE);
+                return;
+            }
+            Err(e) => exit_with_usage(&e.to_string()),
+        };
+        if launch.mode != NetworkMode::Client {
+            exit_with_usage("bots are clients, leave --mode out");
+        }
+
+        // consecutive ids from a random base, bots of two runs do not take each other's cars
+        let first_id = fastrand::u64(1..u64::MAX / 2);
+        let mut apps: Vec<_> = (0..bots)
+            .map(|i| {
+                let mut launch = launch.clone();
+                launch.net.profile.name = format!("Bot {}", i + 1);
+                bot_app(launch, first_id + i as u64, driving, private_key)
+            })
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|e| {
+                eprintln!("Can not start the bots: {}", e);
+                std::process::exit(1);
+            });
+        println!("{} bots connecting to {}", bots, launch.net.server_addr());
+
+        let tick = Duration::from_secs_f64(1. / launch.tick_rate.0);
+        let started = Instant::now();
+        let mut report = Report::new(bots);
+        loop {
+            let tick_started = Instant::now();
+            for app in apps.iter_mut() {
+                app.update();
+            }
+            let spent = tick_started.elapsed();
+            report.tick(spent, tick);
+
+            if report.since.elapsed().as_secs_f64() >= report_every {
+                let stats: Vec<BotStats> = apps.iter().map(|app| app.world.resource::<BotStats>().clone()).collect();
+                report.print(started.elapsed().as_secs_f64(), &stats);
+            }
+            if seconds > 0. && started.elapsed().as_secs_f64() >= seconds {
+                break;
+            }
+            if let Some(rest) = tick.checked_sub(spent) {
+                std::thread::sleep(rest);
+            }
+        }
+}
+
+/*Rates over the time since the previous report.*/
+struct Report {
+        since: Instant,
+        snapshots: Vec<u32>,
+        ticks: u32,
+        late_ticks: u32,
+        max_tick: Duration,
+}
+
+impl Report {
+        fn new(bots: usize) -> Self {
+            Self {
+                since: Instant::now(),
+                snapshots: vec![0; bots],
+                ticks: 0,
+                late_ticks: 0,
+                max_tick: Duration::ZERO,
+            }
+        }
+
+        fn tick(&mut self, spent: Duration, tick: Duration) {
+            self.ticks += 1;
+            if spent > tick {
+                self.late_ticks += 1;
+            }
+            self.max_tick = self.max_tick.max(spent);
+        }
+
+        fn print(&mut self, elapsed: f64, stats: &[BotStats]) {
+            let seconds = self.since.elapsed().as_secs_f64();
+            let rates: Vec<f64> = stats
+                .iter()
+                .zip(self.snapshots.iter())
+                .filter(|(stats, _)| stats.connected)
+                .map(|(stats, before)| stats.snapshots.wrapping_sub(*before) as f64 / seconds)
+                .collect();
+            let connected: Vec<&BotStats> = stats.iter().filter(|stats| stats.connected).collect();
+            let mean = |values: &mut dyn Iterator<Item = f64>| {
+                let (sum, count) = values.fold((0., 0), |(sum, count), value| (sum + value, count + 1));
+                if count == 0 { 0. } else { sum / count as f64 }
+            };
+
+            println!("[{:>6.0}s] {}/{} connected", elapsed, connected.len(), stats.len());
+            if !connected.is_empty() {
+                println!(
+                    "  snapshots/s per bot  min {:.1}  mean {:.1}  max {:.1}",
+                    rates.iter().copied().fold(f64::INFINITY, f64::min),
+                    mean(&mut rates.iter().copied()),
+                    rates.iter().copied().fold(0., f64::max),
+                );
+                println!(
+                    "  per bot  down {:.1} KB/s  up {:.1} KB/s  rtt {:.1}  loss {:.1}%",
+                    mean(&mut connected.iter().map(|stats| stats.bytes_received_per_second)) / 1024.,
+                    mean(&mut connected.iter().map(|stats| stats.bytes_sent_per_second)) / 1024.,
+                    mean(&mut connected.iter().map(|stats| stats.rtt)),
+                    mean(&mut connected.iter().map(|stats| stats.packet_loss)) * 100.,
+                );
+                println!(
+                    "  total    down {:.1} KB/s  up {:.1} KB/s",
+                    connected.iter().map(|stats| stats.bytes_received_per_second).sum::<f64>() / 1024.,
+                    connected.iter().map(|stats| stats.bytes_sent_per_second).sum::<f64>() / 1024.,
+                );
+            }
+            // late ticks mean the bots themselves are the bottleneck, not the server
+            println!(
+                "  bot ticks {}  late {}  slowest {:.1} ms",
+                self.ticks,
+                self.late_ticks,
+                self.max_tick.as_secs_f64() * 1000.,
+            );
+
+            self.since = Instant::now();
+            self.snapshots = stats.iter().map(|stats| stats.snapshots).collect();
+            self.ticks = 0;
+            self.late_ticks = 0;
+            self.max_tick = Duration::ZERO;
+        }
+}
+
+fn exit_with_usage(message: &str) -> ! {
+        eprintln!("{}\n\n{}", message, USAGE);
+        std::process::exit(2);
+}
@@ -0,0 +1,70 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use bevy_racing_redux::renet::token::*;
+use bevy_racing_redux::renet::profile::{parse_color, PlayerProfile};
+
+const USAGE: &str = "usage: token [--client-id ID] [--server ADDR]... [--name NAME] [--color RRGGBB] [--archetype N] [--spectate] [--out FILE]
+
+Issue a netcode connect token signed with RENET_PRIVATE_KEY, carrying the player profile.
+The token is printed as hex (for RENET_CONNECT_TOKEN) or written to FILE (for RENET_CONNECT_TOKEN_FILE).";
+
+fn main() {
+        let mut client_id: u64 = fastrand::u64(..);
+        let mut server_addresses: Vec<SocketAddr> = vec![];
+        let mut profile = PlayerProfile::default();
+        let mut out: Option<PathBuf> = None;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().unwrap_or_else(|| exit_with_usage(&format!("missing value for {}", arg)));
+            match arg.as_str() {
+                "--client-id" => client_id = value().parse().unwrap_or_else(|_| exit_with_usage("client id must be a number")),
+                "--server" => server_addresses.push(value().parse().unwrap_or_else(|_| exit_with_usage("server must be an ip:port address"))),
+                "--name" => profile.name = value(),
+                "--color" => profile.color = parse_color(&value()).unwrap_or_else(|| exit_with_usage("color must be rrggbb hex")),
+                "--archetype" => profile.archetype = value().parse().unwrap_or_else(|_| exit_with_usage("archetype must be a number")),
+                "--spectate" => profile.spectator = true,
+                "--out" => out = Some(PathBuf::from(value())),
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    return;
+                }
+                other => exit_with_usage(&format!("unknown argument {}", other)),
+            }
+        }
+
+        if server_addresses.is_empty() {
+            let addr = std::env::var("RENET_SERVER_ADDR").unwrap_or("127.0.0.1:5000".to_string());
+            server_addresses.push(addr.parse().unwrap_or_else(|_| exit_with_usage("RENET_SERVER_ADDR must be an ip:port address")));
+        }
+
+        let private_key = private_key_from_env().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        if let Err(e) = profile.validate() {
+            exit_with_usage(&e.to_string());
+        }
+        let user_data = profile.to_user_data().unwrap_or_else(|e| exit_with_usage(&e.to_string()));
+        let token = issue_connect_token(client_id, server_addresses, Some(&user_data), &private_key)
+            .unwrap_or_else(|e| {
+                eprintln!("can not generate connect token: {}", e);
+                std::process::exit(1);
+            });
+
+        match out {
+            Some(path) => {
+                write_token_file(&token, &path).unwrap_or_else(|e| {
+                    eprintln!("can not write {}: {}", path.display(), e);
+                    std::process::exit(1);
+                });
+                eprintln!("connect token for client {} written to {}", client_id, path.display());
+            }
+            None => println!("{}", token_to_string(&token)),
+        }
+}
+
+fn exit_with_usage(message: &str) -> ! {
+        eprintln!("{}\n\n{}", message, USAGE);
+        std::process::exit(2);
+}
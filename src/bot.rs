@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+use bevy_renet::renet::transport::NETCODE_KEY_BYTES;
+use bevy_renet::renet::RenetClient;
+
+use crate::input::controller::PlayerInputState;
+use crate::launch::LaunchConfig;
+use crate::physics::NoLocalPhysics;
+use crate::renet::client::{ClientIdentity, SnapshotsReceived};
+use crate::renet::error::NetworkError;
+use crate::{add_headless_plugins, car_app};
+
+/*How a bot drives, neither needs to know the track.*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotDriving {
+    /*Throttle and steering wander at random, mostly forward.*/
+    RandomWalk,
+    /*Full throttle, steering slowly from side to side.*/
+    Weave,
+}
+
+impl std::str::FromStr for BotDriving {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "random" => Ok(BotDriving::RandomWalk),
+            "weave" => Ok(BotDriving::Weave),
+            other => Err(format!("unknown driving {}, use random or weave", other)),
+        }
+    }
+}
+
+#[derive(Debug, Resource)]
+struct BotDriver {
+    driving: BotDriving,
+    /*Where in its weave the bot starts, so the bots do not all steer alike.*/
+    phase: f32,
+}
+
+/*What a bot received, read by the load report.*/
+#[derive(Debug, Default, Clone, Resource)]
+pub struct BotStats {
+    pub connected: bool,
+    /*Snapshot messages received, counted since the start.*/
+    pub snapshots: u32,
+    /*From RenetClient::network_info.*/
+    pub rtt: f64,
+    pub packet_loss: f64,
+    pub bytes_sent_per_second: f64,
+    pub bytes_received_per_second: f64,
+}
+
+fn bot_drive_system(time: Res<Time>, driver: Res<BotDriver>, mut input: ResMut<PlayerInputState>) {
+    let dt = time.delta_seconds();
+    match driver.driving {
+        BotDriving::RandomWalk => {
+            input.throttle = (input.throttle + (fastrand::f32() - 0.4) * 4. * dt).clamp(-0.2, 1.);
+            // steering drifts back to the middle so the car does not only turn in circles
+            input.steering = (input.steering * (1. - dt) + (fastrand::f32() - 0.5) * 6. * dt).clamp(-1., 1.);
+            input.brake = fastrand::f32() < 0.002;
+        }
+        BotDriving::Weave => {
+            input.throttle = 1.;
+            input.steering = (time.elapsed_seconds() * 0.5 + driver.phase).sin() * 0.6;
+        }
+    }
+}
+
+fn bot_stats_system(client: Res<RenetClient>, snapshots_received: Res<SnapshotsReceived>, mut stats: ResMut<BotStats>) {
+    stats.connected = client.is_connected();
+    stats.snapshots = snapshots_received.0;
+    let network_info = client.network_info();
+    stats.rtt = network_info.rtt;
+    stats.packet_loss = network_info.packet_loss;
+    stats.bytes_sent_per_second = network_info.bytes_sent_per_second;
+    stats.bytes_received_per_second = network_info.bytes_received_per_second;
+}
+
+/*
+A headless client connecting as client_id, driven by the bot instead of a player. Step it with App::update.
+With the server key the bot signs its own connect tokens, without it the server must be unsecure.
+Bots do not simulate anything, their cars are where the server says.
+*/
+pub fn bot_app(mut launch: LaunchConfig, client_id: u64, driving: BotDriving, private_key: Option<[u8; NETCODE_KEY_BYTES]>) -> Result<App, NetworkError> {
+    launch.headless = true;
+    let tick_rate = launch.tick_rate;
+    let mut app = App::new();
+    app.insert_resource(ClientIdentity { client_id, profile: launch.net.profile.clone(), private_key })
+        .insert_resource(NoLocalPhysics);
+    add_headless_plugins(&mut app, tick_rate);
+    car_app(&mut app, launch)?;
+    app.insert_resource(BotDriver { driving, phase: fastrand::f32() * std::f32::consts::TAU })
+        .init_resource::<BotStats>()
+        .add_systems(Update, (bot_drive_system, bot_stats_system));
+    app.finish();
+    app.cleanup();
+    Ok(app)
+}
@@ -4,7 +4,6 @@ use bevy::prelude::*;
 use bevy::render::camera::Projection;
 use bevy::window::CursorGrabMode;
 use crate::input::*;
-use bevy_rapier3d::prelude::PhysicsSet;
 use crate::GameState;
 
 pub fn grab_mouse(
@@ -25,6 +24,10 @@ pub fn grab_mouse(
     }
 }
 
+/*The car the camera follows when we have no car of our own, like a spectator.*/
+#[derive(Component)]
+pub struct SpectatorTarget;
+
 pub struct CarCameraPlugin;
 
 impl Plugin for CarCameraPlugin {
@@ -32,7 +35,8 @@ impl Plugin for CarCameraPlugin {
         app.insert_resource(CameraConfig::default())
             .add_systems(OnEnter(GameState::Playing), camera_start_system)
             .add_systems(Update, (grab_mouse).run_if(in_state(GameState::Playing)))
-            .add_systems(PostUpdate,camera_controller_system.after(PhysicsSet::StepSimulation).run_if(in_state(GameState::Playing)));
+            // physics steps in FixedUpdate, before the camera follows the car
+            .add_systems(PostUpdate,camera_controller_system.run_if(in_state(GameState::Playing)));
     }
 }
 
@@ -216,7 +220,7 @@ pub fn camera_controller_system(
     key_input: Res<Input<KeyCode>>,
     mut pset: ParamSet<(
         Query<(&mut Transform, &mut CameraController), With<Camera>>,
-        Query<&Transform, With<PlayerControlled>>,
+        Query<&Transform, Or<(With<PlayerControlled>, With<SpectatorTarget>)>>,
         Query<&mut Transform, With<DirectionalLight>>,
     )>,
     windows: Query<&Window>,
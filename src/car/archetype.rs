@@ -0,0 +1,50 @@
+use bevy::prelude::*;
+
+use crate::car::CarSpec;
+use crate::game_asset::GameAssets;
+
+/*A car a player can pick, the archetype id is its index in CAR_ARCHETYPES.*/
+pub struct CarArchetype {
+    pub name: &'static str,
+    pub spec: fn() -> CarSpec,
+    #[cfg(feature = "graphics")]
+    pub body: fn(&GameAssets) -> Handle<Scene>,
+    #[cfg(feature = "graphics")]
+    pub wheel: fn(&GameAssets) -> Handle<Scene>,
+}
+
+impl CarArchetype {
+    /*Body and wheel scenes, None without assets and the car is physics only.*/
+    #[cfg(feature = "graphics")]
+    pub fn scenes(&self, game_asset: Option<&GameAssets>) -> Option<(Handle<Scene>, Handle<Scene>)> {
+        game_asset.map(|game_asset| ((self.body)(game_asset), (self.wheel)(game_asset)))
+    }
+
+    #[cfg(not(feature = "graphics"))]
+    pub fn scenes(&self, _game_asset: Option<&GameAssets>) -> Option<(Handle<Scene>, Handle<Scene>)> {
+        None
+    }
+}
+
+pub const CAR_ARCHETYPES: &[CarArchetype] = &[
+    CarArchetype {
+        name: "race",
+        spec: CarSpec::default,
+        #[cfg(feature = "graphics")]
+        body: |assets| assets.car_body.clone(),
+        #[cfg(feature = "graphics")]
+        wheel: |assets| assets.wheel_body.clone(),
+    },
+    CarArchetype {
+        name: "touring",
+        spec: CarSpec::touring,
+        #[cfg(feature = "graphics")]
+        body: |assets| assets.car_body.clone(),
+        #[cfg(feature = "graphics")]
+        wheel: |assets| assets.wheel_body.clone(),
+    },
+];
+
+pub fn car_archetype(archetype: u128) -> Option<&'static CarArchetype> {
+    usize::try_from(archetype).ok().and_then(|i| CAR_ARCHETYPES.get(i))
+}
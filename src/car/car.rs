@@ -5,7 +5,7 @@ use bevy_rapier3d::prelude::*;
 use crate::game_asset::GameAssets;
 use crate::input::*;
 
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone, Copy)]
 pub struct Car {
     pub gas: f32,
     pub brake: f32,
@@ -52,29 +52,23 @@ impl CarWheels {
     }
 }
 
-pub fn spawn_car(
+/*Without scenes the car is physics only, that is what the headless server spawns.*/
+pub fn spawn_car_with_spec(
     cmd: &mut Commands,
-    car_scene: &Handle<Scene>,
-    wheel_scene: &Handle<Scene>,
+    scenes: Option<(&Handle<Scene>, &Handle<Scene>)>,
     player: bool,
     transform: Transform,
+    spec: CarSpec,
 ) -> Entity {
-    let spec = CarSpec::default();
     let wheel_spec = WheelSpec::new(spec.wheel_radius, spec.wheel_width);
     let mounts = spec.wheel_mount.clone();
 
-    #[cfg(feature = "graphics")]
+    let (car_scene, wheel_scene) = scenes.unzip();
     let car_id = spawn_car_body(cmd, car_scene, Car::new(transform), spec);
-    #[cfg(not(feature = "graphics"))]
-    let car_id = spawn_car_body(cmd, Car::new(transform), spec);
 
     let wheels = CarWheels::new(mounts.map(|mount| {
         let joint = ImpulseJoint::new(car_id, build_joint(mount.anchor, mount.left));
-        #[cfg(feature = "graphics")]
-        let wheel_id = spawn_wheel(cmd, wheel_scene, &wheel_spec, &mount, transform, joint);
-        #[cfg(not(feature = "graphics"))]
-        let wheel_id = spawn_wheel(cmd, &wheel_spec, &mount, transform, joint);
-        wheel_id
+        spawn_wheel(cmd, wheel_scene, &wheel_spec, &mount, transform, joint)
     }));
     cmd.entity(car_id).insert(wheels);
     if player {
@@ -85,7 +79,7 @@ pub fn spawn_car(
 
 pub fn spawn_car_body(
     cmd: &mut Commands,
-    #[cfg(feature = "graphics")] car_gl: &Handle<Scene>,
+    car_gl: Option<&Handle<Scene>>,
     car: Car,
     spec: CarSpec,
 ) -> Entity {
@@ -98,17 +92,10 @@ pub fn spawn_car_body(
         car_border_radius,
     );
     let transform = car.spawn_transform;
-    cmd.spawn((
+    let car_id = cmd.spawn((
         Name::new("car"),
         car,
         spec,
-        #[cfg(feature = "graphics")]
-        SceneBundle {
-            scene: car_gl.clone(),
-            transform,
-            ..default()
-        },
-        #[cfg(not(feature = "graphics"))]
         TransformBundle::from_transform(transform),
         (
             collider,
@@ -139,5 +126,13 @@ pub fn spawn_car_body(
             Velocity::zero(),
         ),
     ))
-    .id()
+    .id();
+    if let Some(scene) = car_gl {
+        cmd.entity(car_id).insert(SceneBundle {
+            scene: scene.clone(),
+            transform,
+            ..default()
+        });
+    }
+    car_id
 }
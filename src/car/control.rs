@@ -1,10 +1,16 @@
 use crate::car::Car;
 use bevy::prelude::*;
-use crate::input::{*,controller::*};
+use crate::input::controller::*;
+#[cfg(feature = "graphics")]
+use crate::input::*;
+#[cfg(feature = "graphics")]
 use crate::controller::PlayerController;
+#[cfg(feature = "graphics")]
 use crate::camera::CameraConfig;
+#[cfg(feature = "graphics")]
 use crate::gamestate::GameState;
 
+#[cfg(feature = "graphics")]
 pub fn do_input<const MAXVIEW : u32>(
     mut controller: ResMut<PlayerController<MAXVIEW>>,
     mut camera_config: ResMut<CameraConfig>,
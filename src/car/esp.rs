@@ -29,8 +29,9 @@ const WHEEL_RAY_SHIFT: Vec3 = Vec3 {
     z: 0.,
 };
 
+/*Runs in PhysicsStep, once per tick of FixedTime.*/
 pub fn esp_system(
-    time: Res<Time>,
+    fixed_time: Res<FixedTime>,
     mut car_query: Query<(&mut Car, &CarSpec, &CarWheels, &Velocity, &Transform)>,
     mut wheels_query: Query<(
         &Wheel,
@@ -39,9 +40,8 @@ pub fn esp_system(
         &Velocity,
         &mut ImpulseJoint,
     )>,
-    #[cfg(feature = "graphics")] mut gizmos: Gizmos,
 ) {
-    let d_seconds = time.delta_seconds();
+    let d_seconds = fixed_time.period.as_secs_f32();
     for (mut car, spec, car_wheels, velocity, transform) in car_query.iter_mut() {
         let car_vector = transform.rotation.mul_vec3(Vec3::Z);
         let car_vector_norm = car_vector.normalize();
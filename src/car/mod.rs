@@ -1,21 +1,38 @@
 mod car;
+mod archetype;
 mod esp;
 mod joint;
 mod spawn;
+#[cfg(feature = "graphics")]
+mod spectator;
 mod spec;
 mod wheel;
+#[cfg(feature = "graphics")]
 mod sensor;
+#[cfg(feature = "graphics")]
 mod dash;
+#[cfg(feature = "graphics")]
+mod nameplate;
 mod network;
+mod prediction;
+mod replication;
 pub mod control;
 
 pub use network::*;
+pub use prediction::*;
+pub use replication::*;
 pub use car::*;
+pub use archetype::*;
 pub use esp::*;
 pub use spec::*;
 pub use wheel::*;
 pub use spawn::*;
+#[cfg(feature = "graphics")]
 pub use dash::*;
+#[cfg(feature = "graphics")]
+pub use nameplate::*;
+#[cfg(feature = "graphics")]
+pub use spectator::*;
 pub use control::*;
 
 use bevy::prelude::SystemSet;
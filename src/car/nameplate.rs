@@ -0,0 +1,63 @@
+use bevy::prelude::*;
+
+use crate::camera::CameraController;
+use crate::game_asset::GameAssets;
+use crate::renet::profile::PlayerProfile;
+
+/*Nameplates further than this are hidden.*/
+const NAMEPLATE_DISTANCE: f32 = 150.;
+
+/*Screen space label following a car.*/
+#[derive(Component)]
+pub struct Nameplate {
+    pub target: Entity,
+}
+
+pub fn spawn_nameplate(cmd: &mut Commands, game_asset: &GameAssets, target: Entity, profile: &PlayerProfile) {
+    let [r, g, b] = profile.color;
+    cmd.spawn(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            ..default()
+        },
+        text: Text::from_section(
+            profile.name.clone(),
+            TextStyle {
+                font: game_asset.dash_font.clone(),
+                font_size: 18.0,
+                color: Color::rgb_u8(r, g, b),
+            },
+        ),
+        visibility: Visibility::Hidden,
+        ..default()
+    })
+    .insert(Nameplate { target });
+}
+
+pub fn nameplate_system(
+    mut cmd: Commands,
+    cameras: Query<(&Camera, &GlobalTransform), With<CameraController>>,
+    targets: Query<&GlobalTransform>,
+    mut nameplates: Query<(Entity, &Nameplate, &Node, &mut Style, &mut Visibility)>,
+) {
+    let Ok((camera, camera_transform)) = cameras.get_single() else {
+        return;
+    };
+    for (entity, nameplate, node, mut style, mut visibility) in nameplates.iter_mut() {
+        let Ok(target) = targets.get(nameplate.target) else {
+            cmd.entity(entity).despawn_recursive();
+            continue;
+        };
+        let position = target.translation() + Vec3::Y * 1.5;
+        let in_range = camera_transform.translation().distance(position) < NAMEPLATE_DISTANCE;
+        match camera.world_to_viewport(camera_transform, position).filter(|_| in_range) {
+            Some(screen) => {
+                let size = node.size();
+                style.left = Val::Px(screen.x - size.x / 2.);
+                style.top = Val::Px(screen.y - size.y);
+                *visibility = Visibility::Inherited;
+            }
+            None => *visibility = Visibility::Hidden,
+        }
+    }
+}
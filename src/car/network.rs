@@ -1,54 +1,175 @@
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
-use crate::input::controller::PlayerInputState;
+use crate::input::controller::{PlayerInputState,TickedInputState};
 use crate::renet::client::*;
 use crate::renet::server::*;
 use bevy_renet::renet::{RenetClient,RenetServer,Bytes};
 use crate::car::*;
 use crate::renet::server::PlayerCommandProcessor;
-use crate::renet::{PlayerSpawner,NetPlayer};
+use crate::renet::{PlayerSpawner,NetPlayer,LastInputTick,InputQueue,tick_after};
+use crate::renet::interpolation::*;
+use crate::renet::reconnect::ConnectionStatus;
+use crate::renet::interest::{ReplicationConfig,ReplicationPriorities};
+use crate::renet::quantize::*;
+use crate::renet::error::{NetError,decode};
 use crate::game_asset::GameAssets;
+use crate::track::CarTrack;
+use crate::race::RaceProgress;
+use crate::chat::validate_chat;
+use crate::renet::profile::PlayerProfile;
+use bevy_rapier3d::prelude::{RigidBody,Velocity};
+use bincode::Options;
+use std::collections::HashMap;
 
+/*One snapshot per client, car updates are deltas against the last snapshot that client acknowledged.*/
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NetworkedEntities {
+    pub tick: u32,
+    pub time: f64,
+    pub baseline: Option<u32>,
     pub entities: Vec<Entity>,
-    pub translations: Vec<[f32; 3]>,
-    pub rotations: Vec<[f32; 4]>,
-    pub wheels_translations: Vec<[[f32; 3]; 4]>,
-    pub wheels_rotations: Vec<[[f32; 4]; 4]>,
+    pub updates: Vec<CarUpdate>,
+}
+
+/*Latest wheel state of a remote car, applied on top of its interpolated transform.*/
+#[derive(Component, Default)]
+pub struct RemoteWheels {
+    pub steering: f32,
+    pub spin: [f32; 4],
+}
+
+/*Queue a snapshot for a remote entity, the first one turns it kinematic so physics stops fighting the replication.*/
+fn push_remote_snapshot(
+    cmd: &mut Commands,
+    buffers: &mut Query<&mut SnapshotBuffer>,
+    config: &InterpolationConfig,
+    entity: Entity,
+    snapshot: TransformSnapshot,
+) {
+    if let Ok(mut buffer) = buffers.get_mut(entity) {
+        buffer.push(snapshot, config.buffer_len);
+    } else {
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(snapshot, config.buffer_len);
+        cmd.entity(entity)
+            .insert(buffer)
+            .insert(RigidBody::KinematicPositionBased);
+    }
+}
+
+/*Resolve every update of a snapshot against its baseline, returns the updated cars and every car known at its tick.*/
+fn decode_snapshot(
+    networked_entities: &NetworkedEntities,
+    history: &SnapshotHistory,
+) -> Result<(HashMap<Entity, CarNetState>, HashMap<Entity, CarNetState>), NetError> {
+    let baseline = match networked_entities.baseline {
+        Some(tick) => Some(history.get(tick).ok_or(NetError::MissingBaseline(tick))?),
+        None => None,
+    };
+    let mut states = HashMap::new();
+    for (entity, update) in networked_entities.entities.iter().zip(networked_entities.updates.iter()) {
+        let base = baseline.and_then(|baseline| baseline.get(entity));
+        if let Some(state) = update.decode(base) {
+            states.insert(*entity, state);
+        }
+    }
+    let mut known = baseline.cloned().unwrap_or_default();
+    known.extend(states.iter().map(|(entity, state)| (*entity, *state)));
+    Ok((states, known))
+}
+
+/*
+Every connection starts over, like SnapshotAck does: predicted states and baselines of the last
+one would be matched against the ticks of the next.
+*/
+pub fn client_forget_history_system(status: Res<ConnectionStatus>, mut prediction: ResMut<PredictionHistory>, mut received: ResMut<SnapshotHistory>) {
+    if status.is_changed() && *status != ConnectionStatus::Connected {
+        *prediction = PredictionHistory::default();
+        *received = SnapshotHistory::default();
+    }
 }
 
 pub fn client_sync_entities(
     mut cmd: Commands,
     mut client: ResMut<RenetClient>,
+    time: Res<Time>,
+    bounds: Res<QuantizationBounds>,
+    mut snapshot_clock: ResMut<SnapshotClock>,
+    mut snapshot_ack: ResMut<SnapshotAck>,
+    mut snapshots_received: ResMut<SnapshotsReceived>,
+    mut received: ResMut<SnapshotHistory>,
+    interpolation_config: Res<InterpolationConfig>,
     network_mapping: Res<NetworkMapping>,
+    mut prediction: ResMut<PredictionHistory>,
     car_wheels: Query<&CarWheels>,
-    mut wheel_query: Query<&mut Transform, With<Wheel>>,
+    controlled: Query<(), With<ControlledPlayer>>,
+    mut buffers: Query<&mut SnapshotBuffer>,
+    mut remote_wheels: Query<&mut RemoteWheels>,
 ) 
 {
     while let Some(message) = client.receive_message(ServerChannel::NetworkedEntities) {
-        let networked_entities: NetworkedEntities = bincode::deserialize(&message).unwrap();
-        for i in 0..networked_entities.entities.len() {
+        snapshots_received.0 = snapshots_received.0.wrapping_add(1);
+        let networked_entities: NetworkedEntities = match snapshot_codec().deserialize(&message) {
+            Ok(networked_entities) => networked_entities,
+            Err(e) => {
+                warn!("Dropping snapshot: {}", NetError::Decode(e));
+                continue;
+            }
+        };
+        let (states, known) = match decode_snapshot(&networked_entities, &received) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                // The server will delta from a newer ack soon, the snapshot is simply lost.
+                debug!("Dropping snapshot {}: {}", networked_entities.tick, e);
+                continue;
+            }
+        };
+        let snapshot_tick = networked_entities.tick;
+        let snapshot_time = networked_entities.time;
+        snapshot_clock.observe(snapshot_time, time.elapsed_seconds_f64());
+        received.insert(snapshot_tick, known);
+        if tick_after(snapshot_tick, snapshot_ack.0) {
+            snapshot_ack.0 = snapshot_tick;
+        }
+
+        for (server_entity, state) in states.iter() {
+            let Some(entity) = network_mapping.0.get(server_entity) else {
+                continue;
+            };
+            let translation = bounds.dequantize_position(state.translation);
+            let rotation = decompress_quat(state.rotation);
 
-            if let Some(entity) = network_mapping.0.get(&networked_entities.entities[i]) {
-                let translation = networked_entities.translations[i].into();
-                let rotation: Quat = Quat::from_array(networked_entities.rotations[i]);
-                let transform = Transform {
+            // Our own car is predicted locally, client_reconcile_system corrects it when the server disagrees.
+            if controlled.contains(*entity) {
+                prediction.correct(AuthoritativeState {
+                    acked_tick: state.input_tick,
                     translation,
                     rotation,
-                    ..Default::default()
-                };
-                cmd.entity(*entity).insert(transform);
-
-                let translations = networked_entities.wheels_translations[i];
-                let rotations = networked_entities.wheels_rotations[i];
-
-                let car_wheels = car_wheels.get(*entity);
-                if let Ok(car_wheels) = car_wheels {
-                    for (i, e) in car_wheels.entities.iter().enumerate() {
-                        let mut wheel_transform = wheel_query.get_mut(*e).unwrap();
-                        wheel_transform.translation = translations[i].into();
-                        wheel_transform.rotation = Quat::from_array(rotations[i]);
+                    linvel: dequantize_vec3(state.linvel, LINVEL_RANGE),
+                    angvel: dequantize_vec3(state.angvel, ANGVEL_RANGE),
+                });
+                continue;
+            }
+
+            // Remote cars are rendered in the past, interpolated between buffered snapshots.
+            let snapshot = TransformSnapshot {
+                time: snapshot_time,
+                translation,
+                rotation,
+            };
+            push_remote_snapshot(&mut cmd, &mut buffers, &interpolation_config, *entity, snapshot);
+
+            let wheels = RemoteWheels {
+                steering: dequantize_steering(state.steering),
+                spin: state.wheels_spin.map(dequantize_angle),
+            };
+            if let Ok(mut remote) = remote_wheels.get_mut(*entity) {
+                *remote = wheels;
+            } else {
+                cmd.entity(*entity).insert(wheels);
+                if let Ok(car_wheels) = car_wheels.get(*entity) {
+                    for wheel_entity in car_wheels.entities.iter() {
+                        cmd.entity(*wheel_entity).insert(RigidBody::KinematicPositionBased);
                     }
                 }
             }
@@ -56,97 +177,285 @@ pub fn client_sync_entities(
     }
 }
 
+/*Place the wheels of remote cars from their interpolated body and the replicated steering and spin.*/
+pub fn client_remote_wheels_system(
+    cars: Query<(&Transform, &CarSpec, &CarWheels, &RemoteWheels), Without<Wheel>>,
+    mut wheel_query: Query<&mut Transform, With<Wheel>>,
+) {
+    for (car_transform, spec, car_wheels, remote) in cars.iter() {
+        for (i, wheel_entity) in car_wheels.entities.iter().enumerate() {
+            if let Ok(mut wheel_transform) = wheel_query.get_mut(*wheel_entity) {
+                let (translation, rotation) =
+                    wheel_pose(car_transform, &spec.wheel_mount[i], remote.steering, remote.spin[i]);
+                wheel_transform.translation = translation;
+                wheel_transform.rotation = rotation;
+            }
+        }
+    }
+}
+
 #[allow(clippy::type_complexity)]
 pub fn server_network_sync(
     mut server: ResMut<RenetServer>,
-    mut tr_set: ParamSet<(
-        Query<(Entity, &Transform, &CarWheels), With<NetPlayer>>,
-        Query<&Transform, With<Wheel>>,
-    )>,
+    server_tick: Res<ServerTick>,
+    mut last_tick: Local<Option<u32>>,
+    mut sent: ResMut<SentSnapshots>,
+    mut priorities: ResMut<ReplicationPriorities>,
+    config: Res<ReplicationConfig>,
+    lobby: Res<ServerLobby>,
+    bounds: Res<QuantizationBounds>,
+    time: Res<Time>,
+    cars: Query<(Entity, &Transform, &Velocity, &CarWheels, Option<&LastInputTick>), With<NetPlayer>>,
+    wheel_query: Query<(&Transform, &Wheel)>,
 ) {
-    let mut networked_entities = NetworkedEntities::default();
-    let mut wheels_all: Vec<[Entity; 4]> = vec![];
-    
-    for (entity, transform, wheels) in tr_set.p0().iter() {
-        networked_entities.entities.push(entity);
-        networked_entities
-            .translations
-            .push(transform.translation.into());
-        networked_entities.rotations.push(transform.rotation.into());
-
-        wheels_all.push(wheels.entities);
-    }
-    for wheels in wheels_all {
-        networked_entities.wheels_translations.push([
-            tr_set.p1().get(wheels[0]).unwrap().translation.into(),
-            tr_set.p1().get(wheels[1]).unwrap().translation.into(),
-            tr_set.p1().get(wheels[2]).unwrap().translation.into(),
-            tr_set.p1().get(wheels[3]).unwrap().translation.into(),
-        ]);
-        networked_entities.wheels_rotations.push([
-            tr_set.p1().get(wheels[0]).unwrap().rotation.into(),
-            tr_set.p1().get(wheels[1]).unwrap().rotation.into(),
-            tr_set.p1().get(wheels[2]).unwrap().rotation.into(),
-            tr_set.p1().get(wheels[3]).unwrap().rotation.into(),
-        ]);
-    }
-
-    
-    let sync_message = bincode::serialize(&networked_entities).unwrap();
-    server.broadcast_message(ServerChannel::NetworkedEntities, sync_message);
+    if !priorities.snapshot_due(&config, time.delta_seconds_f64()) {
+        return;
+    }
+    // nothing was simulated since the last snapshot, and the tick keys the baselines of the clients
+    if *last_tick == Some(server_tick.0) {
+        return;
+    }
+    *last_tick = Some(server_tick.0);
+
+    let mut states: HashMap<Entity, CarNetState> = HashMap::new();
+    let mut positions: HashMap<Entity, Vec3> = HashMap::new();
+    for (entity, transform, velocity, wheels, input_tick) in cars.iter() {
+        let mut front_steering = Vec::new();
+        let mut wheels_spin = [0u16; 4];
+        for (i, wheel_entity) in wheels.entities.iter().enumerate() {
+            let Ok((wheel_transform, wheel)) = wheel_query.get(*wheel_entity) else {
+                continue;
+            };
+            let steering = if wheel.front {
+                let angle = wheel_steering_angle(transform.rotation, wheel_transform.rotation, wheel.left);
+                front_steering.push(angle);
+                angle
+            } else {
+                0.
+            };
+            let spin = wheel_spin_angle(transform.rotation, wheel_transform.rotation, steering, wheel.left);
+            wheels_spin[i] = quantize_angle(spin);
+        }
+        let steering = if front_steering.is_empty() {
+            0.
+        } else {
+            front_steering.iter().sum::<f32>() / front_steering.len() as f32
+        };
+
+        positions.insert(entity, transform.translation);
+        states.insert(entity, CarNetState {
+            translation: bounds.quantize_position(transform.translation),
+            rotation: compress_quat(transform.rotation),
+            linvel: quantize_vec3(velocity.linvel, LINVEL_RANGE),
+            angvel: quantize_vec3(velocity.angvel, ANGVEL_RANGE),
+            input_tick: input_tick.map_or(0, |tick| tick.0),
+            steering: quantize_steering(steering),
+            wheels_spin,
+        });
+    }
+
+    // clients still in the handshake get nothing until they joined
+    let client_ids: Vec<u64> = server.clients_id().into_iter().filter(|client_id| lobby.profiles.contains_key(client_id)).collect();
+    priorities.retain_clients(&client_ids);
+    sent.0.retain(|client_id, _| client_ids.contains(client_id));
+    for client_id in client_ids {
+        let history = sent.0.entry(client_id).or_default();
+        let baseline_tick = lobby.snapshot_acks.get(&client_id).copied();
+        let baseline = baseline_tick.and_then(|tick| history.get(tick));
+        let mut networked_entities = NetworkedEntities {
+            tick: server_tick.0,
+            time: time.elapsed_seconds_f64(),
+            baseline: baseline.and(baseline_tick),
+            ..Default::default()
+        };
+
+        // Distance from the client's own car, its own car is in every snapshot for the prediction.
+        let own_car = lobby.players.get(&client_id).copied();
+        let origin = own_car.and_then(|entity| positions.get(&entity)).copied();
+        let relevances = positions.iter().map(|(entity, position)| {
+            let relevance = if Some(*entity) == own_car {
+                f32::INFINITY
+            } else {
+                config.relevance(origin.map(|origin| origin.distance(*position)))
+            };
+            (*entity, relevance)
+        });
+        let due = priorities.due(client_id, relevances);
+
+        // The client keeps the baseline state of every car left out.
+        let mut known: HashMap<Entity, CarNetState> = baseline
+            .map(|baseline| baseline.iter().filter(|(entity, _)| states.contains_key(entity)).map(|(entity, state)| (*entity, *state)).collect())
+            .unwrap_or_default();
+        let mut budget = config.snapshot_budget;
+        for entity in due {
+            let state = &states[&entity];
+            let update = CarUpdate::encode(baseline.and_then(|baseline| baseline.get(&entity)), state);
+            let size = (snapshot_codec().serialized_size(&entity).unwrap() + snapshot_codec().serialized_size(&update).unwrap()) as usize;
+            if size > budget && !networked_entities.entities.is_empty() {
+                break;
+            }
+            budget = budget.saturating_sub(size);
+            networked_entities.entities.push(entity);
+            networked_entities.updates.push(update);
+            known.insert(entity, *state);
+            priorities.sent(client_id, entity);
+        }
+
+        let sync_message = snapshot_codec().serialize(&networked_entities).unwrap();
+        server.send_message(client_id, ServerChannel::NetworkedEntities, sync_message);
+        history.insert(server_tick.0, known);
+    }
+}
+
+const MAX_ADMIN_PASSWORD_BYTES: usize = 128;
+
+/*Commands a player sends on ClientChannel::Command.*/
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub enum PlayerCarCommand {
+    Ready(bool),
+    Chat(String),
+    /*An admin console line, checked against the admin password of the server.*/
+    Admin { password: String, line: String },
+}
+impl PlayerCommand for PlayerCarCommand {}
+
+/*A decoded PlayerCarCommand, raised on the server for the game systems.*/
+#[derive(Debug, Event)]
+pub struct PlayerCarCommandEvent {
+    pub client_id: u64,
+    pub command: PlayerCarCommand,
 }
 
 pub struct PlayerCarCommandProcessor;
 impl PlayerCommandProcessor for PlayerCarCommandProcessor
 {
-    fn process_command(_client_id : u64,_cmd: &mut Commands,_message : &Bytes,_lobby : &mut ResMut<ServerLobby>)
+    fn process_command(client_id : u64,cmd: &mut Commands,message : &Bytes,_lobby : &mut ResMut<ServerLobby>) -> Result<(),NetError>
     {
+        let command: PlayerCarCommand = decode(message)?;
+        match &command {
+            PlayerCarCommand::Chat(text) => validate_chat(text)?,
+            PlayerCarCommand::Admin { password, line } => {
+                validate_chat(line)?;
+                if password.len() > MAX_ADMIN_PASSWORD_BYTES {
+                    return Err(NetError::InvalidCommand("admin password too long"));
+                }
+            }
+            PlayerCarCommand::Ready(_) => {}
+        }
+        cmd.add(move |world: &mut World| {
+            world.send_event(PlayerCarCommandEvent { client_id, command });
+        });
+        Ok(())
+    }
+}
 
+/*Clamp what a client asks its car to do into what the car can do, non finite values are refused.*/
+fn validate_input(mut input: PlayerInputState) -> Result<PlayerInputState,NetError>
+{
+    let finite = input.throttle.is_finite()
+        && input.steering.is_finite()
+        && input.direction.iter().all(|v| v.is_finite());
+    if !finite {
+        return Err(NetError::InvalidCommand("non finite input"));
     }
+    input.throttle = input.throttle.clamp(-1., 1.);
+    input.steering = input.steering.clamp(-1., 1.);
+    input.direction = Vec3::from_array(input.direction).clamp_length_max(1.).to_array();
+    Ok(input)
 }
 
 pub struct PlayerCarInputProcessor;
 impl PlayerCommandProcessor for PlayerCarInputProcessor
 {
-    fn process_command(client_id : u64,cmd: &mut Commands,message : &Bytes,lobby : &mut ResMut<ServerLobby>)
+    fn process_command(client_id : u64,cmd: &mut Commands,message : &Bytes,lobby : &mut ResMut<ServerLobby>) -> Result<(),NetError>
     {
-        let input: PlayerInputState = bincode::deserialize(&message).unwrap();
-        if let Some(player_entity) = lobby.players.get(&client_id) {
-            cmd.entity(*player_entity).insert(input);
+        let ticked_input: TickedInputState = decode(message)?;
+        let input = validate_input(ticked_input.input)?;
+        lobby.snapshot_acks.insert(client_id, ticked_input.snapshot_ack);
+        if let Some(player_entity) = lobby.players.get(&client_id).copied() {
+            // server_apply_inputs_system simulates it on its own tick
+            cmd.add(move |world: &mut World| {
+                if let Some(mut queue) = world.get_mut::<InputQueue>(player_entity) {
+                    queue.0.push_back(TickedInputState { input, ..ticked_input });
+                }
+            });
         }
+        Ok(())
+    }
+}
+
+/*Server side car of a player, scored by the race systems, local_player when the host drives it.*/
+fn spawn_authority_car(cmd: &mut Commands,game_asset : Option<&GameAssets>,profile : &PlayerProfile,local_player : bool) -> (bool,Entity,Transform)
+{
+    let Some(archetype) = car_archetype(profile.archetype) else {
+        return (false,Entity::PLACEHOLDER,Transform::IDENTITY);
+    };
+    let transform = Transform::from_xyz(
+        (fastrand::f32() - 0.5) * 40.,
+        0.51,
+        (fastrand::f32() - 0.5) * 40.,
+    );
+    let scenes = archetype.scenes(game_asset);
+    let player_entity = spawn_car_with_spec(
+        cmd,
+        scenes.as_ref().map(|(body, wheel)| (body, wheel)),
+        local_player,
+        transform,
+        (archetype.spec)(),
+    );
+    cmd.entity(player_entity).insert((CarTrack::default(), RaceProgress::default()));
+    (true,player_entity,transform)
+}
+
+/*The host player's commands skip the network and go straight to the game systems.*/
+pub fn host_player_commands_system(
+    mut commands: EventReader<PlayerCarCommand>,
+    mut command_events: EventWriter<PlayerCarCommandEvent>,
+) {
+    for command in commands.iter() {
+        command_events.send(PlayerCarCommandEvent { client_id: HOST_CLIENT_ID, command: command.clone() });
     }
 }
 
 pub struct PlayerCarSpawner;
 impl PlayerSpawner for PlayerCarSpawner
 {
-    fn spawn_authority(cmd: &mut Commands,game_asset : &Res<GameAssets>,_archetype : u128,_players : &Query<(Entity, &NetPlayer, &Transform)>) -> (bool,Entity,Transform)
+    fn check_profile(profile : &PlayerProfile) -> Result<(), String>
     {
-        let transform = Transform::from_xyz(
-            (fastrand::f32() - 0.5) * 40.,
-            0.51,
-            (fastrand::f32() - 0.5) * 40.,
-        );
-        let player_entity = spawn_car(
-            cmd,
-            #[cfg(feature = "graphics")]
-            &game_asset.car_body,
-            #[cfg(feature = "graphics")]
-            &game_asset.wheel_body,
-            false,
-            transform,
-        );
-        (true,player_entity,transform)
+        match car_archetype(profile.archetype) {
+            Some(_) => Ok(()),
+            None => Err(format!("unknown car archetype {}", profile.archetype)),
+        }
+    }
+    fn spawn_authority(cmd: &mut Commands,game_asset : Option<&GameAssets>,profile : &PlayerProfile,_players : &Query<(Entity, &NetPlayer, &Transform)>) -> (bool,Entity,Transform)
+    {
+        spawn_authority_car(cmd, game_asset, profile, false)
     }
-    fn spawn_proxy(cmd: &mut Commands,game_asset : &Res<GameAssets>,_archetype : u128,transform : Transform,local_player : bool) -> (bool,Entity)
+    fn spawn_local(cmd: &mut Commands,game_asset : &GameAssets,profile : &PlayerProfile,_players : &Query<(Entity, &NetPlayer, &Transform)>) -> (bool,Entity,Transform)
     {
-        let client_entity = car::spawn_car(
+        spawn_authority_car(cmd, Some(game_asset), profile, true)
+    }
+    fn spawn_proxy(cmd: &mut Commands,game_asset : Option<&GameAssets>,profile : &PlayerProfile,transform : Transform,local_player : bool) -> (bool,Entity)
+    {
+        let Some(archetype) = car_archetype(profile.archetype) else {
+            return (false,Entity::PLACEHOLDER);
+        };
+        let scenes = archetype.scenes(game_asset);
+        let client_entity = car::spawn_car_with_spec(
             cmd,
-            &game_asset.car_body,
-            &game_asset.wheel_body,
+            scenes.as_ref().map(|(body, wheel)| (body, wheel)),
             local_player,
             transform,
+            (archetype.spec)(),
         );
+        #[cfg(feature = "graphics")]
+        if let (Some(game_asset), false) = (game_asset, local_player) {
+            spawn_nameplate(cmd, game_asset, client_entity, profile);
+        }
+        // Our own car drives on the ticked input, like its authority on the server.
+        if local_player {
+            cmd.entity(client_entity).insert(PlayerInputState::default());
+        }
         (true,client_entity)
     }
 }
\ No newline at end of file
@@ -0,0 +1,256 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use std::collections::VecDeque;
+use crate::car::{Car, CarWheels, Wheel};
+use crate::input::controller::PlayerInputState;
+use crate::physics::PhysicsStep;
+use crate::renet::client::{ClientInputTick, ControlledPlayer};
+use crate::renet::tick_after;
+
+/*Pose and velocity of one rigid body.*/
+#[derive(Debug, Clone, Copy)]
+pub struct BodyState {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub linvel: Vec3,
+    pub angvel: Vec3,
+}
+
+impl BodyState {
+    fn read(transform: &Transform, velocity: &Velocity) -> Self {
+        Self {
+            translation: transform.translation,
+            rotation: transform.rotation,
+            linvel: velocity.linvel,
+            angvel: velocity.angvel,
+        }
+    }
+
+    fn write_transform(&self, transform: &mut Transform) {
+        transform.translation = self.translation;
+        transform.rotation = self.rotation;
+    }
+
+    fn write_velocity(&self, velocity: &mut Velocity) {
+        velocity.linvel = self.linvel;
+        velocity.angvel = self.angvel;
+    }
+}
+
+/*The locally predicted car right after the input of `tick` was simulated, with that input to replay it.*/
+#[derive(Debug, Clone, Copy)]
+pub struct PredictedState {
+    pub tick: u32,
+    pub input: PlayerInputState,
+    pub body: BodyState,
+    pub wheels: [BodyState; 4],
+    /*Steering and torque the car eases from, they are not in the snapshots.*/
+    pub car: Car,
+}
+
+#[derive(Resource)]
+pub struct PredictionConfig {
+    /*How many ticks of history are kept while waiting for the server to acknowledge them.*/
+    pub history_len: usize,
+    /*Position error (meters) below which the prediction is trusted as is.*/
+    pub position_tolerance: f32,
+    /*Rotation error (radians) below which the prediction is trusted as is.*/
+    pub rotation_tolerance: f32,
+}
+
+impl Default for PredictionConfig {
+    fn default() -> Self {
+        Self {
+            history_len: 256,
+            position_tolerance: 0.25,
+            rotation_tolerance: 0.05,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PredictionHistory {
+    pub states: VecDeque<PredictedState>,
+    /*Newest server state of our car, reconciled once the snapshots of the frame are read.*/
+    pub correction: Option<AuthoritativeState>,
+}
+
+impl PredictionHistory {
+    /*Keep the newest server state, an older snapshot arriving late is ignored.*/
+    pub fn correct(&mut self, server_state: AuthoritativeState) {
+        if self.correction.as_ref().map_or(true, |pending| !tick_after(pending.acked_tick, server_state.acked_tick)) {
+            self.correction = Some(server_state);
+        }
+    }
+
+    /*Drop everything older than the acknowledged tick and return the entry predicted for it.*/
+    fn acknowledge(&mut self, tick: u32) -> Option<PredictedState> {
+        while let Some(front) = self.states.front() {
+            if tick_after(tick, front.tick) {
+                self.states.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.states.front().filter(|state| state.tick == tick).copied()
+    }
+}
+
+/*Authoritative state of the local car as received from the server.*/
+#[derive(Debug, Clone, Copy)]
+pub struct AuthoritativeState {
+    pub acked_tick: u32,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub linvel: Vec3,
+    pub angvel: Vec3,
+}
+
+/*The controlled car drives on the input sent for this tick, the very input the server applies for it.*/
+pub fn client_apply_input(
+    input: Res<PlayerInputState>,
+    mut cars: Query<&mut PlayerInputState, (With<Car>, With<ControlledPlayer>)>,
+) {
+    for mut car_input in cars.iter_mut() {
+        *car_input = *input;
+    }
+}
+
+/*Record the predicted car state once physics has simulated the input of the current tick.*/
+pub fn client_record_prediction(
+    input_tick: Res<ClientInputTick>,
+    config: Res<PredictionConfig>,
+    mut history: ResMut<PredictionHistory>,
+    cars: Query<(&Transform, &Velocity, &Car, &CarWheels, &PlayerInputState), With<ControlledPlayer>>,
+    wheel_query: Query<(&Transform, &Velocity), (With<Wheel>, Without<Car>)>,
+) {
+    let Ok((transform, velocity, car, car_wheels, input)) = cars.get_single() else {
+        return;
+    };
+    if history.states.back().map_or(false, |last| last.tick == input_tick.0) {
+        return;
+    }
+    let Some(wheels) = read_wheels(car_wheels, |entity| wheel_query.get(entity).ok().map(|(t, v)| BodyState::read(t, v))) else {
+        return;
+    };
+    history.states.push_back(PredictedState {
+        tick: input_tick.0,
+        input: *input,
+        body: BodyState::read(transform, velocity),
+        wheels,
+        car: *car,
+    });
+    while history.states.len() > config.history_len {
+        history.states.pop_front();
+    }
+}
+
+fn read_wheels(car_wheels: &CarWheels, mut read: impl FnMut(Entity) -> Option<BodyState>) -> Option<[BodyState; 4]> {
+    let [a, b, c, d] = car_wheels.entities;
+    Some([read(a)?, read(b)?, read(c)?, read(d)?])
+}
+
+/*
+Rewind and replay : compare the server state with what we predicted for the acknowledged tick.
+When they disagree, the car and its wheels are put back where the server says they were at that
+tick and the input of every later, not yet acknowledged, tick is simulated again on top of it.
+*/
+pub fn client_reconcile_system(world: &mut World) {
+    let Some(server_state) = world.resource_mut::<PredictionHistory>().correction.take() else {
+        return;
+    };
+    let mut cars = world.query_filtered::<(Entity, &CarWheels), (With<Car>, With<ControlledPlayer>)>();
+    let Some((car_entity, wheel_entities)) = cars.iter(world).next().map(|(entity, wheels)| (entity, wheels.entities)) else {
+        return;
+    };
+    let (position_tolerance, rotation_tolerance) = {
+        let config = world.resource::<PredictionConfig>();
+        (config.position_tolerance, config.rotation_tolerance)
+    };
+    let server_body = BodyState {
+        translation: server_state.translation,
+        rotation: server_state.rotation,
+        linvel: server_state.linvel,
+        angvel: server_state.angvel,
+    };
+
+    let mut history = world.resource_mut::<PredictionHistory>();
+    if history.states.front().map_or(false, |front| tick_after(front.tick, server_state.acked_tick)) {
+        // Older than what we already reconciled against.
+        return;
+    }
+    let within_tolerance = |predicted: &BodyState| {
+        (server_body.translation - predicted.translation).length() < position_tolerance
+            && (server_body.rotation * predicted.rotation.inverse()).angle_between(Quat::IDENTITY) < rotation_tolerance
+    };
+    let Some(predicted) = history.acknowledge(server_state.acked_tick) else {
+        // Nothing recorded for that tick (history overflow or first snapshot), trust the server.
+        history.states.clear();
+        if let Some((body, wheels, car)) = read_car(world, car_entity, &wheel_entities) {
+            if !within_tolerance(&body) {
+                let wheels = carry_wheels(&body, &server_body, wheels);
+                write_car(world, car_entity, &wheel_entities, &server_body, &wheels, car);
+            }
+        }
+        return;
+    };
+    if within_tolerance(&predicted.body) {
+        return;
+    }
+
+    // Back to the acknowledged tick, as the server simulated it.
+    let replay: Vec<(u32, PlayerInputState)> = history.states.iter().skip(1).map(|state| (state.tick, state.input)).collect();
+    let wheels = carry_wheels(&predicted.body, &server_body, predicted.wheels);
+    history.states.clear();
+    history.states.push_back(PredictedState { body: server_body, wheels, ..predicted });
+    write_car(world, car_entity, &wheel_entities, &server_body, &wheels, predicted.car);
+
+    for (tick, input) in replay {
+        if let Some(mut car_input) = world.get_mut::<PlayerInputState>(car_entity) {
+            *car_input = input;
+        }
+        world.run_schedule(PhysicsStep);
+        let Some((body, wheels, car)) = read_car(world, car_entity, &wheel_entities) else {
+            return;
+        };
+        world.resource_mut::<PredictionHistory>().states.push_back(PredictedState { tick, input, body, wheels, car });
+    }
+}
+
+/*Wheels are jointed to the body, move them along with it so the joints are not torn apart.*/
+fn carry_wheels(from: &BodyState, to: &BodyState, wheels: [BodyState; 4]) -> [BodyState; 4] {
+    let rotation = to.rotation * from.rotation.inverse();
+    wheels.map(|wheel| BodyState {
+        translation: to.translation + rotation.mul_vec3(wheel.translation - from.translation),
+        rotation: (rotation * wheel.rotation).normalize(),
+        linvel: to.linvel + rotation.mul_vec3(wheel.linvel - from.linvel),
+        angvel: to.angvel + rotation.mul_vec3(wheel.angvel - from.angvel),
+    })
+}
+
+fn read_car(world: &World, car_entity: Entity, wheel_entities: &[Entity; 4]) -> Option<(BodyState, [BodyState; 4], Car)> {
+    let read = |entity: Entity| Some(BodyState::read(world.get::<Transform>(entity)?, world.get::<Velocity>(entity)?));
+    let body = read(car_entity)?;
+    let wheels = read_wheels(&CarWheels::new(*wheel_entities), read)?;
+    Some((body, wheels, *world.get::<Car>(car_entity)?))
+}
+
+fn write_car(world: &mut World, car_entity: Entity, wheel_entities: &[Entity; 4], body: &BodyState, wheels: &[BodyState; 4], car: Car) {
+    let mut write = |entity: Entity, state: &BodyState| {
+        if let Some(mut entity) = world.get_entity_mut(entity) {
+            if let Some(mut transform) = entity.get_mut::<Transform>() {
+                state.write_transform(&mut transform);
+            }
+            if let Some(mut velocity) = entity.get_mut::<Velocity>() {
+                state.write_velocity(&mut velocity);
+            }
+        }
+    };
+    write(car_entity, body);
+    for (entity, state) in wheel_entities.iter().zip(wheels.iter()) {
+        write(*entity, state);
+    }
+    if let Some(mut current) = world.get_mut::<Car>(car_entity) {
+        *current = car;
+    }
+}
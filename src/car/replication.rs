@@ -0,0 +1,243 @@
+use bevy::prelude::*;
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::PI;
+use crate::car::WheelMount;
+use crate::renet::quantize::*;
+use crate::track::TRACK_POSITIONS;
+
+/*Velocities are quantized within these ranges (m/s and rad/s).*/
+pub const LINVEL_RANGE: f32 = 150.;
+pub const ANGVEL_RANGE: f32 = 50.;
+
+/*How many sent (server) or received (client) snapshots are kept to resolve delta baselines.*/
+pub const SNAPSHOT_HISTORY_LEN: usize = 128;
+
+/*Varint encoding, so the small numbers of a delta only take a byte or two on the wire.*/
+pub fn snapshot_codec() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+/*Positions are quantized over the track extent, with room for cars leaving the asphalt.*/
+pub fn track_quantization_bounds() -> QuantizationBounds {
+    QuantizationBounds::from_points(
+        TRACK_POSITIONS.iter().map(|p| Vec3::new(p.0, p.1, p.2)),
+        Vec3::new(100., 50., 100.),
+    )
+}
+
+/*Quantized state of one car, see renet::quantize for the encodings.*/
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+pub struct CarNetState {
+    pub translation: [u16; 3],
+    pub rotation: u32,
+    pub linvel: [i16; 3],
+    pub angvel: [i16; 3],
+    pub input_tick: u32,
+    pub steering: i16,
+    pub wheels_spin: [u16; 4],
+}
+
+/*Field wise difference with a baseline state, unchanged fields encode to a single zero byte.*/
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct CarNetDelta {
+    pub translation: [i32; 3],
+    /*The packed rotation does not diff into a small number, it is sent as is when it changed.*/
+    pub rotation: Option<u32>,
+    pub linvel: [i32; 3],
+    pub angvel: [i32; 3],
+    pub input_tick: u32,
+    pub steering: i32,
+    pub wheels_spin: [i16; 4],
+}
+
+impl CarNetDelta {
+    pub fn between(baseline: &CarNetState, state: &CarNetState) -> Self {
+        let mut delta = CarNetDelta {
+            rotation: (state.rotation != baseline.rotation).then_some(state.rotation),
+            input_tick: state.input_tick.wrapping_sub(baseline.input_tick),
+            steering: state.steering as i32 - baseline.steering as i32,
+            ..default()
+        };
+        for i in 0..3 {
+            delta.translation[i] = state.translation[i] as i32 - baseline.translation[i] as i32;
+            delta.linvel[i] = state.linvel[i] as i32 - baseline.linvel[i] as i32;
+            delta.angvel[i] = state.angvel[i] as i32 - baseline.angvel[i] as i32;
+        }
+        for i in 0..4 {
+            delta.wheels_spin[i] = state.wheels_spin[i].wrapping_sub(baseline.wheels_spin[i]) as i16;
+        }
+        delta
+    }
+
+    pub fn apply(&self, baseline: &CarNetState) -> CarNetState {
+        let mut state = CarNetState {
+            rotation: self.rotation.unwrap_or(baseline.rotation),
+            input_tick: baseline.input_tick.wrapping_add(self.input_tick),
+            steering: (baseline.steering as i32 + self.steering) as i16,
+            ..default()
+        };
+        for i in 0..3 {
+            state.translation[i] = (baseline.translation[i] as i32 + self.translation[i]) as u16;
+            state.linvel[i] = (baseline.linvel[i] as i32 + self.linvel[i]) as i16;
+            state.angvel[i] = (baseline.angvel[i] as i32 + self.angvel[i]) as i16;
+        }
+        for i in 0..4 {
+            state.wheels_spin[i] = baseline.wheels_spin[i].wrapping_add(self.wheels_spin[i] as u16);
+        }
+        state
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CarUpdate {
+    Full(CarNetState),
+    Delta(CarNetDelta),
+    Unchanged,
+}
+
+impl CarUpdate {
+    pub fn encode(baseline: Option<&CarNetState>, state: &CarNetState) -> Self {
+        match baseline {
+            Some(baseline) if baseline == state => CarUpdate::Unchanged,
+            Some(baseline) => CarUpdate::Delta(CarNetDelta::between(baseline, state)),
+            None => CarUpdate::Full(*state),
+        }
+    }
+
+    pub fn decode(&self, baseline: Option<&CarNetState>) -> Option<CarNetState> {
+        match self {
+            CarUpdate::Full(state) => Some(*state),
+            CarUpdate::Delta(delta) => baseline.map(|baseline| delta.apply(baseline)),
+            CarUpdate::Unchanged => baseline.copied(),
+        }
+    }
+}
+
+/*Every car state known at a snapshot tick, keyed by server entity. Cars left out of a snapshot keep the state of its baseline.*/
+#[derive(Resource, Default)]
+pub struct SnapshotHistory {
+    snapshots: VecDeque<(u32, HashMap<Entity, CarNetState>)>,
+}
+
+impl SnapshotHistory {
+    pub fn insert(&mut self, tick: u32, states: HashMap<Entity, CarNetState>) {
+        self.snapshots.push_back((tick, states));
+        while self.snapshots.len() > SNAPSHOT_HISTORY_LEN {
+            self.snapshots.pop_front();
+        }
+    }
+
+    pub fn get(&self, tick: u32) -> Option<&HashMap<Entity, CarNetState>> {
+        self.snapshots
+            .iter()
+            .find(|(snapshot_tick, _)| *snapshot_tick == tick)
+            .map(|(_, states)| states)
+    }
+}
+
+/*What the server sent every client, snapshots carry only some cars so every client has its own baselines.*/
+#[derive(Resource, Default)]
+pub struct SentSnapshots(pub HashMap<u64, SnapshotHistory>);
+
+fn wheel_axle(left: bool) -> Vec3 {
+    if left {
+        -Vec3::Y
+    } else {
+        Vec3::Y
+    }
+}
+
+/*Rotation lining the wheel axle up with the car X axis, the joint keeps them aligned.*/
+fn wheel_rest_rotation(left: bool) -> Quat {
+    Quat::from_rotation_arc(wheel_axle(left), Vec3::X)
+}
+
+/*Angle the wheel axle is turned around the car Y axis.*/
+pub fn wheel_steering_angle(car_rotation: Quat, wheel_rotation: Quat, left: bool) -> f32 {
+    let axle = car_rotation.inverse() * (wheel_rotation * wheel_axle(left));
+    (-axle.z).atan2(axle.x)
+}
+
+/*Angle the wheel has rolled around its axle, once steering is taken out.*/
+pub fn wheel_spin_angle(car_rotation: Quat, wheel_rotation: Quat, steering: f32, left: bool) -> f32 {
+    let local = Quat::from_rotation_y(steering).inverse() * car_rotation.inverse() * wheel_rotation;
+    let twist = local * wheel_rest_rotation(left).inverse();
+    2. * twist.x.atan2(twist.w)
+}
+
+/*World pose of a wheel rebuilt from the car transform, steering and spin.*/
+pub fn wheel_pose(car: &Transform, mount: &WheelMount, steering: f32, spin: f32) -> (Vec3, Quat) {
+    let steering = if mount.front { steering } else { 0. };
+    let translation = car.translation + car.rotation.mul_vec3(mount.anchor);
+    let rotation = car.rotation
+        * Quat::from_rotation_y(steering)
+        * Quat::from_rotation_x(spin)
+        * wheel_rest_rotation(mount.left);
+    (translation, rotation.normalize())
+}
+
+pub fn quantize_steering(steering: f32) -> i16 {
+    quantize_f32(steering, PI)
+}
+
+pub fn dequantize_steering(quantized: i16) -> f32 {
+    dequantize_f32(quantized, PI)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> CarNetState {
+        CarNetState {
+            translation: [100, 30000, 65535],
+            rotation: compress_quat(Quat::from_rotation_y(0.3)),
+            linvel: [-200, 0, 32767],
+            angvel: [5, -32768, 12],
+            input_tick: u32::MAX - 1,
+            steering: -1200,
+            wheels_spin: [0, 65535, 100, 40000],
+        }
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let baseline = state();
+        let state = CarNetState {
+            translation: [0, 30010, 65530],
+            rotation: compress_quat(Quat::from_rotation_y(0.35)),
+            linvel: [32767, -5, -32768],
+            angvel: [5, 32767, -12],
+            // wraps past u32::MAX
+            input_tick: 3,
+            steering: 1200,
+            wheels_spin: [65535, 2, 100, 10],
+        };
+        let delta = CarNetDelta::between(&baseline, &state);
+        assert_eq!(delta.apply(&baseline), state);
+    }
+
+    #[test]
+    fn unchanged_rotation_is_not_sent() {
+        let baseline = state();
+        let state = CarNetState { steering: 0, ..baseline };
+        let delta = CarNetDelta::between(&baseline, &state);
+        assert_eq!(delta.rotation, None);
+        assert_eq!(delta.apply(&baseline), state);
+    }
+
+    #[test]
+    fn update_encodes_against_the_baseline() {
+        let baseline = state();
+        let moved = CarNetState { translation: [101, 30000, 65535], ..baseline };
+        assert!(matches!(CarUpdate::encode(Some(&baseline), &baseline), CarUpdate::Unchanged));
+        assert!(matches!(CarUpdate::encode(None, &baseline), CarUpdate::Full(_)));
+        let update = CarUpdate::encode(Some(&baseline), &moved);
+        assert!(matches!(update, CarUpdate::Delta(_)));
+        assert_eq!(update.decode(Some(&baseline)), Some(moved));
+        // a delta without its baseline can not be decoded
+        assert_eq!(update.decode(None), None);
+    }
+}
@@ -1,5 +1,7 @@
 use bevy::prelude::*;
 use crate::{track::{spawn_car_on_track, SpawnCarOnTrackEvent, TrackConfig}, game_asset::{self, GameAssets}};
+use crate::car::{car_archetype, CAR_ARCHETYPES};
+use crate::config::Config;
 
 pub fn spawn_car_start_system(mut car_spawn_events: EventWriter<SpawnCarOnTrackEvent>) {
     car_spawn_events.send(SpawnCarOnTrackEvent {
@@ -14,7 +16,9 @@ pub fn spawn_car_system(
     mut cmd: Commands,
     track_config: ResMut<TrackConfig>,
     game_asset: ResMut<GameAssets>,
+    config: Res<Config>,
 ) {
+    let archetype = car_archetype(config.archetype).unwrap_or(&CAR_ARCHETYPES[0]);
     for spawn_event in events.iter() {
         dbg!(spawn_event);
 
@@ -28,8 +32,8 @@ pub fn spawn_car_system(
 
         spawn_car_on_track(
             &mut cmd,
-            &game_asset.car_body,
-            &game_asset.wheel_body,
+            &game_asset,
+            archetype,
             spawn_event.player,
             transform,
             spawn_event.index,
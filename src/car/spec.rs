@@ -8,7 +8,7 @@ pub struct CarSize {
     pub hl: f32,
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone)]
 pub struct CarSpec {
     pub size: CarSize,
     pub wheel_radius: f32,
@@ -69,3 +69,16 @@ pub struct WheelMount {
     pub front: bool,
     pub left: bool,
 }
+
+impl CarSpec {
+    /*Softer steering and more torque, for a lower top speed.*/
+    pub fn touring() -> Self {
+        Self {
+            max_speed: 240. * 1000. / 3600.,
+            max_steering_speed: 200. * 1000. / 3600.,
+            wheel_max_torque: 1600.,
+            wheel_max_angle: FRAC_PI_4 * 0.8,
+            ..Self::default()
+        }
+    }
+}
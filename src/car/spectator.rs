@@ -0,0 +1,47 @@
+use bevy::prelude::*;
+
+use crate::camera::SpectatorTarget;
+use crate::renet::client::ClientLobby;
+
+/*Tab follows the next car, shift+Tab the previous one, a car that leaves hands over to the next.*/
+pub fn spectator_camera_system(
+    mut cmd: Commands,
+    input: Res<Input<KeyCode>>,
+    lobby: Res<ClientLobby>,
+    targets: Query<Entity, With<SpectatorTarget>>,
+) {
+    let mut cars: Vec<(u64, Entity)> = lobby
+        .players
+        .iter()
+        .filter(|(_, player_info)| !player_info.stale)
+        .map(|(id, player_info)| (*id, player_info.client_entity))
+        .collect();
+    cars.sort_by_key(|(id, _)| *id);
+
+    let current = targets.iter().next();
+    let current_index = current.and_then(|target| cars.iter().position(|(_, entity)| *entity == target));
+    let step: isize = if input.just_pressed(KeyCode::Tab) {
+        if input.pressed(KeyCode::ShiftLeft) || input.pressed(KeyCode::ShiftRight) { -1 } else { 1 }
+    } else {
+        0
+    };
+    if step == 0 && current_index.is_some() {
+        return;
+    }
+
+    let next = match (current_index, cars.is_empty()) {
+        (_, true) => None,
+        (Some(index), false) => Some(cars[(index as isize + step).rem_euclid(cars.len() as isize) as usize].1),
+        (None, false) => Some(cars[0].1),
+    };
+    for target in targets.iter() {
+        if Some(target) != next {
+            cmd.entity(target).remove::<SpectatorTarget>();
+        }
+    }
+    if let Some(next) = next {
+        if Some(next) != current {
+            cmd.entity(next).insert(SpectatorTarget);
+        }
+    }
+}
@@ -38,7 +38,7 @@ impl Wheel {
 
 pub fn spawn_wheel(
     cmd: &mut Commands,
-    #[cfg(feature = "graphics")] wheel_gl: &Handle<Scene>,
+    wheel_gl: Option<&Handle<Scene>>,
     spec: &WheelSpec,
     mount: &WheelMount,
     car_transform: Transform,
@@ -58,18 +58,11 @@ pub fn spawn_wheel(
         wheel.border_radius,
     );
 
-    cmd.spawn((
+    let wheel_id = cmd.spawn((
         Name::new("wheel"),
         wheel,
         joint,
-        #[cfg(feature = "graphics")]
-        SceneBundle {
-            scene: wheel_gl.clone(),
-            transform,
-            ..default()
-        },
-        #[cfg(not(feature = "graphics"))]
-        transform,
+        TransformBundle::from_transform(transform),
         (
             collider,
             ColliderMassProperties::MassProperties(MassProperties {
@@ -100,5 +93,13 @@ pub fn spawn_wheel(
             Velocity::zero(),
         ),
     ))
-    .id()
+    .id();
+    if let Some(scene) = wheel_gl {
+        cmd.entity(wheel_id).insert(SceneBundle {
+            scene: scene.clone(),
+            transform,
+            ..default()
+        });
+    }
+    wheel_id
 }
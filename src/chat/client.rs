@@ -0,0 +1,144 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+use crate::chat::ChatMessage;
+#[cfg(feature = "graphics")]
+use crate::{admin::AdminConfig, car::PlayerCarCommand, chat::MAX_CHAT_CHARS, game_asset::GameAssets};
+#[cfg(feature = "graphics")]
+use crate::renet::handshake::{Features, ServerHandshake};
+
+/*Lines kept in the log, older ones are dropped.*/
+const CHAT_LOG_LINES: usize = 8;
+/*Seconds a line stays on screen while the input box is closed.*/
+#[cfg(feature = "graphics")]
+const CHAT_LINE_SECONDS: f64 = 15.;
+
+#[derive(Debug, Default, Resource)]
+pub struct ChatLog {
+    /*App time the line arrived at and the line.*/
+    pub lines: VecDeque<(f64, ChatMessage)>,
+}
+
+#[cfg(feature = "graphics")]
+#[derive(Debug, Default, Resource)]
+pub struct ChatInput {
+    pub active: bool,
+    pub text: String,
+}
+
+#[cfg(feature = "graphics")]
+#[derive(Component)]
+pub struct ChatText;
+
+/*
+Enter opens the input box, Enter again sends and Escape cancels. While the box is open the
+keyboard belongs to the chat, so this runs right after Bevy updated the keys and clears them.
+A line starting with a slash goes to the server as an admin command, the others only go to a
+server that agreed on Features::CHAT.
+*/
+#[cfg(feature = "graphics")]
+pub fn chat_input_system(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut input: ResMut<ChatInput>,
+    admin: Res<AdminConfig>,
+    handshake: Option<Res<ServerHandshake>>,
+    mut commands: EventWriter<PlayerCarCommand>,
+) {
+    if !input.active {
+        characters.clear();
+        if keys.just_pressed(KeyCode::Return) {
+            input.active = true;
+            input.text.clear();
+            keys.reset_all();
+        }
+        return;
+    }
+
+    for character in characters.iter() {
+        if !character.char.is_control() && input.text.chars().count() < MAX_CHAT_CHARS {
+            input.text.push(character.char);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        input.text.pop();
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        let text = input.text.trim().to_string();
+        if let Some(line) = text.strip_prefix('/') {
+            commands.send(PlayerCarCommand::Admin {
+                password: admin.password.clone().unwrap_or_default(),
+                line: line.to_string(),
+            });
+        } else if !text.is_empty() {
+            // the host is the server, it has no handshake
+            if handshake.map_or(true, |handshake| handshake.features().contains(Features::CHAT)) {
+                commands.send(PlayerCarCommand::Chat(text));
+            } else {
+                warn!("The server does not relay chat");
+            }
+        }
+        input.active = false;
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        input.active = false;
+    }
+    keys.reset_all();
+}
+
+pub fn chat_log_system(time: Res<Time>, mut log: ResMut<ChatLog>, mut chat_messages: EventReader<ChatMessage>) {
+    for message in chat_messages.iter() {
+        log.lines.push_back((time.elapsed_seconds_f64(), message.clone()));
+        while log.lines.len() > CHAT_LOG_LINES {
+            log.lines.pop_front();
+        }
+    }
+}
+
+/*Recent lines in the bottom left corner, all of them plus the input line while typing.*/
+#[cfg(feature = "graphics")]
+pub fn chat_ui_system(
+    mut cmd: Commands,
+    time: Res<Time>,
+    log: Res<ChatLog>,
+    input: Res<ChatInput>,
+    game_asset: Res<GameAssets>,
+    mut texts: Query<&mut Text, With<ChatText>>,
+) {
+    let now = time.elapsed_seconds_f64();
+    let mut message: String = log
+        .lines
+        .iter()
+        .filter(|(received, _)| input.active || now - received < CHAT_LINE_SECONDS)
+        .map(|(_, line)| format!("{}: {}\n", line.name, line.text))
+        .collect();
+    if input.active {
+        message += &format!("> {}_", input.text);
+    }
+
+    if let Ok(mut text) = texts.get_single_mut() {
+        if text.sections[0].value != message {
+            text.sections[0].value = message;
+        }
+        return;
+    }
+    cmd.spawn(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.),
+            bottom: Val::Px(60.),
+            max_width: Val::Percent(50.),
+            ..default()
+        },
+        text: Text::from_section(
+            message,
+            TextStyle {
+                font: game_asset.dash_font.clone(),
+                font_size: 20.0,
+                color: Color::WHITE,
+            },
+        ),
+        ..default()
+    })
+    .insert(ChatText);
+}
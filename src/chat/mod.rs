@@ -0,0 +1,33 @@
+mod server;
+mod client;
+
+pub use server::*;
+pub use client::*;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::renet::error::NetError;
+
+pub const MAX_CHAT_CHARS: usize = 200;
+/*Sender id of the lines the server itself writes, no client gets it.*/
+pub const SERVER_CHAT_ID: u64 = u64::MAX;
+
+/*A chat line relayed by the server with the name of whoever wrote it.*/
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub struct ChatMessage {
+    pub id: u64,
+    pub name: String,
+    pub text: String,
+}
+
+/*What a client may send, the client side input box keeps within it so only forged messages fail.*/
+pub fn validate_chat(text: &str) -> Result<(), NetError> {
+    if text.chars().count() > MAX_CHAT_CHARS {
+        return Err(NetError::InvalidCommand("chat message too long"));
+    }
+    if text.chars().any(|c| c.is_control()) {
+        return Err(NetError::InvalidCommand("chat message has control characters"));
+    }
+    Ok(())
+}
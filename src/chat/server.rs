@@ -0,0 +1,60 @@
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+
+use crate::car::{PlayerCarCommand, PlayerCarCommandEvent};
+use crate::chat::ChatMessage;
+use crate::renet::error::encode;
+use crate::renet::handshake::Features;
+use crate::renet::server::{ServerChannel, ServerLobby, ServerMessages, HOST_CLIENT_ID};
+
+/*Chat is only taken from and sent to clients that negotiated Features::CHAT.*/
+pub fn speaks_chat(lobby: &ServerLobby, client_id: u64) -> bool {
+    lobby.features.get(&client_id).map_or(false, |features| features.contains(Features::CHAT))
+}
+
+/*To every client that speaks chat, the others could not read it.*/
+pub fn broadcast_chat(server: &mut RenetServer, lobby: &ServerLobby, message: &ChatMessage) {
+    let bytes = match encode(&ServerMessages::Chat(message.clone())) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    for client_id in server.clients_id() {
+        if speaks_chat(lobby, client_id) {
+            server.send_message(client_id, ServerChannel::ServerMessages, bytes.clone());
+        }
+    }
+}
+
+/*Relay chat to everybody who speaks it, the sender included, and raise it locally for the host and the log.*/
+pub fn server_chat_system(
+    lobby: Res<ServerLobby>,
+    mut server: ResMut<RenetServer>,
+    mut command_events: EventReader<PlayerCarCommandEvent>,
+    mut chat_messages: EventWriter<ChatMessage>,
+) {
+    for event in command_events.iter() {
+        let PlayerCarCommand::Chat(text) = &event.command else {
+            continue;
+        };
+        // the host plays on the server itself, it negotiates nothing
+        if event.client_id != HOST_CLIENT_ID && !speaks_chat(&lobby, event.client_id) {
+            continue;
+        }
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let name = lobby.profiles.get(&event.client_id).map_or(event.client_id.to_string(), |profile| profile.name.clone());
+        info!("[chat] {}: {}", name, text);
+        let message = ChatMessage {
+            id: event.client_id,
+            name,
+            text: text.to_string(),
+        };
+        broadcast_chat(&mut server, &lobby, &message);
+        chat_messages.send(message);
+    }
+}
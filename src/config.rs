@@ -3,9 +3,14 @@ use bevy::prelude::*;
 #[derive(Resource)]
 pub struct Config {
     pub cars_count: usize,
+    /*Car archetype of the player car outside of multiplayer.*/
+    pub archetype: u128,
 }
 impl Default for Config {
     fn default() -> Self {
-        Self { cars_count: 1 }
+        Self {
+            cars_count: 1,
+            archetype: 0,
+        }
     }
-}
\ No newline at end of file
+}
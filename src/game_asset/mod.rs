@@ -1,15 +1,26 @@
 
+#[cfg(feature = "graphics")]
 mod gameasset;
 use bevy::prelude::*;
+#[cfg(feature = "graphics")]
 use bevy_asset_loader::prelude::*;
+#[cfg(feature = "graphics")]
 pub use gameasset::GameAssets;
+#[cfg(feature = "graphics")]
 use crate::gamestate::GameState;
 
+/*Built without graphics nothing is loaded, this never exists and the spawners get None.*/
+#[cfg(not(feature = "graphics"))]
+#[derive(Resource)]
+pub struct GameAssets;
+
+#[cfg(feature = "graphics")]
 pub struct LoadingPlugin;
 
 /// This plugin loads all assets using [`AssetLoader`] from a third party bevy plugin
 /// Alternatively you can write the logic to load assets yourself
 /// If interested, take a look at <https://bevy-cheatbook.github.io/features/assets.html>
+#[cfg(feature = "graphics")]
 impl Plugin for LoadingPlugin {
     fn build(&self, app: &mut App) {
         app.add_loading_state(
@@ -17,4 +28,4 @@ impl Plugin for LoadingPlugin {
         )
         .add_collection_to_loading_state::<_, GameAssets>(GameState::Loading);
     }
-}
\ No newline at end of file
+}
@@ -3,6 +3,7 @@ use bevy::prelude::*;
 use bevy::math::*;
 use serde::{Serialize,Deserialize};
 
+#[cfg(feature = "graphics")]
 pub struct ControllerSignal {
     pub move_dir: Vec3,
     pub throttle : f32,
@@ -12,6 +13,7 @@ pub struct ControllerSignal {
     pub pause_game : bool,
 }
 
+#[cfg(feature = "graphics")]
 impl Default for ControllerSignal {
     fn default() -> Self {
         Self {
@@ -35,6 +37,16 @@ pub struct PlayerInputState
     pub direction : [f32;3]
 }
 
+/*Input state stamped with the client tick it was sampled on, so the server can acknowledge it.*/
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct TickedInputState
+{
+    pub tick : u32,
+    pub snapshot_ack : u32,
+    pub input : PlayerInputState,
+}
+
+#[cfg(feature = "graphics")]
 #[derive(Resource)]
 pub struct PlayerController<const MAXVIEW : u32> {
     pub keyboard : ControllerSignal,
@@ -49,6 +61,7 @@ pub struct PlayerController<const MAXVIEW : u32> {
 }
 
 
+#[cfg(feature = "graphics")]
 impl<const MAXVIEW : u32> Default for PlayerController<MAXVIEW> {
     fn default() -> Self {
         Self {
@@ -63,6 +76,7 @@ impl<const MAXVIEW : u32> Default for PlayerController<MAXVIEW> {
         }
     }
 }
+#[cfg(feature = "graphics")]
 impl<const MAXVIEW : u32> PlayerController<MAXVIEW> {
     pub fn set_view_index(&mut self,index :u32) -> () {
         self.view_index = index % MAXVIEW;
@@ -1,7 +1,9 @@
 
 use bevy::prelude::*;
 pub mod controller;
+#[cfg(feature = "graphics")]
 pub mod keyboard;
+#[cfg(feature = "graphics")]
 pub mod gamepad;
 
 
@@ -11,20 +13,26 @@ pub mod joystick;
 #[cfg(feature = "virtual_joystick")]
 use crate::joystick::{initialize_joystick,JoystickTypeAxis};
 
+#[cfg(feature = "graphics")]
 use crate::GameState;
+#[cfg(feature = "graphics")]
 use crate::controller::PlayerController;
 
+#[cfg(feature = "graphics")]
 use self::controller::PlayerInputState;
+#[cfg(feature = "graphics")]
 use self::gamepad::process_gamepad;
 
 #[cfg(feature = "virtual_joystick")]
 use self::joystick::process_joystick;
 
+#[cfg(feature = "graphics")]
 use self::keyboard::process_keyboard;
 
 #[derive(Component)]
 pub struct PlayerControlled;
 
+#[cfg(feature = "graphics")]
 pub fn cache_current_inputstate<const MAXVIEW : u32>(
     mut input_state: ResMut<PlayerInputState>,
     controller: Res<PlayerController<MAXVIEW>>) 
@@ -36,8 +44,10 @@ pub fn cache_current_inputstate<const MAXVIEW : u32>(
     input_state.direction = controller.get_move_direction().to_array();
 }
 
+#[cfg(feature = "graphics")]
 pub struct InputPlugin<const MAXVIEW:u32>;
 
+#[cfg(feature = "graphics")]
 impl<const MAXVIEW:u32> Plugin for InputPlugin<MAXVIEW> {
     fn build(&self, app: &mut App) {
 
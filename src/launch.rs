@@ -0,0 +1,622 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy::window::{PresentMode, WindowMode, WindowResolution};
+use ron::extensions::Extensions;
+use serde::Deserialize;
+
+use crate::car::car_archetype;
+use crate::renet::conditioner::LinkConditions;
+use crate::renet::profile::parse_color;
+use crate::renet::{NetConfig, NetworkMode};
+use crate::track::TRACK_NAMES;
+use crate::{add_headless_plugins, TickRate};
+
+pub const USAGE: &str = "usage: [--config FILE] [--mode standalone|client|server|host] [--address IP] [--port PORT] [--max-clients N]
+       [--public-address IP] [--server-name NAME] [--discovery-port PORT] [--status-port PORT]
+       [--track NAME] [--name NAME] [--color RRGGBB] [--archetype N] [--spectate] [--cars N] [--tick-rate HZ]
+       [--headless | --window] [--width W] [--height H] [--fullscreen] [--no-vsync]
+       [--send-rate HZ] [--snapshot-budget BYTES] [--resume-grace SECONDS] [--admin-password PASSWORD]
+       [--latency MS] [--jitter MS] [--loss PERCENT] [--duplicate PERCENT]
+
+Settings are read in order from the defaults of the binary, the RON config FILE,
+the RENET_* environment variables and the command line, later ones win.
+The server and the host listen on address:port, the client connects to it. A server bound to
+another address than the one clients reach it on, 0.0.0.0 or behind NAT, sets --public-address,
+connect tokens list it and LAN discovery advertises it.
+Any of --latency, --jitter, --loss or --duplicate turns on the network condition simulator.";
+
+#[derive(Debug)]
+pub enum LaunchError {
+    Usage(String),
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, ron::error::SpannedError),
+}
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LaunchError::Usage(message) => write!(f, "{}", message),
+            LaunchError::Io(path, e) => write!(f, "can not read {}: {}", path.display(), e),
+            LaunchError::Parse(path, e) => write!(f, "can not parse {}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for LaunchError {}
+
+fn usage_error<T>(message: impl Into<String>) -> Result<T, LaunchError> {
+    Err(LaunchError::Usage(message.into()))
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowConfig {
+    pub width: f32,
+    pub height: f32,
+    pub fullscreen: bool,
+    pub vsync: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let (width, height) = (1280., 720.);
+        #[cfg(target_arch = "wasm32")]
+        let (width, height) = (720., 360.);
+        Self {
+            width,
+            height,
+            fullscreen: false,
+            vsync: true,
+        }
+    }
+}
+
+/*Everything a binary needs to know to start, resolved from defaults, config file, environment and command line.*/
+#[derive(Debug, Clone, Resource)]
+pub struct LaunchConfig {
+    pub mode: NetworkMode,
+    pub net: NetConfig,
+    /*Name the server gives itself in the LAN server list.*/
+    pub server_name: String,
+    pub track: String,
+    pub cars_count: usize,
+    pub tick_rate: TickRate,
+    pub headless: bool,
+    pub window: WindowConfig,
+    /*Checked by the server for remote admin commands, sent along with them by a client.*/
+    pub admin_password: Option<String>,
+    /*Loopback port of the server's HTTP status endpoint, None leaves it off.*/
+    pub status_port: Option<u16>,
+}
+
+impl LaunchConfig {
+    /*Defaults of a binary, the dedicated server runs headless and so does anything built without graphics.*/
+    pub fn new(mode: NetworkMode) -> Self {
+        Self {
+            mode,
+            net: NetConfig::default(),
+            server_name: "Bevy Racing Redux".to_string(),
+            track: TRACK_NAMES[0].to_string(),
+            cars_count: 1,
+            tick_rate: TickRate::default(),
+            headless: mode == NetworkMode::Server || !cfg!(feature = "graphics"),
+            window: WindowConfig::default(),
+            admin_password: None,
+            status_port: None,
+        }
+    }
+
+    /*Launch config of the process, prints the usage and exits when it is invalid.*/
+    pub fn from_args(mode: NetworkMode) -> Self {
+        match Self::parse(mode, std::env::args().skip(1)) {
+            Ok(Some(launch)) => launch,
+            Ok(None) => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("{}\n\n{}", e, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    /*None when the usage was asked for.*/
+    pub fn parse(mode: NetworkMode, args: impl IntoIterator<Item = String>) -> Result<Option<Self>, LaunchError> {
+        let Some((file, args)) = LaunchOverrides::from_args(args)? else {
+            return Ok(None);
+        };
+        let file = file.map(|path| LaunchOverrides::from_file(&path)).transpose()?;
+        // the mode picks the defaults and the environment variables, so settle it first
+        let mode = args.mode.or(file.as_ref().and_then(|file| file.mode)).unwrap_or(mode);
+        let mut launch = Self::new(mode);
+        if let Some(file) = file {
+            file.apply(&mut launch)?;
+        }
+        LaunchOverrides::from_env(mode)?.apply(&mut launch)?;
+        args.apply(&mut launch)?;
+        launch.validate()?;
+        Ok(Some(launch))
+    }
+
+    pub fn validate(&self) -> Result<(), LaunchError> {
+        if !TRACK_NAMES.contains(&self.track.as_str()) {
+            return usage_error(format!("unknown track {}, known tracks are {}", self.track, TRACK_NAMES.join(", ")));
+        }
+        if car_archetype(self.net.profile.archetype).is_none() {
+            return usage_error(format!("unknown car archetype {}", self.net.profile.archetype));
+        }
+        if let Err(e) = self.net.profile.validate() {
+            return usage_error(e.to_string());
+        }
+        if self.net.max_clients == 0 {
+            return usage_error("max clients must be at least 1");
+        }
+        if self.cars_count == 0 {
+            return usage_error("cars must be at least 1");
+        }
+        if self.tick_rate.0 <= 0. || !self.tick_rate.0.is_finite() {
+            return usage_error("tick rate must be a positive number");
+        }
+        if self.admin_password.as_deref() == Some("") {
+            return usage_error("admin password can not be empty");
+        }
+        if self.net.replication.send_rate <= 0. || !self.net.replication.send_rate.is_finite() {
+            return usage_error("send rate must be a positive number");
+        }
+        if self.net.replication.snapshot_budget == 0 {
+            return usage_error("snapshot budget must be at least 1 byte");
+        }
+        if self.net.resume_grace < 0. || !self.net.resume_grace.is_finite() {
+            return usage_error("resume grace must be 0 or more seconds");
+        }
+        if self.net.profile.spectator && self.mode != NetworkMode::Client {
+            return usage_error("only a client can spectate");
+        }
+        if self.net.public_address.is_some() && !matches!(self.mode, NetworkMode::Server | NetworkMode::Host) {
+            return usage_error("only the server or a host has a public address");
+        }
+        if self.net.public_address.is_some_and(|address| address.is_unspecified()) {
+            return usage_error("public address must be one clients can connect to");
+        }
+        if self.status_port.is_some() && !matches!(self.mode, NetworkMode::Server | NetworkMode::Host) {
+            return usage_error("only the server or a host has a status endpoint");
+        }
+        if self.headless && !matches!(self.mode, NetworkMode::Server | NetworkMode::Client) {
+            return usage_error("only the server or a client can run headless");
+        }
+        if !self.headless && !cfg!(feature = "graphics") {
+            return usage_error("built without the graphics feature, only --headless runs");
+        }
+        if let Some(conditions) = &self.net.conditions {
+            if self.mode == NetworkMode::Standalone {
+                return usage_error("network conditions need a client, server or host");
+            }
+            conditions.validate().or_else(usage_error)?;
+        }
+        Ok(())
+    }
+
+    pub fn window_plugin(&self) -> WindowPlugin {
+        WindowPlugin {
+            primary_window: Some(Window {
+                title: "Bevy Racing Redux".to_string(),
+                resolution: WindowResolution::new(self.window.width, self.window.height),
+                mode: if self.window.fullscreen { WindowMode::BorderlessFullscreen } else { WindowMode::Windowed },
+                present_mode: if self.window.vsync { PresentMode::AutoVsync } else { PresentMode::AutoNoVsync },
+                canvas: Some("#bevy-racing-redux".to_string()),
+                ..default()
+            }),
+            ..default()
+        }
+    }
+}
+
+/*Bevy plugins of the launch, a window with the full renderer or the headless set at the tick rate.*/
+pub fn add_launch_plugins(app: &mut App, launch: &LaunchConfig) -> &mut App {
+    if launch.headless {
+        add_headless_plugins(app, launch.tick_rate)
+    } else {
+        app.add_plugins(DefaultPlugins.set(launch.window_plugin()))
+    }
+}
+
+/*One layer of settings, the config file, the environment and the command line each give one.*/
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LaunchOverrides {
+    pub mode: Option<NetworkMode>,
+    pub address: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub public_address: Option<IpAddr>,
+    pub max_clients: Option<usize>,
+    pub server_name: Option<String>,
+    pub discovery_port: Option<u16>,
+    pub status_port: Option<u16>,
+    pub track: Option<String>,
+    pub name: Option<String>,
+    pub color: Option<String>,
+    pub archetype: Option<u128>,
+    pub spectate: Option<bool>,
+    pub cars: Option<usize>,
+    pub tick_rate: Option<f64>,
+    pub headless: Option<bool>,
+    pub width: Option<f32>,
+    pub height: Option<f32>,
+    pub fullscreen: Option<bool>,
+    pub vsync: Option<bool>,
+    pub admin_password: Option<String>,
+    pub send_rate: Option<f64>,
+    pub snapshot_budget: Option<usize>,
+    pub resume_grace: Option<f32>,
+    pub latency: Option<u32>,
+    pub jitter: Option<u32>,
+    pub loss: Option<f32>,
+    pub duplicate: Option<f32>,
+}
+
+impl LaunchOverrides {
+    pub fn from_file(path: &Path) -> Result<Self, LaunchError> {
+        let text = std::fs::read_to_string(path).map_err(|e| LaunchError::Io(path.to_path_buf(), e))?;
+        ron::Options::default()
+            .with_default_extension(Extensions::IMPLICIT_SOME)
+            .from_str(&text)
+            .map_err(|e| LaunchError::Parse(path.to_path_buf(), e))
+    }
+
+    /*RENET_SERVER_SOCKET on the server and RENET_SERVER_ADDR on the client, the RENET_PLAYER_* profile and RENET_ADMIN_PASSWORD.*/
+    pub fn from_env(mode: NetworkMode) -> Result<Self, LaunchError> {
+        let mut overrides = Self::default();
+        let socket_var = if mode == NetworkMode::Server { "RENET_SERVER_SOCKET" } else { "RENET_SERVER_ADDR" };
+        if let Ok(addr) = std::env::var(socket_var) {
+            let Ok(addr) = addr.parse::<SocketAddr>() else {
+                return usage_error(format!("{} must be an ip:port address", socket_var));
+            };
+            overrides.address = Some(addr.ip());
+            overrides.port = Some(addr.port());
+        }
+        overrides.name = std::env::var("RENET_PLAYER_NAME").ok();
+        overrides.color = std::env::var("RENET_PLAYER_COLOR").ok();
+        if let Ok(archetype) = std::env::var("RENET_PLAYER_ARCHETYPE") {
+            overrides.archetype = Some(parse_arg("RENET_PLAYER_ARCHETYPE", &archetype)?);
+        }
+        overrides.admin_password = std::env::var("RENET_ADMIN_PASSWORD").ok();
+        Ok(overrides)
+    }
+
+    /*The command line layer and the config file it names, None when the usage was asked for.*/
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Option<(Option<PathBuf>, Self)>, LaunchError> {
+        let mut overrides = Self::default();
+        let mut file = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| LaunchError::Usage(format!("missing value for {}", arg)));
+            match arg.as_str() {
+                "--config" => file = Some(PathBuf::from(value()?)),
+                "--mode" => overrides.mode = Some(parse_arg(&arg, &value()?)?),
+                "--address" => overrides.address = Some(parse_arg(&arg, &value()?)?),
+                "--port" => overrides.port = Some(parse_arg(&arg, &value()?)?),
+                "--public-address" => overrides.public_address = Some(parse_arg(&arg, &value()?)?),
+                "--max-clients" => overrides.max_clients = Some(parse_arg(&arg, &value()?)?),
+                "--server-name" => overrides.server_name = Some(value()?),
+                "--discovery-port" => overrides.discovery_port = Some(parse_arg(&arg, &value()?)?),
+                "--status-port" => overrides.status_port = Some(parse_arg(&arg, &value()?)?),
+                "--track" => overrides.track = Some(value()?),
+                "--name" => overrides.name = Some(value()?),
+                "--color" => overrides.color = Some(value()?),
+                "--archetype" => overrides.archetype = Some(parse_arg(&arg, &value()?)?),
+                "--cars" => overrides.cars = Some(parse_arg(&arg, &value()?)?),
+                "--tick-rate" => overrides.tick_rate = Some(parse_arg(&arg, &value()?)?),
+                "--width" => overrides.width = Some(parse_arg(&arg, &value()?)?),
+                "--height" => overrides.height = Some(parse_arg(&arg, &value()?)?),
+                "--admin-password" => overrides.admin_password = Some(value()?),
+                "--send-rate" => overrides.send_rate = Some(parse_arg(&arg, &value()?)?),
+                "--snapshot-budget" => overrides.snapshot_budget = Some(parse_arg(&arg, &value()?)?),
+                "--resume-grace" => overrides.resume_grace = Some(parse_arg(&arg, &value()?)?),
+                "--latency" => overrides.latency = Some(parse_arg(&arg, &value()?)?),
+                "--jitter" => overrides.jitter = Some(parse_arg(&arg, &value()?)?),
+                "--loss" => overrides.loss = Some(parse_arg(&arg, &value()?)?),
+                "--duplicate" => overrides.duplicate = Some(parse_arg(&arg, &value()?)?),
+                "--spectate" => overrides.spectate = Some(true),
+                "--headless" => overrides.headless = Some(true),
+                "--window" => overrides.headless = Some(false),
+                "--fullscreen" => overrides.fullscreen = Some(true),
+                "--no-vsync" => overrides.vsync = Some(false),
+                "--help" | "-h" => return Ok(None),
+                other => return usage_error(format!("unknown argument {}", other)),
+            }
+        }
+        Ok(Some((file, overrides)))
+    }
+
+    pub fn apply(self, launch: &mut LaunchConfig) -> Result<(), LaunchError> {
+        if let Some(mode) = self.mode {
+            launch.mode = mode;
+        }
+        if let Some(address) = self.address {
+            launch.net.address = address;
+        }
+        if let Some(port) = self.port {
+            launch.net.port = port;
+        }
+        if let Some(public_address) = self.public_address {
+            launch.net.public_address = Some(public_address);
+        }
+        if let Some(max_clients) = self.max_clients {
+            launch.net.max_clients = max_clients;
+        }
+        if let Some(server_name) = self.server_name {
+            launch.server_name = server_name;
+        }
+        if let Some(discovery_port) = self.discovery_port {
+            launch.net.discovery_port = discovery_port;
+        }
+        if let Some(status_port) = self.status_port {
+            launch.status_port = Some(status_port);
+        }
+        if let Some(track) = self.track {
+            launch.track = track;
+        }
+        if let Some(name) = self.name {
+            launch.net.profile.name = name.trim().to_string();
+        }
+        if let Some(color) = self.color {
+            let Some(color) = parse_color(&color) else {
+                return usage_error("color must be rrggbb hex");
+            };
+            launch.net.profile.color = color;
+        }
+        if let Some(archetype) = self.archetype {
+            launch.net.profile.archetype = archetype;
+        }
+        if let Some(spectate) = self.spectate {
+            launch.net.profile.spectator = spectate;
+        }
+        if let Some(cars) = self.cars {
+            launch.cars_count = cars;
+        }
+        if let Some(tick_rate) = self.tick_rate {
+            launch.tick_rate = TickRate(tick_rate);
+        }
+        if let Some(headless) = self.headless {
+            launch.headless = headless;
+        }
+        if let Some(width) = self.width {
+            launch.window.width = width;
+        }
+        if let Some(height) = self.height {
+            launch.window.height = height;
+        }
+        if let Some(fullscreen) = self.fullscreen {
+            launch.window.fullscreen = fullscreen;
+        }
+        if let Some(vsync) = self.vsync {
+            launch.window.vsync = vsync;
+        }
+        if let Some(admin_password) = self.admin_password {
+            launch.admin_password = Some(admin_password);
+        }
+        if let Some(send_rate) = self.send_rate {
+            launch.net.replication.send_rate = send_rate;
+        }
+        if let Some(snapshot_budget) = self.snapshot_budget {
+            launch.net.replication.snapshot_budget = snapshot_budget;
+        }
+        if let Some(resume_grace) = self.resume_grace {
+            launch.net.resume_grace = resume_grace;
+        }
+        fn conditions(launch: &mut LaunchConfig) -> &mut LinkConditions {
+            launch.net.conditions.get_or_insert_with(Default::default)
+        }
+        if let Some(latency) = self.latency {
+            conditions(launch).latency_ms = latency;
+        }
+        if let Some(jitter) = self.jitter {
+            conditions(launch).jitter_ms = jitter;
+        }
+        if let Some(loss) = self.loss {
+            conditions(launch).loss_percent = loss;
+        }
+        if let Some(duplicate) = self.duplicate {
+            conditions(launch).duplicate_percent = duplicate;
+        }
+        Ok(())
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, LaunchError> {
+    value.parse().or_else(|_| usage_error(format!("invalid value {} for {}", value, name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /*The environment is one for the whole test process, parse reads it so these take turns.*/
+    static ENV: Mutex<()> = Mutex::new(());
+
+    const ENV_VARS: &[&str] = &[
+        "RENET_SERVER_SOCKET",
+        "RENET_SERVER_ADDR",
+        "RENET_PLAYER_NAME",
+        "RENET_PLAYER_COLOR",
+        "RENET_PLAYER_ARCHETYPE",
+        "RENET_ADMIN_PASSWORD",
+    ];
+
+    fn parse_with(mode: NetworkMode, file: Option<&str>, env: &[(&str, &str)], args: &[&str]) -> Result<Option<LaunchConfig>, LaunchError> {
+        let _guard = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let path = file.map(|text| {
+            let path = std::env::temp_dir().join(format!("launch-test-{}-{:?}.ron", std::process::id(), std::thread::current().id()));
+            std::fs::write(&path, text).unwrap();
+            args.extend(["--config".to_string(), path.display().to_string()]);
+            path
+        });
+        for var in ENV_VARS {
+            std::env::remove_var(var);
+        }
+        for (var, value) in env {
+            std::env::set_var(var, value);
+        }
+        let launch = LaunchConfig::parse(mode, args);
+        for (var, _) in env {
+            std::env::remove_var(var);
+        }
+        if let Some(path) = path {
+            let _ = std::fs::remove_file(path);
+        }
+        launch
+    }
+
+    fn parse_ok(mode: NetworkMode, file: Option<&str>, env: &[(&str, &str)], args: &[&str]) -> LaunchConfig {
+        parse_with(mode, file, env, args).unwrap().unwrap()
+    }
+
+    fn usage_message(result: Result<Option<LaunchConfig>, LaunchError>) -> String {
+        match result {
+            Err(LaunchError::Usage(message)) => message,
+            other => panic!("expected a usage error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn defaults_of_the_binary() {
+        let launch = parse_ok(NetworkMode::Server, None, &[], &[]);
+        let defaults = LaunchConfig::new(NetworkMode::Server);
+        assert_eq!(launch.mode, NetworkMode::Server);
+        assert!(launch.headless);
+        assert_eq!(launch.net.address, defaults.net.address);
+        assert_eq!(launch.net.port, defaults.net.port);
+        assert_eq!(launch.track, TRACK_NAMES[0]);
+        assert_eq!(launch.cars_count, 1);
+        assert_eq!(launch.admin_password, None);
+        assert!(launch.net.conditions.is_none());
+    }
+
+    #[test]
+    fn help_returns_none() {
+        assert!(parse_with(NetworkMode::Client, None, &[], &["--port", "5000", "--help"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let file = "(port: 6000, name: \"File\", cars: 2, tick_rate: 30.0, admin_password: \"file\")";
+        let env = [("RENET_SERVER_ADDR", "10.0.0.1:7000"), ("RENET_PLAYER_NAME", "Env")];
+        let launch = parse_ok(NetworkMode::Client, Some(file), &env, &["--port", "8000", "--headless"]);
+        // command line over environment over file
+        assert_eq!(launch.net.port, 8000);
+        // environment over file
+        assert_eq!(launch.net.address, "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(launch.net.profile.name, "Env");
+        // file over defaults
+        assert_eq!(launch.cars_count, 2);
+        assert_eq!(launch.tick_rate.0, 30.);
+        assert_eq!(launch.admin_password.as_deref(), Some("file"));
+        // defaults where nobody says anything
+        assert_eq!(launch.net.replication.send_rate, LaunchConfig::new(NetworkMode::Client).net.replication.send_rate);
+    }
+
+    #[test]
+    fn command_line_over_file_and_environment() {
+        let file = "(name: \"File\", admin_password: \"file\")";
+        let env = [("RENET_PLAYER_NAME", "Env"), ("RENET_ADMIN_PASSWORD", "env")];
+        let launch = parse_ok(NetworkMode::Client, Some(file), &env, &["--name", "Args", "--admin-password", "args", "--headless"]);
+        assert_eq!(launch.net.profile.name, "Args");
+        assert_eq!(launch.admin_password.as_deref(), Some("args"));
+    }
+
+    #[test]
+    fn file_mode_picks_the_defaults() {
+        // the server defaults to headless, a client does not unless built without graphics
+        let launch = parse_ok(NetworkMode::Client, Some("(mode: Server)"), &[], &[]);
+        assert_eq!(launch.mode, NetworkMode::Server);
+        assert!(launch.headless);
+        let launch = parse_ok(NetworkMode::Client, Some("(mode: Server)"), &[], &["--mode", "client", "--headless"]);
+        assert_eq!(launch.mode, NetworkMode::Client);
+    }
+
+    #[test]
+    fn server_reads_its_own_socket_variable() {
+        let env = [("RENET_SERVER_SOCKET", "0.0.0.0:7000"), ("RENET_SERVER_ADDR", "10.0.0.1:8000")];
+        let launch = parse_ok(NetworkMode::Server, None, &env, &[]);
+        assert_eq!(launch.net.address, "0.0.0.0".parse::<IpAddr>().unwrap());
+        assert_eq!(launch.net.port, 7000);
+    }
+
+    #[test]
+    fn network_conditions_turn_on_the_simulator() {
+        let launch = parse_ok(NetworkMode::Client, None, &[], &["--latency", "100", "--loss", "2", "--headless"]);
+        let conditions = launch.net.conditions.unwrap();
+        assert_eq!(conditions.latency_ms, 100);
+        assert_eq!(conditions.loss_percent, 2.);
+        assert_eq!(conditions.jitter_ms, LinkConditions::default().jitter_ms);
+    }
+
+    #[test]
+    fn bad_command_line_values() {
+        let cases: &[(&[&str], &str)] = &[
+            (&["--port", "nope"], "invalid value nope for --port"),
+            (&["--port"], "missing value for --port"),
+            (&["--bogus"], "unknown argument --bogus"),
+            (&["--mode", "referee"], "invalid value referee for --mode"),
+            (&["--color", "orange"], "color must be rrggbb hex"),
+            (&["--track", "moon"], "unknown track moon"),
+            (&["--archetype", "99"], "unknown car archetype 99"),
+            (&["--cars", "0"], "cars must be at least 1"),
+            (&["--max-clients", "0"], "max clients must be at least 1"),
+            (&["--tick-rate", "0"], "tick rate must be a positive number"),
+            (&["--send-rate", "-1"], "send rate must be a positive number"),
+            (&["--admin-password", ""], "admin password can not be empty"),
+            (&["--loss", "150"], "loss must be a percentage between 0 and 100"),
+            (&["--public-address", "0.0.0.0"], "public address must be one clients can connect to"),
+        ];
+        for (args, message) in cases {
+            let mut args = args.to_vec();
+            args.push("--headless");
+            let error = usage_message(parse_with(NetworkMode::Server, None, &[], &args));
+            assert!(error.contains(message), "{:?} gave {:?}", args, error);
+        }
+    }
+
+    #[test]
+    fn bad_values_outside_the_command_line() {
+        let error = usage_message(parse_with(NetworkMode::Client, None, &[("RENET_SERVER_ADDR", "localhost")], &["--headless"]));
+        assert_eq!(error, "RENET_SERVER_ADDR must be an ip:port address");
+        let error = usage_message(parse_with(NetworkMode::Client, None, &[("RENET_PLAYER_ARCHETYPE", "race")], &["--headless"]));
+        assert_eq!(error, "invalid value race for RENET_PLAYER_ARCHETYPE");
+        assert!(matches!(
+            parse_with(NetworkMode::Client, Some("(port: \"5000\")"), &[], &[]),
+            Err(LaunchError::Parse(..))
+        ));
+        assert!(matches!(
+            parse_with(NetworkMode::Client, Some("(speed: 5)"), &[], &[]),
+            Err(LaunchError::Parse(..))
+        ));
+        assert!(matches!(
+            parse_with(NetworkMode::Client, None, &[], &["--config", "/nonexistent/launch.ron"]),
+            Err(LaunchError::Io(..))
+        ));
+    }
+
+    #[test]
+    fn mode_specific_settings() {
+        let error = usage_message(parse_with(NetworkMode::Server, None, &[], &["--spectate"]));
+        assert_eq!(error, "only a client can spectate");
+        let error = usage_message(parse_with(NetworkMode::Client, None, &[], &["--public-address", "203.0.113.7", "--headless"]));
+        assert_eq!(error, "only the server or a host has a public address");
+        let error = usage_message(parse_with(NetworkMode::Client, None, &[], &["--status-port", "5200", "--headless"]));
+        assert_eq!(error, "only the server or a host has a status endpoint");
+        if cfg!(feature = "graphics") {
+            let error = usage_message(parse_with(NetworkMode::Standalone, None, &[], &["--latency", "50"]));
+            assert_eq!(error, "network conditions need a client, server or host");
+        } else {
+            let error = usage_message(parse_with(NetworkMode::Client, None, &[], &["--window"]));
+            assert_eq!(error, "built without the graphics feature, only --headless runs");
+        }
+    }
+}
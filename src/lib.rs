@@ -1,44 +1,124 @@
 #![allow(clippy::type_complexity)]
+#![allow(clippy::too_many_arguments)]
 
 mod game_asset;
+#[cfg(feature = "graphics")]
 mod menu;
 mod car;
 mod track;
 mod collision;
 mod gamestate;
+#[cfg(feature = "graphics")]
 pub mod camera;
+#[cfg(feature = "graphics")]
 mod light;
 pub mod renet;
 mod physics;
+#[cfg(feature = "graphics")]
 mod graphics;
+mod race;
+mod chat;
+mod admin;
+mod status;
+pub mod bot;
+pub mod launch;
+#[cfg(feature = "graphics")]
 use graphics::GraphicSettingPlugin;
+#[cfg(feature = "graphics")]
 use bevy_kira_audio::prelude::*;
+#[cfg(feature = "graphics")]
 use crate::game_asset::LoadingPlugin;
+#[cfg(feature = "graphics")]
 use crate::menu::MenuPlugin;
 use bevy::app::App;
-use crate::physics::{PhysicPlugin,physics_settings::PhysicsParams};
+use crate::physics::{PhysicPlugin,PhysicsStep,NoLocalPhysics,run_physics_step,physics_settings::PhysicsParams};
 mod config;
 mod input;
 
-use crate::car::{aero_system,do_input_from_state, esp_system,dash_start_system,dash_fps_system,dash_speed_update_system,spawn_car_start_system,spawn_car_system};
+use crate::car::{aero_system,do_input_from_state, esp_system,spawn_car_start_system,spawn_car_system};
+#[cfg(feature = "graphics")]
+use crate::car::{dash_start_system,dash_fps_system,dash_speed_update_system};
+#[cfg(feature = "graphics")]
 use crate::light::{animate_light_direction, light_start_system};
-use crate::track::{SpawnCarOnTrackEvent, TrackPlugin};
+use crate::track::{SpawnCarOnTrackEvent, TrackPhysicsPlugin};
+#[cfg(feature = "graphics")]
+use crate::track::TrackPlugin;
 use config::*;
+#[cfg(feature = "graphics")]
 use input::*;
+#[cfg(feature = "graphics")]
 use car::control::do_input;
+use input::controller::PlayerInputState;
 use gamestate::GameState;
+#[cfg(feature = "graphics")]
 use crate::camera::CarCameraPlugin;
 use crate::renet::NetworkMode;
+use crate::renet::discovery::DiscoveryInfo;
+use crate::renet::handshake::handshake_accepted;
+use crate::renet::reconnect::{client_join_server_system,client_reconnect_system};
+use crate::launch::LaunchConfig;
 
 
 #[cfg(debug_assertions)]
 use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, diagnostic::LogDiagnosticsPlugin};
 use bevy::prelude::*;
 use car::{PlayerCarSpawner, CarSet};
-use crate::renet::server::NetServerPlugin;
-use car::{PlayerCarCommandProcessor,server_network_sync, PlayerCarInputProcessor};
-use crate::renet::client::{NetClientPlugin,NullPlayerCommand};
-use crate::car::client_sync_entities;
+use crate::renet::server::{NetServerPlugin,ServerInputSet,start_renet_server};
+use crate::renet::error::NetworkError;
+use car::{PlayerCarCommandProcessor,PlayerCarCommandEvent,server_network_sync, PlayerCarInputProcessor,host_player_commands_system};
+use crate::renet::client::{NetClientPlugin,ClientInputSet};
+use car::PlayerCarCommand;
+use crate::admin::{AdminConfig,AdminCommandEvent,admin_remote_system,admin_command_system,admin_console_system,new_admin_console};
+use crate::chat::{ChatLog,ChatMessage,server_chat_system,chat_log_system};
+#[cfg(feature = "graphics")]
+use crate::chat::{ChatInput,chat_input_system,chat_ui_system};
+use crate::race::{RaceConfig,RaceSession,race_hold_cars_system,server_race_session_system,server_race_progress_system,client_race_session_system,client_ready_input_system};
+#[cfg(feature = "graphics")]
+use crate::race::client_race_status_ui_system;
+#[cfg(feature = "graphics")]
+use crate::car::{spectator_camera_system,nameplate_system};
+use crate::car::{client_forget_history_system,client_sync_entities,client_apply_input,client_record_prediction,client_reconcile_system,client_remote_wheels_system,PredictionConfig,PredictionHistory,SnapshotHistory,SentSnapshots,track_quantization_bounds};
+use crate::renet::interpolation::interpolate_snapshots_system;
+use crate::status::{TickTimings,new_status_endpoint,server_status_system,tick_start_system,tick_end_system};
+use bevy_rapier3d::prelude::PhysicsSet;
+use bevy::app::ScheduleRunnerPlugin;
+use std::time::Duration;
+
+/*Simulation ticks per second of a headless server.*/
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct TickRate(pub f64);
+
+impl Default for TickRate {
+    fn default() -> Self {
+        TickRate(60.)
+    }
+}
+
+/*True when the app renders, false on a headless server.*/
+#[cfg(feature = "graphics")]
+pub fn has_renderer(app: &App) -> bool {
+    app.is_plugin_added::<bevy::render::RenderPlugin>()
+}
+
+/*Built without graphics nothing can render.*/
+#[cfg(not(feature = "graphics"))]
+pub fn has_renderer(_app: &App) -> bool {
+    false
+}
+
+/*What the simulation needs from Bevy without window, renderer or audio, the loop runs at a fixed tick rate.*/
+pub fn add_headless_plugins(app: &mut App, tick_rate: TickRate) -> &mut App {
+    app.add_plugins((
+        MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1. / tick_rate.0))),
+        bevy::log::LogPlugin::default(),
+        TransformPlugin,
+        HierarchyPlugin,
+        bevy::diagnostic::DiagnosticsPlugin,
+        bevy::asset::AssetPlugin::default(),
+        bevy::scene::ScenePlugin,
+    ))
+    .insert_resource(tick_rate)
+}
 
 #[cfg(feature = "graphics")]
 pub fn setup_simple_camera(mut commands: Commands) {
@@ -47,7 +127,9 @@ pub fn setup_simple_camera(mut commands: Commands) {
         ..Default::default()
     });
 }
+#[cfg(feature = "graphics")]
 use bevy::app::AppExit;
+#[cfg(feature = "graphics")]
 pub fn close_on_esc_ex(
     mut exit: EventWriter<AppExit>,
     input: Res<Input<KeyCode>>,
@@ -63,25 +145,113 @@ fn bypass_menu_state(mut state: ResMut<NextState<GameState>>) {
     info!("Bypass to playing because of it's server");
     state.set(GameState::Playing);
 }
-pub fn car_app(app: &mut App,network_mode : NetworkMode) -> &mut App {
+
+/*Nothing to load without a renderer.*/
+fn bypass_loading_state(mut state: ResMut<NextState<GameState>>) {
+    info!("Bypass to playing because of it's headless");
+    state.set(GameState::Playing);
+}
+/*The whole game for launch, fails when the server of a server or host can not be started.*/
+pub fn car_app(app: &mut App,launch : LaunchConfig) -> Result<&mut App, NetworkError> {
     //#[cfg(feature = "nn")]
     //let esp_run_after: CarSet = CarSet::NeuralNetwork;
     #[cfg(not(feature = "nn"))]
     let esp_run_after: CarSet = CarSet::Input;
 
+    let headless = !has_renderer(app);
+    let network_mode = launch.mode;
+    app.insert_resource(launch.net.clone())
+        .insert_resource(launch.tick_rate);
     app.add_state::<GameState>()
-        .add_plugins((
-            LoadingPlugin,
-            PhysicPlugin(PhysicsParams::make_default())
-        ));
+        .add_plugins(PhysicPlugin(PhysicsParams::make_default()));
+        if headless {
+            app.add_systems(OnEnter(GameState::Loading),bypass_loading_state);
+        }
+        #[cfg(feature = "graphics")]
+        if !headless {
+            app.add_plugins(LoadingPlugin);
+        }
+        if network_mode == NetworkMode::Server || network_mode == NetworkMode::Host
+        {
+            let server_plugin = if network_mode == NetworkMode::Host {
+                NetServerPlugin::<PlayerCarSpawner,PlayerCarInputProcessor,PlayerCarCommandProcessor>::host()
+            } else {
+                NetServerPlugin::<PlayerCarSpawner,PlayerCarInputProcessor,PlayerCarCommandProcessor>::default()
+            };
+            app.add_plugins(server_plugin)
+                .configure_set(FixedUpdate,ServerInputSet.before(run_physics_step))
+                .insert_resource(DiscoveryInfo {
+                    name: launch.server_name.clone(),
+                    track: launch.track.clone(),
+                })
+                .insert_resource(track_quantization_bounds())
+                .init_resource::<SentSnapshots>()
+                .add_event::<PlayerCarCommandEvent>();
+            app.add_systems(Update,(server_network_sync,server_race_session_system,server_race_progress_system.after(CarSet::Input)).run_if(in_state(GameState::Playing)))
+                .add_event::<ChatMessage>()
+                .add_systems(Update,server_chat_system.run_if(in_state(GameState::Playing)))
+                .add_event::<AdminCommandEvent>()
+                .insert_resource(new_admin_console())
+                .add_systems(Update,(admin_console_system,admin_remote_system,admin_command_system.after(server_race_session_system))
+                    .chain()
+                    .run_if(in_state(GameState::Playing)));
+            start_renet_server(app)?;
+            if let Some(endpoint) = launch.status_port.and_then(new_status_endpoint) {
+                app.insert_resource(endpoint)
+                    .init_resource::<TickTimings>()
+                    .add_systems(First,tick_start_system)
+                    .add_systems(Last,tick_end_system)
+                    .add_systems(Update,server_status_system);
+            }
+        }
+
+        if network_mode != NetworkMode::Standalone
+        {
+            app.insert_resource(AdminConfig {
+                password: launch.admin_password.clone(),
+            });
+        }
+
+        if network_mode == NetworkMode::Client || network_mode == NetworkMode::Host
+        {
+            app.init_resource::<ChatLog>()
+                .add_systems(Update,chat_log_system.run_if(in_state(GameState::Playing)));
+            #[cfg(feature = "graphics")]
+            if !headless {
+                app.init_resource::<ChatInput>()
+                    .add_systems(PreUpdate,chat_input_system.after(bevy::input::InputSystem).run_if(in_state(GameState::Playing)))
+                    .add_systems(Update,chat_ui_system.after(chat_log_system).run_if(in_state(GameState::Playing)));
+            }
+        }
+
+        if network_mode == NetworkMode::Host
+        {
+            // The host plays through the server session directly, its commands never hit the network.
+            app.add_event::<PlayerCarCommand>()
+                .add_systems(Update,(client_ready_input_system,host_player_commands_system)
+                    .chain()
+                    .before(server_race_session_system)
+                    .run_if(in_state(GameState::Playing)));
+            #[cfg(feature = "graphics")]
+            app.add_systems(Update,client_race_status_ui_system
+                .after(host_player_commands_system)
+                .run_if(in_state(GameState::Playing)));
+        }
+
         if network_mode == NetworkMode::Server
         {
-            app.add_plugins(NetServerPlugin::<PlayerCarSpawner,PlayerCarInputProcessor,PlayerCarCommandProcessor>::default());
-            app.add_systems(OnEnter(GameState::Playing),setup_simple_camera)
-            .add_systems(Update,(server_network_sync,do_input_from_state).run_if(in_state(GameState::Playing)))
-            .add_systems(OnEnter(GameState::Menu),bypass_menu_state);
+            #[cfg(feature = "graphics")]
+            if !headless {
+                app.add_systems(OnEnter(GameState::Playing),setup_simple_camera);
+            }
+            app.add_systems(OnEnter(GameState::Menu),bypass_menu_state);
         }
-        else {
+        else if headless {
+            // Nobody at the keyboard, whatever drives a headless client sets the input state.
+            app.init_resource::<PlayerInputState>();
+        }
+        #[cfg(feature = "graphics")]
+        if network_mode != NetworkMode::Server && !headless {
             app.add_plugins((InputPlugin::<6>,
                 GraphicSettingPlugin,
                 AudioPlugin,MenuPlugin,
@@ -111,8 +281,36 @@ pub fn car_app(app: &mut App,network_mode : NetworkMode) -> &mut App {
 
         if network_mode == NetworkMode::Client
         {
-            app.add_plugins(NetClientPlugin::<PlayerCarSpawner,NullPlayerCommand>::default());
-            app.add_systems(Update,client_sync_entities.run_if(bevy_renet::transport::client_connected()));
+            app.add_plugins(NetClientPlugin::<PlayerCarSpawner,PlayerCarCommand>::default());
+            app.init_resource::<PredictionConfig>()
+                .init_resource::<PredictionHistory>()
+                .insert_resource(track_quantization_bounds())
+                .init_resource::<SnapshotHistory>()
+                .add_systems(Update,client_forget_history_system
+                    .after(client_reconnect_system)
+                    .after(client_join_server_system)
+                    .before(client_sync_entities))
+                .add_systems(Update,client_sync_entities.run_if(bevy_renet::transport::client_connected().and_then(handshake_accepted)))
+                .add_systems(Update,client_reconcile_system
+                    .after(client_sync_entities)
+                    .run_if(bevy_renet::transport::client_connected().and_then(handshake_accepted)))
+                .add_systems(FixedUpdate,(client_apply_input.after(ClientInputSet).before(run_physics_step),client_record_prediction.after(run_physics_step))
+                    .run_if(bevy_renet::transport::client_connected().and_then(handshake_accepted).and_then(not(resource_exists::<NoLocalPhysics>()))))
+                .add_systems(Update,client_remote_wheels_system.after(interpolate_snapshots_system))
+                .add_systems(Update,client_race_session_system
+                    .run_if(in_state(GameState::Playing).and_then(bevy_renet::transport::client_connected().and_then(handshake_accepted))));
+            #[cfg(feature = "graphics")]
+            if !headless {
+                app.add_systems(PostUpdate,nameplate_system.after(bevy::transform::TransformSystem::TransformPropagate).run_if(in_state(GameState::Playing)))
+                    .add_systems(Update,(client_ready_input_system,client_race_status_ui_system)
+                        .chain()
+                        .after(client_race_session_system)
+                        .run_if(in_state(GameState::Playing).and_then(bevy_renet::transport::client_connected().and_then(handshake_accepted))));
+            }
+            #[cfg(feature = "graphics")]
+            if launch.net.profile.spectator && !headless {
+                app.add_systems(Update,spectator_camera_system.run_if(in_state(GameState::Playing)));
+            }
         }
 
 
@@ -121,16 +319,29 @@ pub fn car_app(app: &mut App,network_mode : NetworkMode) -> &mut App {
             app.add_plugins((FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin::default()));
         }
         
+        if headless {
+            app.add_plugins(TrackPhysicsPlugin);
+        }
+        #[cfg(feature = "graphics")]
+        if !headless {
+            app.add_plugins(TrackPlugin)
+                .add_systems(Update,close_on_esc_ex);
+        }
         app
-        .insert_resource(Config::default())
-        .add_plugins((
-            TrackPlugin,
-        ))
+        .insert_resource(Config {
+            cars_count: launch.cars_count,
+            archetype: launch.net.profile.archetype,
+        })
+        .init_resource::<RaceSession>()
+        .init_resource::<RaceConfig>()
        .add_event::<SpawnCarOnTrackEvent>()
+       .configure_sets(PhysicsStep, (CarSet::Input, CarSet::Esp).before(PhysicsSet::SyncBackend))
        .add_systems(
-            Update,
+            PhysicsStep,
             (
+                do_input_from_state.in_set(CarSet::Input),
                 aero_system.in_set(CarSet::Input),
+                race_hold_cars_system.after(CarSet::Input).before(CarSet::Esp),
                 esp_system.in_set(CarSet::Esp).after(esp_run_after),
             ).run_if(in_state(GameState::Playing)),
         );
@@ -155,6 +366,5 @@ pub fn car_app(app: &mut App,network_mode : NetworkMode) -> &mut App {
     {
         app.add_plugins(bevy_garage_nn::NeuralNetworkPlugin);
     }
-    app.add_systems(Update,close_on_esc_ex);
-    app
+    Ok(app.insert_resource(launch))
 }
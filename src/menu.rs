@@ -1,15 +1,18 @@
 use crate::GameState;
+use crate::renet::discovery::DiscoveredServers;
+use crate::renet::reconnect::JoinServer;
 use bevy::prelude::*;
+use std::net::SocketAddr;
 
 pub struct MenuPlugin;
 
-/// This plugin is responsible for the game menu (containing only one button...)
+/// This plugin is responsible for the game menu (a play button and the servers found on the LAN)
 /// The menu is only drawn during the State `GameState::Menu` and is removed when that state is exited
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<ButtonColors>()
         .add_systems(OnEnter(GameState::Menu), setup_menu)
-        .add_systems(Update, click_play_button.run_if(in_state(GameState::Menu)))
+        .add_systems(Update, (click_play_button, update_server_list).run_if(in_state(GameState::Menu)))
         .add_systems(OnExit(GameState::Menu), cleanup_menu);
     }
 }
@@ -29,44 +32,151 @@ impl Default for ButtonColors {
     }
 }
 
+#[derive(Component)]
+struct MenuRoot;
+
+#[derive(Component)]
+struct ServerList;
+
+/// Button joining a discovered server
+#[derive(Component)]
+struct ServerEntry(SocketAddr);
+
 fn setup_menu(mut commands: Commands, button_colors: Res<ButtonColors>) {
     commands.spawn(Camera2dBundle::default());
     commands
-        .spawn(ButtonBundle {
-            style: Style {
-                width: Val::Px(120.0),
-                height: Val::Px(50.0),
-                margin: UiRect::all(Val::Auto),
-                justify_content: JustifyContent::Center,
-                align_items: AlignItems::Center,
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(10.0),
+                    ..default()
+                },
                 ..default()
             },
-            background_color: button_colors.normal.into(),
-            ..Default::default()
-        })
+            MenuRoot,
+        ))
         .with_children(|parent| {
-            parent.spawn(TextBundle::from_section(
-                "Play",
-                TextStyle {
-                    font_size: 40.0,
-                    color: Color::rgb(0.9, 0.9, 0.9),
+            parent
+                .spawn(ButtonBundle {
+                    style: Style {
+                        width: Val::Px(120.0),
+                        height: Val::Px(50.0),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: button_colors.normal.into(),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    parent.spawn(TextBundle::from_section(
+                        "Play",
+                        TextStyle {
+                            font_size: 40.0,
+                            color: Color::rgb(0.9, 0.9, 0.9),
+                            ..default()
+                        },
+                    ));
+                });
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        row_gap: Val::Px(6.0),
+                        ..default()
+                    },
                     ..default()
                 },
+                ServerList,
             ));
         });
 }
 
+/// Rebuild the list whenever a server shows up, changes or goes away
+fn update_server_list(
+    mut commands: Commands,
+    button_colors: Res<ButtonColors>,
+    discovered: Option<Res<DiscoveredServers>>,
+    lists: Query<Entity, With<ServerList>>,
+) {
+    let Some(discovered) = discovered else {
+        return;
+    };
+    let Ok(list) = lists.get_single() else {
+        return;
+    };
+    if !discovered.is_changed() {
+        return;
+    }
+    commands.entity(list).despawn_descendants();
+    commands.entity(list).with_children(|parent| {
+        let title = if discovered.servers.is_empty() { "Looking for LAN servers..." } else { "LAN servers" };
+        parent.spawn(TextBundle::from_section(
+            title,
+            TextStyle {
+                font_size: 24.0,
+                color: Color::rgb(0.7, 0.7, 0.7),
+                ..default()
+            },
+        ));
+        for server in discovered.servers.iter() {
+            let info = &server.info;
+            let mut label = format!("{}  {}  {}/{}  {}", info.name, info.track, info.players, info.max_players, server.addr);
+            if !info.compatible() {
+                label += "  (other version)";
+            }
+            let text = TextBundle::from_section(
+                label,
+                TextStyle {
+                    font_size: 24.0,
+                    color: if info.compatible() { Color::rgb(0.9, 0.9, 0.9) } else { Color::rgb(0.5, 0.5, 0.5) },
+                    ..default()
+                },
+            );
+            if !info.compatible() {
+                parent.spawn(text);
+                continue;
+            }
+            parent
+                .spawn((
+                    ButtonBundle {
+                        style: Style {
+                            padding: UiRect::axes(Val::Px(12.0), Val::Px(6.0)),
+                            ..default()
+                        },
+                        background_color: button_colors.normal.into(),
+                        ..default()
+                    },
+                    ServerEntry(server.addr),
+                ))
+                .with_children(|parent| {
+                    parent.spawn(text);
+                });
+        }
+    });
+}
+
 fn click_play_button(
     button_colors: Res<ButtonColors>,
     mut state: ResMut<NextState<GameState>>,
+    mut join_events: Option<ResMut<Events<JoinServer>>>,
     mut interaction_query: Query<
-        (&Interaction, &mut BackgroundColor),
+        (&Interaction, &mut BackgroundColor, Option<&ServerEntry>),
         (Changed<Interaction>, With<Button>),
     >,
 ) {
-    for (interaction, mut color) in &mut interaction_query {
+    for (interaction, mut color, server) in &mut interaction_query {
         match *interaction {
             Interaction::Pressed => {
+                if let (Some(ServerEntry(addr)), Some(join_events)) = (server, join_events.as_mut()) {
+                    join_events.send(JoinServer(*addr));
+                }
                 state.set(GameState::Playing);
             }
             Interaction::Hovered => {
@@ -80,9 +190,9 @@ fn click_play_button(
 }
 
 fn cleanup_menu(mut commands: Commands, 
-    button: Query<Entity, With<Button>>,
+    root: Query<Entity, With<MenuRoot>>,
     camera : Query<Entity,With<Camera2d>>) {
     
-    commands.entity(button.single()).despawn_recursive();
+    commands.entity(root.single()).despawn_recursive();
     commands.entity(camera.single()).despawn_recursive();
 }
@@ -1,34 +1,83 @@
 
-use crate::GameState;
+use crate::{GameState, TickRate};
 pub mod physics_settings;
 use bevy::app::*;
 use bevy::ecs::schedule::*;
+use bevy::ecs::world::World;
+use bevy::prelude::{not, resource_exists, Resource};
+use bevy::time::FixedTime;
 use bevy_rapier3d::prelude::*;
 use physics_settings::{rapier_config_start_system,PhysicsParams};
+use std::time::Duration;
 pub struct PhysicPlugin(pub PhysicsParams);
 
+/*
+One tick of the car simulation : the car systems then one rapier step of a tick long.
+FixedUpdate runs it once per tick, the client runs it again to replay the ticks the server
+has not acknowledged yet.
+*/
+#[derive(ScheduleLabel, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PhysicsStep;
+
+/*
+Leaves the simulation to the server : PhysicsStep never runs and the own car of a client follows
+its snapshots without prediction. For the load test bots, which share one process.
+*/
+#[derive(Debug, Default, Resource)]
+pub struct NoLocalPhysics;
+
+pub fn run_physics_step(world: &mut World) {
+    world.run_schedule(PhysicsStep);
+}
+
 impl Plugin for PhysicPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
-        .add_plugins(RapierDebugRenderPlugin {
-            enabled: false,
-            style: DebugRenderStyle {
-                rigid_body_axes_length: 0.5,
-                ..DebugRenderStyle::default()
-            },
-            mode: DebugRenderMode::COLLIDER_SHAPES
-                | DebugRenderMode::RIGID_BODY_AXES
-                | DebugRenderMode::JOINTS
-                | DebugRenderMode::CONTACTS
-                | DebugRenderMode::SOLVER_CONTACTS,
-            ..RapierDebugRenderPlugin::default()
-        })
-        .add_systems(OnEnter(GameState::Playing),rapier_config_start_system)
+        let tick_rate = app.world.get_resource::<TickRate>().copied().unwrap_or_default();
+        app.add_plugins(RapierPhysicsPlugin::<NoUserData>::default().with_default_system_setup(false));
+        #[cfg(feature = "graphics")]
+        if crate::has_renderer(app) {
+            app.add_plugins(RapierDebugRenderPlugin {
+                enabled: false,
+                style: DebugRenderStyle {
+                    rigid_body_axes_length: 0.5,
+                    ..DebugRenderStyle::default()
+                },
+                mode: DebugRenderMode::COLLIDER_SHAPES
+                    | DebugRenderMode::RIGID_BODY_AXES
+                    | DebugRenderMode::JOINTS
+                    | DebugRenderMode::CONTACTS
+                    | DebugRenderMode::SOLVER_CONTACTS,
+                ..RapierDebugRenderPlugin::default()
+            });
+        }
+        // The whole game steps physics a tick at a time, server and client then simulate the same steps.
+        app.configure_sets(
+            PhysicsStep,
+            (
+                PhysicsSet::SyncBackend,
+                PhysicsSet::SyncBackendFlush,
+                PhysicsSet::StepSimulation,
+                PhysicsSet::Writeback,
+            )
+                .chain(),
+        )
+        .add_systems(
+            PhysicsStep,
+            (
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::SyncBackend).in_set(PhysicsSet::SyncBackend),
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::SyncBackendFlush).in_set(PhysicsSet::SyncBackendFlush),
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::StepSimulation).in_set(PhysicsSet::StepSimulation),
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::Writeback).in_set(PhysicsSet::Writeback),
+            ),
+        )
+        .add_systems(FixedUpdate, run_physics_step.run_if(not(resource_exists::<NoLocalPhysics>())))
+        .insert_resource(FixedTime::new(Duration::from_secs_f64(1. / tick_rate.0)));
+
+        app.add_systems(OnEnter(GameState::Playing),rapier_config_start_system)
         .insert_resource(self.0)
         .insert_resource(RapierConfiguration {
-            timestep_mode: TimestepMode::Variable {
-                max_dt: 1. / 60.,
-                time_scale: 1.,
+            timestep_mode: TimestepMode::Fixed {
+                dt: (1. / tick_rate.0) as f32,
                 substeps: self.0.substeps,
             },
             ..RapierConfiguration::default()
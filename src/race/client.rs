@@ -0,0 +1,218 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_renet::renet::transport::NetcodeClientTransport;
+
+use crate::car::PlayerCarCommand;
+#[cfg(feature = "graphics")]
+use crate::game_asset::GameAssets;
+use crate::race::{RaceMessage, RacePhase, RaceSession, RaceStanding};
+use crate::renet::client::{ClientIdentity, ClientLobby};
+use crate::renet::clock::ServerClock;
+use crate::renet::server::HOST_CLIENT_ID;
+#[cfg(feature = "graphics")]
+use crate::renet::server::ServerLobby;
+use crate::track::{CarTrack, ReplicatedTrack};
+
+#[cfg(feature = "graphics")]
+#[derive(Component)]
+pub struct RaceStatusText;
+
+/*Who plays here and what everybody is called, on a client or on the host.*/
+#[derive(SystemParam)]
+pub struct LocalRacePlayers<'w> {
+    transport: Option<Res<'w, NetcodeClientTransport>>,
+    identity: Option<Res<'w, ClientIdentity>>,
+    #[cfg(feature = "graphics")]
+    client_lobby: Option<Res<'w, ClientLobby>>,
+    #[cfg(feature = "graphics")]
+    server_lobby: Option<Res<'w, ServerLobby>>,
+}
+
+impl LocalRacePlayers<'_> {
+    pub fn local_id(&self) -> u64 {
+        self.transport.as_ref().map_or(HOST_CLIENT_ID, |transport| transport.client_id())
+    }
+
+    pub fn spectating(&self) -> bool {
+        self.identity.as_ref().map_or(false, |identity| identity.profile.spectator)
+    }
+
+    #[cfg(feature = "graphics")]
+    pub fn name(&self, id: u64) -> String {
+        let client_name = self.client_lobby.as_ref().and_then(|lobby| lobby.players.get(&id)).map(|player_info| &player_info.profile.name);
+        let server_name = self.server_lobby.as_ref().and_then(|lobby| lobby.profiles.get(&id)).map(|profile| &profile.name);
+        client_name.or(server_name).map_or(id.to_string(), |name| name.clone())
+    }
+}
+
+/*Mirror the server session, phase times are rebased on our own clock through ServerClock.*/
+pub fn client_race_session_system(
+    mut cmd: Commands,
+    time: Res<Time>,
+    clock: Res<ServerClock>,
+    // server time the current phase started at, kept until the clock can map it
+    mut server_phase_started: Local<Option<f64>>,
+    lobby: Res<ClientLobby>,
+    mut session: ResMut<RaceSession>,
+    mut race_messages: EventReader<RaceMessage>,
+    mut car_tracks: Query<&mut CarTrack>,
+) {
+    for message in race_messages.iter() {
+        match message {
+            RaceMessage::Phase { phase, started, duration, grid, laps } => {
+                match phase {
+                    RacePhase::Lobby => session.ready.clear(),
+                    RacePhase::Countdown => session.standings.clear(),
+                    _ => {}
+                }
+                session.phase = *phase;
+                session.phase_started = time.elapsed_seconds_f64();
+                *server_phase_started = Some(*started);
+                session.phase_duration = *duration;
+                session.grid = grid.clone();
+                session.laps = *laps;
+            }
+            RaceMessage::Ready { id, ready } => {
+                if *ready {
+                    session.ready.insert(*id);
+                } else {
+                    session.ready.remove(id);
+                }
+            }
+            RaceMessage::Standings(standings) => {
+                for standing in standings.iter() {
+                    if let Some(player_info) = lobby.players.get(&standing.id) {
+                        apply_standing(&mut cmd, &mut car_tracks, player_info.client_entity, standing);
+                    }
+                }
+                session.standings = standings.clone();
+            }
+        }
+    }
+    // countdown and race time then run out when they do on the server, not half a round trip later
+    if let (Some(started), true) = (*server_phase_started, clock.is_synced()) {
+        session.phase_started = clock.local_time(started);
+    }
+}
+
+/*The dash reads CarTrack, keep it in line with what the server computed.*/
+fn apply_standing(cmd: &mut Commands, car_tracks: &mut Query<&mut CarTrack>, entity: Entity, standing: &RaceStanding) {
+    match car_tracks.get_mut(entity) {
+        Ok(mut car_track) => {
+            car_track.lap = standing.lap;
+            car_track.place = standing.place;
+            car_track.track_position = standing.track_position;
+            car_track.ride_distance = standing.ride_distance;
+        }
+        Err(_) => {
+            cmd.entity(entity).insert((
+                CarTrack {
+                    lap: standing.lap,
+                    place: standing.place,
+                    track_position: standing.track_position,
+                    ride_distance: standing.ride_distance,
+                    ..default()
+                },
+                ReplicatedTrack,
+            ));
+        }
+    }
+}
+
+/*R toggles ready while in the lobby.*/
+pub fn client_ready_input_system(
+    input: Res<Input<KeyCode>>,
+    session: Res<RaceSession>,
+    players: LocalRacePlayers,
+    mut commands: EventWriter<PlayerCarCommand>,
+) {
+    if session.phase != RacePhase::Lobby || players.spectating() || !input.just_pressed(KeyCode::R) {
+        return;
+    }
+    let ready = !session.ready.contains(&players.local_id());
+    commands.send(PlayerCarCommand::Ready(ready));
+}
+
+#[cfg(feature = "graphics")]
+pub fn client_race_status_ui_system(
+    mut cmd: Commands,
+    time: Res<Time>,
+    session: Res<RaceSession>,
+    game_asset: Res<GameAssets>,
+    players: LocalRacePlayers,
+    mut texts: Query<&mut Text, With<RaceStatusText>>,
+) {
+    let elapsed = session.elapsed(time.elapsed_seconds_f64());
+    let message = match session.phase {
+        RacePhase::Lobby => {
+            let hint = if players.spectating() {
+                "spectating, Tab to switch cars"
+            } else if session.ready.contains(&players.local_id()) {
+                "ready"
+            } else {
+                "press R when ready"
+            };
+            format!("Lobby: {} ready, {}", session.ready.len(), hint)
+        }
+        RacePhase::Countdown => format!("{}", (session.phase_duration - elapsed).ceil().max(1.)),
+        RacePhase::Racing => {
+            let mut message = format_race_time(elapsed);
+            if let Some(standing) = session.standing(players.local_id()) {
+                message += &format!("  P{}/{}  lap {}/{}", standing.place + 1, session.standings.len(), standing.lap.clamp(1, session.laps), session.laps);
+                if let Some(last_lap) = standing.last_lap {
+                    message += &format!("  last {}", format_race_time(last_lap));
+                }
+                if let Some(best_lap) = standing.best_lap {
+                    message += &format!("  best {}", format_race_time(best_lap));
+                }
+                if let Some(finish_time) = standing.finish_time {
+                    message += &format!("  finished {}", format_race_time(finish_time));
+                }
+            }
+            message
+        }
+        RacePhase::Finished => {
+            let mut message = "Finished".to_string();
+            for standing in session.standings.iter() {
+                let time = standing.finish_time.map_or("DNF".to_string(), format_race_time);
+                message += &format!("\n{}. {} {}", standing.place + 1, players.name(standing.id), time);
+            }
+            message
+        }
+    };
+
+    if let Ok(mut text) = texts.get_single_mut() {
+        if text.sections[0].value != message {
+            text.sections[0].value = message;
+        }
+        return;
+    }
+    cmd.spawn(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.),
+            width: Val::Percent(100.),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        text: Text {
+            alignment: TextAlignment::Center,
+            sections: vec![TextSection {
+                value: message,
+                style: TextStyle {
+                    font: game_asset.dash_font.clone(),
+                    font_size: 28.0,
+                    color: Color::WHITE,
+                },
+            }],
+            ..default()
+        },
+        ..default()
+    })
+    .insert(RaceStatusText);
+}
+
+#[cfg(feature = "graphics")]
+fn format_race_time(seconds: f32) -> String {
+    format!("{:02}:{:04.1}", (seconds / 60.).floor(), seconds % 60.)
+}
@@ -0,0 +1,142 @@
+mod server;
+mod client;
+
+pub use server::*;
+pub use client::*;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use crate::car::Car;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RacePhase {
+    /*Free driving while players get ready.*/
+    #[default]
+    Lobby,
+    /*Cars are on the grid and held until the start.*/
+    Countdown,
+    Racing,
+    /*Results are shown, then back to the lobby.*/
+    Finished,
+}
+
+/*Race session updates, sent by the server on the reliable ServerMessages channel.*/
+#[derive(Debug, Clone, Serialize, Deserialize, Event)]
+pub enum RaceMessage {
+    /*duration is how long the phase lasts in seconds, 0 when it has no fixed length. started is the server time it began.*/
+    Phase {
+        phase: RacePhase,
+        started: f64,
+        duration: f32,
+        grid: Vec<u64>,
+        laps: i32,
+    },
+    Ready {
+        id: u64,
+        ready: bool,
+    },
+    /*Everybody's progress, best placed first.*/
+    Standings(Vec<RaceStanding>),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RaceStanding {
+    pub id: u64,
+    /*0 on the grid, 1 once the start line is crossed.*/
+    pub lap: i32,
+    pub place: usize,
+    pub track_position: f32,
+    pub ride_distance: f32,
+    pub last_lap: Option<f32>,
+    pub best_lap: Option<f32>,
+    /*Total race time once the last lap is done.*/
+    pub finish_time: Option<f32>,
+}
+
+/*Server side lap timing of a networked car.*/
+#[derive(Component, Debug, Default)]
+pub struct RaceProgress {
+    pub lap: i32,
+    /*App time (seconds) the current lap started at.*/
+    pub lap_started: f64,
+    pub last_lap: Option<f32>,
+    pub best_lap: Option<f32>,
+    pub finish_time: Option<f32>,
+}
+
+#[derive(Resource)]
+pub struct RaceConfig {
+    /*Connected players needed to start, players waiting to resume do not count.*/
+    pub min_players: usize,
+    pub countdown_seconds: f32,
+    pub max_race_seconds: f32,
+    pub finished_seconds: f32,
+    /*Distance between two grid rows, in meters.*/
+    pub grid_spacing: f32,
+    pub laps: i32,
+    /*Seconds between two Standings broadcasts.*/
+    pub standings_interval: f32,
+}
+
+impl Default for RaceConfig {
+    fn default() -> Self {
+        Self {
+            min_players: 1,
+            countdown_seconds: 3.,
+            max_race_seconds: 600.,
+            finished_seconds: 10.,
+            grid_spacing: 8.,
+            laps: 3,
+            standings_interval: 0.25,
+        }
+    }
+}
+
+/*Authoritative on the server, mirrored from RaceMessage on clients.*/
+#[derive(Resource, Default)]
+pub struct RaceSession {
+    pub phase: RacePhase,
+    /*App time (seconds) the current phase started at.*/
+    pub phase_started: f64,
+    pub phase_duration: f32,
+    pub ready: HashSet<u64>,
+    /*Client ids in start order.*/
+    pub grid: Vec<u64>,
+    /*Client ids in the order they took the flag.*/
+    pub finish_order: Vec<u64>,
+    /*RaceConfig::laps when the countdown started, the race finishes on it.*/
+    pub laps: i32,
+    pub standings: Vec<RaceStanding>,
+    pub standings_sent: f64,
+}
+
+impl RaceSession {
+    pub fn elapsed(&self, now: f64) -> f32 {
+        (now - self.phase_started) as f32
+    }
+    pub fn standing(&self, id: u64) -> Option<&RaceStanding> {
+        self.standings.iter().find(|standing| standing.id == id)
+    }
+    pub fn phase_message(&self) -> RaceMessage {
+        RaceMessage::Phase {
+            phase: self.phase,
+            started: self.phase_started,
+            duration: self.phase_duration,
+            grid: self.grid.clone(),
+            laps: self.laps,
+        }
+    }
+}
+
+/*Cars can not move during the countdown, whatever the input says.*/
+pub fn race_hold_cars_system(session: Res<RaceSession>, mut cars: Query<&mut Car>) {
+    if session.phase != RacePhase::Countdown {
+        return;
+    }
+    for mut car in cars.iter_mut() {
+        car.gas = 0.;
+        car.brake = 1.;
+        car.steering = 0.;
+    }
+}
@@ -0,0 +1,267 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Velocity;
+use bevy_renet::renet::RenetServer;
+use std::f32::consts::PI;
+
+use crate::car::{Car, CarSpec, CarWheels, PlayerCarCommand, PlayerCarCommandEvent, Wheel};
+use crate::race::{RaceConfig, RaceMessage, RacePhase, RaceProgress, RaceSession, RaceStanding};
+use crate::renet::NetPlayer;
+use crate::renet::error::encode;
+use crate::renet::server::{PlayerJoined, PlayerLeft, ServerChannel, ServerLobby, ServerMessages};
+use crate::track::{CarTrack, TrackConfig};
+
+fn race_message(message: RaceMessage) -> Option<Vec<u8>> {
+    match encode(&ServerMessages::Race(message)) {
+        Ok(bytes) => Some(bytes),
+        Err(e) => {
+            error!("{}", e);
+            None
+        }
+    }
+}
+
+fn broadcast_race_message(server: &mut RenetServer, message: RaceMessage) {
+    if let Some(bytes) = race_message(message) {
+        server.broadcast_message(ServerChannel::ServerMessages, bytes);
+    }
+}
+
+fn enter_phase(session: &mut RaceSession, server: &mut RenetServer, phase: RacePhase, duration: f32, now: f64) {
+    info!("Race phase {:?}", phase);
+    session.phase = phase;
+    session.phase_started = now;
+    session.phase_duration = duration;
+    broadcast_race_message(server, session.phase_message());
+}
+
+/*Drop whatever race is going on and go back to the lobby, nobody ready.*/
+pub fn restart_race(session: &mut RaceSession, server: &mut RenetServer, now: f64) {
+    session.ready.clear();
+    session.grid.clear();
+    session.finish_order.clear();
+    session.standings.clear();
+    enter_phase(session, server, RacePhase::Lobby, 0., now);
+}
+
+/*Grid slot i : two cars per row, rows going back from the start line. Also returns how far behind the line it is.*/
+fn grid_transform(track_config: &TrackConfig, config: &RaceConfig, slot: usize) -> (Transform, f32) {
+    let row = (slot / 2 + 1) as f32;
+    let side = if slot % 2 == 0 { -1. } else { 1. };
+    let behind = row * config.grid_spacing;
+    let (translation, rotation) = track_config.get_transform_by_meter(track_config.track_length - behind);
+    let transform = Transform::from_translation(translation + rotation.mul_vec3(Vec3::X) * side * 2.5)
+        .with_rotation(rotation);
+    (transform, behind)
+}
+
+/*Put a car (and its jointed wheels) on the grid, at rest.*/
+fn place_car(
+    transform: Transform,
+    car: &mut Car,
+    spec: &CarSpec,
+    car_wheels: &CarWheels,
+    body: (&mut Transform, &mut Velocity),
+    wheels: &mut Query<(&mut Transform, &mut Velocity), (With<Wheel>, Without<Car>)>,
+) {
+    let (body_transform, body_velocity) = body;
+    body_transform.translation = transform.translation;
+    body_transform.rotation = transform.rotation;
+    *body_velocity = Velocity::zero();
+    car.spawn_transform = transform;
+    car.prev_steering = 0.;
+    car.prev_torque = 0.;
+    car.prev_dir = 0.;
+
+    for (i, wheel_entity) in car_wheels.entities.iter().enumerate() {
+        if let Ok((mut wheel_transform, mut wheel_velocity)) = wheels.get_mut(*wheel_entity) {
+            wheel_transform.translation =
+                transform.translation + transform.rotation.mul_vec3(spec.wheel_mount[i].anchor);
+            wheel_transform.rotation = transform.rotation * Quat::from_axis_angle(Vec3::Y, PI);
+            *wheel_velocity = Velocity::zero();
+        }
+    }
+}
+
+/*
+Lobby -> Countdown once everybody connected is ready, Countdown -> Racing when the countdown
+runs out, Racing -> Finished on the time limit, Finished -> Lobby after the results.
+*/
+pub fn server_race_session_system(
+    time: Res<Time>,
+    config: Res<RaceConfig>,
+    track_config: Res<TrackConfig>,
+    lobby: Res<ServerLobby>,
+    mut session: ResMut<RaceSession>,
+    mut server: ResMut<RenetServer>,
+    mut players_joined: EventReader<PlayerJoined>,
+    mut players_left: EventReader<PlayerLeft>,
+    mut command_events: EventReader<PlayerCarCommandEvent>,
+    mut cars: Query<(&NetPlayer, &mut Car, &CarSpec, &CarWheels, &mut Transform, &mut Velocity, &mut CarTrack, &mut RaceProgress), Without<Wheel>>,
+    mut wheels: Query<(&mut Transform, &mut Velocity), (With<Wheel>, Without<Car>)>,
+) {
+    let now = time.elapsed_seconds_f64();
+
+    // Late joiners get the current phase and who is ready, once through the handshake.
+    for PlayerJoined { client_id } in players_joined.iter() {
+        if let Some(bytes) = race_message(session.phase_message()) {
+            server.send_message(*client_id, ServerChannel::ServerMessages, bytes);
+        }
+        for id in session.ready.iter() {
+            if let Some(bytes) = race_message(RaceMessage::Ready { id: *id, ready: true }) {
+                server.send_message(*client_id, ServerChannel::ServerMessages, bytes);
+            }
+        }
+        if let Some(bytes) = race_message(RaceMessage::Standings(session.standings.clone())) {
+            server.send_message(*client_id, ServerChannel::ServerMessages, bytes);
+        }
+    }
+    for PlayerLeft { client_id } in players_left.iter() {
+        session.ready.remove(client_id);
+        session.grid.retain(|id| id != client_id);
+    }
+
+    for event in command_events.iter() {
+        let PlayerCarCommand::Ready(ready) = event.command else {
+            continue;
+        };
+        // Spectators have no car to race with.
+        if session.phase != RacePhase::Lobby || !lobby.players.contains_key(&event.client_id) {
+            continue;
+        }
+        let changed = if ready {
+            session.ready.insert(event.client_id)
+        } else {
+            session.ready.remove(&event.client_id)
+        };
+        if changed {
+            broadcast_race_message(&mut server, RaceMessage::Ready { id: event.client_id, ready });
+        }
+    }
+
+    let elapsed = session.elapsed(now);
+    match session.phase {
+        RacePhase::Lobby => {
+            // a player who dropped out does not hold the start back, nor counts to start without anybody
+            let active = lobby.players.keys().filter(|id| !lobby.suspended.contains_key(id)).count();
+            let all_ready = active >= config.min_players.max(1)
+                && lobby.players.keys().all(|id| session.ready.contains(id) || lobby.suspended.contains_key(id));
+            if all_ready {
+                let mut grid: Vec<u64> = lobby.players.keys().copied().collect();
+                fastrand::shuffle(&mut grid);
+                for (player, mut car, spec, car_wheels, mut transform, mut velocity, mut car_track, mut progress) in cars.iter_mut() {
+                    if let Some(slot) = grid.iter().position(|id| *id == player.id) {
+                        let (grid_slot, behind) = grid_transform(&track_config, &config, slot);
+                        place_car(grid_slot, &mut car, spec, car_wheels, (&mut transform, &mut velocity), &mut wheels);
+                        // Behind the line on lap 0, crossing it starts lap 1.
+                        car_track.start_shift = 0.;
+                        car_track.lap = 0;
+                        car_track.track_position = track_config.track_length - behind;
+                        car_track.ride_distance = -behind;
+                        *progress = RaceProgress::default();
+                    }
+                }
+                session.grid = grid;
+                session.finish_order.clear();
+                session.standings.clear();
+                session.laps = config.laps;
+                enter_phase(&mut session, &mut server, RacePhase::Countdown, config.countdown_seconds, now);
+            }
+        }
+        RacePhase::Countdown => {
+            if elapsed >= config.countdown_seconds {
+                for (.., mut progress) in cars.iter_mut() {
+                    progress.lap_started = now;
+                }
+                enter_phase(&mut session, &mut server, RacePhase::Racing, config.max_race_seconds, now);
+            }
+        }
+        RacePhase::Racing => {
+            let everybody_finished = !session.grid.is_empty()
+                && session.grid.iter().all(|id| session.finish_order.contains(id));
+            if elapsed >= config.max_race_seconds || lobby.players.is_empty() || everybody_finished {
+                enter_phase(&mut session, &mut server, RacePhase::Finished, config.finished_seconds, now);
+            }
+        }
+        RacePhase::Finished => {
+            if elapsed >= config.finished_seconds {
+                session.ready.clear();
+                session.grid.clear();
+                enter_phase(&mut session, &mut server, RacePhase::Lobby, 0., now);
+            }
+        }
+    }
+}
+
+/*
+Time laps from the CarTrack progress_system keeps up to date, rank the cars and send the
+standings to everybody a few times per second.
+*/
+pub fn server_race_progress_system(
+    time: Res<Time>,
+    config: Res<RaceConfig>,
+    mut session: ResMut<RaceSession>,
+    mut server: ResMut<RenetServer>,
+    mut cars: Query<(&NetPlayer, &mut CarTrack, &mut RaceProgress)>,
+) {
+    if session.phase != RacePhase::Racing {
+        return;
+    }
+    let now = time.elapsed_seconds_f64();
+    let race_time = session.elapsed(now);
+
+    for (player, car_track, mut progress) in cars.iter_mut() {
+        if progress.finish_time.is_some() || car_track.lap == progress.lap {
+            continue;
+        }
+        // Lap 0 -> 1 is leaving the grid, every later increment completes a lap.
+        if car_track.lap > progress.lap && progress.lap >= 1 {
+            let lap_time = (now - progress.lap_started) as f32;
+            progress.last_lap = Some(lap_time);
+            progress.best_lap = Some(progress.best_lap.map_or(lap_time, |best| best.min(lap_time)));
+            progress.lap_started = now;
+            // laps of this race, an admin may have changed the config for the next one
+            if progress.lap >= session.laps {
+                info!("Player {} finished in {:.2}s", player.id, race_time);
+                progress.finish_time = Some(race_time);
+                session.finish_order.push(player.id);
+            }
+        }
+        progress.lap = car_track.lap;
+    }
+
+    let mut standings: Vec<RaceStanding> = cars
+        .iter()
+        .filter(|(player, ..)| session.grid.contains(&player.id))
+        .map(|(player, car_track, progress)| RaceStanding {
+            id: player.id,
+            lap: progress.lap,
+            place: 0,
+            track_position: car_track.track_position,
+            ride_distance: car_track.ride_distance,
+            last_lap: progress.last_lap,
+            best_lap: progress.best_lap,
+            finish_time: progress.finish_time,
+        })
+        .collect();
+    let finish_place = |id: u64| session.finish_order.iter().position(|finished| *finished == id).unwrap_or(usize::MAX);
+    standings.sort_by(|a, b| {
+        finish_place(a.id)
+            .cmp(&finish_place(b.id))
+            .then(b.lap.cmp(&a.lap))
+            .then(b.ride_distance.total_cmp(&a.ride_distance))
+    });
+    for (place, standing) in standings.iter_mut().enumerate() {
+        standing.place = place;
+    }
+    for (player, mut car_track, _) in cars.iter_mut() {
+        if let Some(standing) = standings.iter().find(|standing| standing.id == player.id) {
+            car_track.place = standing.place;
+        }
+    }
+
+    session.standings = standings;
+    if now - session.standings_sent >= config.standings_interval as f64 {
+        session.standings_sent = now;
+        broadcast_race_message(&mut server, RaceMessage::Standings(session.standings.clone()));
+    }
+}
@@ -1,18 +1,34 @@
 
 use bevy::prelude::*;
-use bevy_renet::renet::{ChannelConfig, SendType,transport::NetcodeTransportError};
+use bevy_renet::renet::{ChannelConfig, SendType};
 use serde::Serialize;
 use std::marker::PhantomData;
 use std::time::Duration;
+#[cfg(feature = "graphics")]
 use bevy_egui::{EguiPlugin,EguiContexts};
 use crate::game_asset::GameAssets;
-use crate::renet::PlayerSpawner;
+use crate::renet::{NetConfig,PlayerSpawner};
 use crate::renet::server::{ServerChannel,ServerMessages};
+use crate::renet::interpolation::*;
+use crate::renet::clock::{client_clock_sync_system, ClockSyncConfig, ServerClock};
+use crate::renet::handshake::{client_handshake_system, handshake_accepted, ServerHandshake};
+use crate::renet::reconnect::*;
+use crate::renet::discovery::*;
+use crate::renet::conditioner::{loopback, LinkConditioner};
+use crate::renet::error::{decode,encode};
 use crate::gamestate::*;
+use crate::race::RaceMessage;
+use crate::chat::ChatMessage;
+use crate::renet::profile::PlayerProfile;
 
+#[derive(Debug, Clone, Copy)]
 pub enum ClientChannel {
     Input,
     Command,
+    /*Clock sync pings, a lost one is simply not answered.*/
+    Clock,
+    /*The ClientHello sent once connected.*/
+    Handshake,
 }
 
 impl From<ClientChannel> for u8 {
@@ -20,6 +36,8 @@ impl From<ClientChannel> for u8 {
         match channel_id {
             ClientChannel::Command => 0,
             ClientChannel::Input => 1,
+            ClientChannel::Clock => 2,
+            ClientChannel::Handshake => 3,
         }
     }
 }
@@ -40,27 +58,47 @@ impl ClientChannel {
                     resend_time: Duration::ZERO,
                 },
             },
+            ChannelConfig {
+                channel_id: Self::Clock.into(),
+                max_memory_usage_bytes: 1024 * 1024,
+                send_type: SendType::Unreliable,
+            },
+            ChannelConfig {
+                channel_id: Self::Handshake.into(),
+                max_memory_usage_bytes: 1024 * 1024,
+                send_type: SendType::ReliableOrdered {
+                    resend_time: Duration::ZERO,
+                },
+            },
         ]
     }
 }
 
-use crate::renet::{connection_config, PROTOCOL_ID};
+use crate::renet::{connection_config, unsecure_requested, PROTOCOL_ID};
+use crate::renet::token::{connect_token_from_env, issue_connect_token};
+use crate::renet::error::NetworkError;
+use crate::renet::guard::{INPUT_BURST, MAX_INPUTS_PER_SECOND};
 
 use bevy_renet::{
     renet::{
-        transport::{ClientAuthentication, NetcodeClientTransport},
+        transport::{ClientAuthentication, NetcodeClientTransport, NETCODE_KEY_BYTES},
         RenetClient,
     },
     transport::NetcodeClientPlugin,
     RenetClientPlugin,
 };
+#[cfg(feature = "graphics")]
 use renet_visualizer::{RenetClientVisualizer, RenetVisualizerStyle};
-use std::{collections::HashMap, net::UdpSocket, time::SystemTime};
+use std::{collections::HashMap, net::{SocketAddr, UdpSocket}, time::SystemTime};
 use crate::input::controller::*;
 
 #[derive(Component)]
 pub struct ControlledPlayer;
 
+/*Sends the input of a tick in FixedUpdate, the game simulates that tick after it.*/
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemSet)]
+pub struct ClientInputSet;
+
 #[derive(Default, Resource)]
 pub struct NetworkMapping(pub HashMap<Entity, Entity>);
 
@@ -68,6 +106,19 @@ pub struct NetworkMapping(pub HashMap<Entity, Entity>);
 pub struct PlayerInfo {
     pub client_entity: Entity,
     pub server_entity: Entity,
+    /*Set while reconnecting, until the server announces this player again.*/
+    pub stale: bool,
+    pub profile: PlayerProfile,
+}
+
+/*Client id used for unsecure connections, kept across reconnects so the server sees the same player.*/
+#[derive(Debug, Resource)]
+pub struct ClientIdentity {
+    pub client_id: u64,
+    /*Sent as user data on unsecure connections, with a token the server reads it from the token.*/
+    pub profile: PlayerProfile,
+    /*The server key, to sign our own connect tokens instead of reading one from the environment, like the load test bots do.*/
+    pub private_key: Option<[u8; NETCODE_KEY_BYTES]>,
 }
 
 #[derive(Debug, Default, Resource)]
@@ -75,40 +126,87 @@ pub struct ClientLobby {
     pub players: HashMap<u64, PlayerInfo>,
 }
 
-fn new_renet_client() -> (RenetClient, NetcodeClientTransport) {
-    let client = RenetClient::new(connection_config());
+/*
+The UDP socket of the client, bound once and shared by the transport of every connection. A
+secure server drops a connect token used again from another address, so a reconnect with the
+same token has to come from the same port.
+*/
+#[derive(Debug, Default, Resource)]
+pub struct ClientSocket(Option<UdpSocket>);
 
-    let addr = if let Ok(addr) = std::env::var("RENET_SERVER_ADDR") {
-        println!("RENET_SERVER_ADDR: {}", &addr);
-        addr
-    } else {
-        let default = "127.0.0.1:5000".to_string();
-        println!("RENET_SERVER_ADDR not set, setting default: {}", &default);
-        default
-    };
+impl ClientSocket {
+    /*A handle on our port for a new transport, bound on first use or when the server is of the other address family.*/
+    fn handle(&mut self, server_addr: SocketAddr) -> std::io::Result<UdpSocket> {
+        let socket = match self.0.take() {
+            Some(socket) if socket.local_addr()?.is_ipv4() == server_addr.is_ipv4() => socket,
+            // Any local interface, so servers on other hosts are reachable too.
+            _ => UdpSocket::bind(if server_addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?,
+        };
+        let handle = socket.try_clone()?;
+        self.0 = Some(socket);
+        Ok(handle)
+    }
+}
 
-    let server_addr = addr.parse().unwrap();
-    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+pub(crate) fn new_renet_client(identity : &ClientIdentity,net_config : &NetConfig,socket : &mut ClientSocket,conditioner : Option<&mut LinkConditioner>) -> Result<(RenetClient, NetcodeClientTransport), NetworkError> {
+    let client = RenetClient::new(connection_config());
+
+    let server_addr = net_config.server_addr();
+    println!("Connecting to {}", server_addr);
+    let socket = socket.handle(server_addr).map_err(NetworkError::Socket)?;
     let current_time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
-    let client_id = current_time.as_millis() as u64;
-    let authentication = ClientAuthentication::Unsecure {
-        client_id,
-        protocol_id: PROTOCOL_ID,
-        server_addr,
-        user_data: None,
+    let mut authentication = if unsecure_requested() {
+        println!("RENET_UNSECURE set, connecting without connect token");
+        let user_data = identity.profile.to_user_data().map_err(NetworkError::Profile)?;
+        ClientAuthentication::Unsecure {
+            client_id: identity.client_id,
+            protocol_id: PROTOCOL_ID,
+            server_addr,
+            user_data: Some(user_data),
+        }
+    } else if let Some(private_key) = &identity.private_key {
+        // a fresh token on every reconnect, it never expires on us
+        let user_data = identity.profile.to_user_data().map_err(NetworkError::Profile)?;
+        let connect_token = issue_connect_token(identity.client_id, vec![server_addr], Some(&user_data), private_key).map_err(NetworkError::TokenGeneration)?;
+        ClientAuthentication::Secure { connect_token }
+    } else {
+        let connect_token = connect_token_from_env()?;
+        ClientAuthentication::Secure { connect_token }
     };
+    if let Some(conditioner) = conditioner {
+        let relay_addr = conditioner.relay(loopback(server_addr), server_addr).map_err(NetworkError::Relay)?;
+        println!("Simulating network conditions through {}", relay_addr);
+        authentication = route_through_relay(authentication, relay_addr)?;
+    }
+
+    let transport = NetcodeClientTransport::new(current_time, authentication, socket).map_err(NetworkError::Transport)?;
 
-    let transport = NetcodeClientTransport::new(current_time, authentication, socket).unwrap();
+    Ok((client, transport))
+}
 
-    (client, transport)
+/*The server checks the addresses inside the token, the client sends to the first public one, so only the public part points at the relay.*/
+fn route_through_relay(authentication : ClientAuthentication,relay_addr : SocketAddr) -> Result<ClientAuthentication, NetworkError> {
+    let mut connect_token = match authentication {
+        // the same token the transport makes for unsecure connections, signed with the zero key
+        ClientAuthentication::Unsecure { client_id, server_addr, user_data, .. } =>
+            issue_connect_token(client_id, vec![server_addr], user_data.as_ref(), &[0; NETCODE_KEY_BYTES]).map_err(NetworkError::TokenGeneration)?,
+        ClientAuthentication::Secure { connect_token } => connect_token,
+    };
+    for (i, addr) in connect_token.server_addresses.iter_mut().enumerate() {
+        *addr = (i == 0).then_some(relay_addr);
+    }
+    Ok(ClientAuthentication::Secure { connect_token })
 }
 
+#[cfg(feature = "graphics")]
 fn update_visulizer_system(
     mut egui_contexts: EguiContexts,
     mut visualizer: ResMut<RenetClientVisualizer<200>>,
     client: Res<RenetClient>,
+    clock: Res<ServerClock>,
+    conditioner: Option<Res<LinkConditioner>>,
     mut show_visualizer: Local<bool>,
     keyboard_input: Res<Input<KeyCode>>,
 ) {
@@ -118,6 +216,18 @@ fn update_visulizer_system(
     }
     if *show_visualizer {
         visualizer.show_window(egui_contexts.ctx_mut());
+        bevy_egui::egui::Window::new("Server clock").show(egui_contexts.ctx_mut(), |ui| {
+            if clock.is_synced() {
+                ui.label(format!("rtt {:.1} ms", clock.rtt() * 1000.));
+                ui.label(format!("offset {:.3} s", clock.offset()));
+                ui.label(format!("tick {}", clock.server_tick().0));
+            } else {
+                ui.label("not synced");
+            }
+        });
+        if let Some(conditioner) = conditioner {
+            crate::renet::conditioner::link_conditioner_window(egui_contexts.ctx_mut(), &conditioner);
+        }
     }
 }
 
@@ -129,12 +239,52 @@ impl PlayerCommand for  NullPlayerCommand {
     
 }
 
+/*Local tick counter, advanced once per simulated tick and sent with its input so the server can acknowledge what it applied.*/
+#[derive(Debug, Default, Resource)]
+pub struct ClientInputTick(pub u32);
+
+/*Newest snapshot tick we decoded, sent back with the input so the server can delta against it.*/
+#[derive(Debug, Default, Resource)]
+pub struct SnapshotAck(pub u32);
+
+/*Snapshot messages received since the start, the ones dropped as old or undecodable too.*/
+#[derive(Debug, Default, Resource)]
+pub struct SnapshotsReceived(pub u32);
+
+/*Inputs we may still send, refilled every tick so a fast tick rate stays under the server's input limit.*/
+#[derive(Debug, Resource)]
+pub struct InputThrottle(pub f32);
+
+impl Default for InputThrottle {
+    fn default() -> Self {
+        InputThrottle(INPUT_BURST)
+    }
+}
+
 fn client_send_input(
+    fixed_time: Res<FixedTime>,
     player_input: Res<PlayerInputState>,
+    mut input_tick: ResMut<ClientInputTick>,
+    mut throttle: ResMut<InputThrottle>,
+    snapshot_ack: Res<SnapshotAck>,
     mut client: ResMut<RenetClient>) 
 {
-    let input_message = bincode::serialize(&*player_input).unwrap();
-    client.send_message(ClientChannel::Input, input_message);
+    input_tick.0 = input_tick.0.wrapping_add(1);
+    throttle.0 = (throttle.0 + MAX_INPUTS_PER_SECOND * fixed_time.period.as_secs_f32()).min(INPUT_BURST);
+    // the tick is simulated anyway, the server keeps driving on the input before
+    if throttle.0 < 1. {
+        return;
+    }
+    throttle.0 -= 1.;
+    let ticked_input = TickedInputState {
+        tick: input_tick.0,
+        snapshot_ack: snapshot_ack.0,
+        input: *player_input,
+    };
+    match encode(&ticked_input) {
+        Ok(input_message) => client.send_message(ClientChannel::Input, input_message),
+        Err(e) => error!("{}", e),
+    }
 }
 
 fn client_send_player_commands<C : PlayerCommand>(
@@ -142,35 +292,43 @@ fn client_send_player_commands<C : PlayerCommand>(
     mut client: ResMut<RenetClient>,
 ) {
     for command in player_commands.iter() {
-        let command_message = bincode::serialize(command).unwrap();
-        client.send_message(ClientChannel::Command, command_message);
-    }
-}
-
-// If any error is found we just panic
-fn panic_on_error_system(mut renet_error: EventReader<NetcodeTransportError>) {
-    for e in renet_error.iter() {
-        panic!("{}", e);
+        match encode(command) {
+            Ok(command_message) => client.send_message(ClientChannel::Command, command_message),
+            Err(e) => error!("{}", e),
+        }
     }
 }
 
-
 pub fn client_sync_players<PS : PlayerSpawner>(
     mut cmd: Commands,
     mut client: ResMut<RenetClient>,
-    game_asset : Res<GameAssets>,
+    game_asset : Option<Res<GameAssets>>,
     transport: Res<NetcodeClientTransport>,
+    identity: Res<ClientIdentity>,
     mut lobby: ResMut<ClientLobby>,
     mut network_mapping: ResMut<NetworkMapping>,
+    mut race_messages: EventWriter<RaceMessage>,
+    mut chat_messages: EventWriter<ChatMessage>,
 ) 
 {
     let client_id = transport.client_id();
+    // A spectator has no car of its own to tell when the server is done announcing the others,
+    // it starts over from what the server announces on the new connection.
+    if identity.profile.spectator {
+        drop_stale_players(&mut cmd, &mut lobby);
+    }
     while let Some(message) = client.receive_message(ServerChannel::ServerMessages) {
-        let server_message = bincode::deserialize(&message).unwrap();
+        let server_message: ServerMessages = match decode(&message) {
+            Ok(server_message) => server_message,
+            Err(e) => {
+                error!("Ignoring server message: {}", e);
+                continue;
+            }
+        };
         match server_message {
-            ServerMessages::PlayerCreate {id,translation,rotation,scale,entity,} =>
+            ServerMessages::PlayerCreate {id,translation,rotation,scale,entity,profile} =>
             {
-                println!("Player {} connected.", id);                
+                println!("Player {} ({}) connected.", id, profile.name);                
                 let is_player = client_id == id;
 
                 // let transform: Transform = Transform::from_translation(translation);
@@ -179,13 +337,26 @@ pub fn client_sync_players<PS : PlayerSpawner>(
                     .with_rotation(Quat::from_array(rotation))
                     .with_scale(Vec3::from_array(scale));
 
-                let archetype : u128 = 0;
-                let (success,client_entity) = PS::spawn_proxy(&mut cmd,&game_asset, archetype, transform,is_player);
+                // Announced again after a reconnect, keep our proxy and bind it to the new server entity.
+                if let Some(player_info) = lobby.players.get_mut(&id) {
+                    player_info.server_entity = entity;
+                    player_info.stale = false;
+                    player_info.profile = profile;
+                    network_mapping.0.insert(entity, player_info.client_entity);
+                    if is_player {
+                        drop_stale_players(&mut cmd, &mut lobby);
+                    }
+                    continue;
+                }
+
+                let (success,client_entity) = PS::spawn_proxy(&mut cmd,game_asset.as_deref(), &profile, transform,is_player);
                 if success
                 {
                     let player_info = PlayerInfo {
                         server_entity: entity,
                         client_entity : client_entity,
+                        stale : false,
+                        profile,
                     };
                     if is_player
                     {
@@ -193,20 +364,42 @@ pub fn client_sync_players<PS : PlayerSpawner>(
                     }
                     lobby.players.insert(id, player_info);
                     network_mapping.0.insert(entity, client_entity);
+                    if is_player {
+                        drop_stale_players(&mut cmd, &mut lobby);
+                    }
+                }
+                else {
+                    error!("Can not spawn proxy with Arch : {} , for Player {}",profile.archetype,id);
                 }
             }
             ServerMessages::PlayerRemove { id } => {
                 println!("Player {} disconnected.", id);
-                if let Some(PlayerInfo {server_entity,client_entity,}) = lobby.players.remove(&id)
+                if let Some(PlayerInfo {server_entity,client_entity,..}) = lobby.players.remove(&id)
                 {
                     cmd.entity(client_entity).despawn();
                     network_mapping.0.remove(&server_entity);
                 }
             }
+            ServerMessages::Race(race_message) => race_messages.send(race_message),
+            ServerMessages::Chat(chat_message) => chat_messages.send(chat_message),
         }
     }
 }
 
+/*
+The server announces every existing player before our own car, so once ours is in
+anything still stale left while we were away. A spectator drops them all once reconnected.
+*/
+fn drop_stale_players(cmd: &mut Commands, lobby: &mut ClientLobby) {
+    lobby.players.retain(|id, player_info| {
+        if player_info.stale {
+            println!("Player {} is gone after reconnecting.", id);
+            cmd.entity(player_info.client_entity).despawn();
+        }
+        !player_info.stale
+    });
+}
+
 use bevy::app::AppExit;
 fn disconnent_on_app_exit(mut exit: EventReader<AppExit>,mut transport : ResMut<NetcodeClientTransport>) {
     for _ev in exit.iter() {
@@ -233,24 +426,88 @@ impl<PS : PlayerSpawner,C : PlayerCommand> Plugin for NetClientPlugin<PS,C> {
         app.add_plugins((
             RenetClientPlugin,
             NetcodeClientPlugin,
-            EguiPlugin,
         ))
-        .add_event::<NullPlayerCommand>()
+        .add_event::<C>()
+        .add_event::<RaceMessage>()
+        .add_event::<ChatMessage>()
         .insert_resource(ClientLobby::default())
         .insert_resource(NetworkMapping::default())
-        .insert_resource(RenetClientVisualizer::<200>::new(
-            RenetVisualizerStyle::default(),
-        ))
-        .add_systems(Update,(update_visulizer_system,panic_on_error_system))
+        .insert_resource(ClientInputTick::default())
+        .insert_resource(InputThrottle::default())
+        .insert_resource(SnapshotAck::default())
+        .insert_resource(SnapshotsReceived::default())
+        .insert_resource(SnapshotClock::default())
+        .insert_resource(ServerClock::default())
+        .insert_resource(ClockSyncConfig::default())
+        .insert_resource(InterpolationConfig::default())
+        .insert_resource(ConnectionStatus::default())
+        .insert_resource(ServerHandshake::default())
+        .insert_resource(ReconnectConfig::default())
+        .insert_resource(DiscoveredServers::default())
+        .add_event::<JoinServer>()
+        .add_systems(Update,(client_connection_status_system,client_reconnect_system.after(client_connection_status_system)))
+        .add_systems(Update,client_join_server_system.before(client_connection_status_system))
+        .add_systems(Update,client_handshake_system
+            .after(client_join_server_system)
+            .before(client_connection_status_system)
+            .run_if(resource_exists::<NetcodeClientTransport>()))
+        .add_systems(Update,client_clock_sync_system.after(client_handshake_system).before(interpolate_snapshots_system))
+        .add_systems(Update,interpolate_snapshots_system.run_if(in_state(GameState::Playing).or_else(in_state(GameState::Pause))))
+        .add_systems(FixedUpdate, client_send_input
+            .in_set(ClientInputSet)
+            .run_if(bevy_renet::transport::client_connected().and_then(handshake_accepted).and_then(in_state(GameState::Playing).or_else(in_state(GameState::Pause)))))
         .add_systems(Update, (
-            client_send_input,
             client_send_player_commands::<C>,
             client_sync_players::<PS>)
-            .run_if(bevy_renet::transport::client_connected().and_then(in_state(GameState::Playing).or_else(in_state(GameState::Pause)))));
+            .after(client_handshake_system)
+            .run_if(bevy_renet::transport::client_connected().and_then(handshake_accepted).and_then(in_state(GameState::Playing).or_else(in_state(GameState::Pause)))));
+
+        // A headless client, a test or a bot, has no window for the visualizer and the status text.
+        #[cfg(feature = "graphics")]
+        if crate::has_renderer(app) {
+            app.add_plugins(EguiPlugin)
+                .insert_resource(RenetClientVisualizer::<200>::new(
+                    RenetVisualizerStyle::default(),
+                ))
+                .add_systems(Update,update_visulizer_system)
+                .add_systems(Update,client_reconnect_ui_system.run_if(in_state(GameState::Playing).or_else(in_state(GameState::Pause))));
+        }
         
-        let (client, transport) = new_renet_client();
-        app.insert_resource(client)
-           .insert_resource(transport);
-        app.add_systems(PostUpdate,disconnent_on_app_exit);
+        let current_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let net_config = app.world.get_resource::<NetConfig>().cloned().unwrap_or_default();
+        // An identity inserted up front wins, so several clients in one process get distinct ids.
+        let identity = app.world.remove_resource::<ClientIdentity>().unwrap_or_else(|| ClientIdentity {
+            client_id: current_time.as_millis() as u64,
+            profile: net_config.profile.clone(),
+            private_key: None,
+        });
+        let mut conditioner = net_config.conditions.map(LinkConditioner::new);
+        let mut socket = ClientSocket::default();
+        match new_renet_client(&identity,&net_config,&mut socket,conditioner.as_mut()) {
+            Ok((client, transport)) => {
+                app.insert_resource(client)
+                   .insert_resource(transport);
+            }
+            Err(e) => {
+                // A client without transport never connects, it waits for client_reconnect_system.
+                let status = connection_failed(e, 0, 0., &ReconnectConfig::default());
+                app.insert_resource(RenetClient::new(connection_config()))
+                   .insert_resource(status);
+            }
+        }
+        if let Some(conditioner) = conditioner {
+            app.insert_resource(conditioner);
+        }
+        let discovery = net_config.discovery_port != 0;
+        app.insert_resource(identity)
+           .insert_resource(socket)
+           .insert_resource(net_config);
+        if let Some(probe) = discovery.then(new_discovery_probe).flatten() {
+            app.insert_resource(probe)
+               .add_systems(Update,client_discovery_system.run_if(in_state(GameState::Menu)));
+        }
+        app.add_systems(PostUpdate,disconnent_on_app_exit.run_if(resource_exists::<NetcodeClientTransport>()));
     }
 }
\ No newline at end of file
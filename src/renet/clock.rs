@@ -0,0 +1,289 @@
+use bevy::prelude::*;
+use bevy_renet::renet::{RenetClient, RenetServer};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::renet::client::ClientChannel;
+use crate::renet::error::{decode, encode};
+use crate::renet::guard::{TrafficGuard, TrafficLimits};
+use crate::renet::handshake::{Features, ServerHandshake};
+use crate::renet::server::{ServerChannel, ServerTick};
+
+/*Sent by the client on ClientChannel::Clock, client_time is its own elapsed time.*/
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ClockPing {
+    pub client_time: f64,
+}
+
+/*The server answers a ping at once with its clock and tick, client_time comes back untouched.*/
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ClockPong {
+    pub client_time: f64,
+    pub server_time: f64,
+    pub server_tick: u32,
+}
+
+#[derive(Debug, Clone, Resource)]
+pub struct ClockSyncConfig {
+    /*Seconds between two pings once the clock is synced.*/
+    pub ping_interval: f64,
+    /*Seconds between two pings while fewer than warmup_samples came back.*/
+    pub warmup_interval: f64,
+    pub warmup_samples: usize,
+    /*Round trips kept, the offset comes from the fastest of them.*/
+    pub window: usize,
+    /*A pong that took longer (seconds) says little about the clock and is ignored.*/
+    pub max_rtt: f64,
+}
+
+impl Default for ClockSyncConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: 1.,
+            warmup_interval: 0.1,
+            warmup_samples: 5,
+            window: 16,
+            max_rtt: 2.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClockSample {
+    rtt: f64,
+    offset: f64,
+}
+
+/*
+The server clock as seen from the client, estimated from ping round trips. The server read its
+clock about halfway through a round trip, so the reply with the shortest one says the most
+about the offset, the others only feed the smoothed rtt.
+*/
+#[derive(Debug, Default, Resource)]
+pub struct ServerClock {
+    samples: VecDeque<ClockSample>,
+    rtt: f64,
+    offset: f64,
+    /*Newest tick the server reported, and its server time then.*/
+    tick: u32,
+    tick_time: f64,
+    since_ping: f64,
+}
+
+impl ServerClock {
+    /*Round trip time in seconds, smoothed.*/
+    pub fn rtt(&self) -> f64 {
+        self.rtt
+    }
+
+    /*Seconds to add to the local elapsed time to get the server's.*/
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    pub fn is_synced(&self) -> bool {
+        !self.samples.is_empty()
+    }
+
+    pub fn server_time(&self, local_time: f64) -> f64 {
+        local_time + self.offset
+    }
+
+    pub fn local_time(&self, server_time: f64) -> f64 {
+        server_time - self.offset
+    }
+
+    /*Newest ServerTick heard of, with the server time it was current at.*/
+    pub fn server_tick(&self) -> (u32, f64) {
+        (self.tick, self.tick_time)
+    }
+
+    pub fn add_sample(&mut self, pong: &ClockPong, local_time: f64, config: &ClockSyncConfig) -> bool {
+        let rtt = local_time - pong.client_time;
+        if !pong.server_time.is_finite() || !(0. ..=config.max_rtt).contains(&rtt) {
+            return false;
+        }
+        let sample = ClockSample {
+            rtt,
+            offset: pong.server_time + rtt / 2. - local_time,
+        };
+        self.rtt = if self.is_synced() { self.rtt + (rtt - self.rtt) * 0.1 } else { rtt };
+        self.samples.push_back(sample);
+        while self.samples.len() > config.window {
+            self.samples.pop_front();
+        }
+        let best = self.samples.iter().min_by(|a, b| a.rtt.total_cmp(&b.rtt)).copied().unwrap_or(sample);
+        // small corrections are eased in so interpolation and timers do not jump, a big error is fixed at once
+        if self.samples.len() == 1 || (best.offset - self.offset).abs() > 0.25 {
+            self.offset = best.offset;
+        } else {
+            self.offset += (best.offset - self.offset) * 0.2;
+        }
+        if pong.server_time >= self.tick_time {
+            self.tick = pong.server_tick;
+            self.tick_time = pong.server_time;
+        }
+        true
+    }
+}
+
+/*Ping the server now and then and fold its answers into ServerClock. A new connection starts over, it may be another server.*/
+pub fn client_clock_sync_system(
+    time: Res<Time>,
+    config: Res<ClockSyncConfig>,
+    handshake: Res<ServerHandshake>,
+    mut client: ResMut<RenetClient>,
+    mut clock: ResMut<ServerClock>,
+) {
+    if !client.is_connected() || !handshake.features().contains(Features::CLOCK_SYNC) {
+        if clock.is_synced() {
+            *clock = ServerClock::default();
+        }
+        return;
+    }
+    let now = time.elapsed_seconds_f64();
+    while let Some(message) = client.receive_message(ServerChannel::Clock) {
+        match decode::<ClockPong>(&message) {
+            Ok(pong) => {
+                if !clock.add_sample(&pong, now, &config) {
+                    debug!("Ignoring clock reply {:?}", pong);
+                }
+            }
+            Err(e) => warn!("Ignoring clock reply: {}", e),
+        }
+    }
+
+    clock.since_ping += time.delta_seconds_f64();
+    let interval = if clock.samples.len() < config.warmup_samples { config.warmup_interval } else { config.ping_interval };
+    if clock.since_ping < interval {
+        return;
+    }
+    clock.since_ping = 0.;
+    match encode(&ClockPing { client_time: now }) {
+        Ok(bytes) => client.send_message(ClientChannel::Clock, bytes),
+        Err(e) => error!("{}", e),
+    }
+}
+
+/*Answer pings right away, more than max_pings_per_tick from one client is a strike.*/
+pub fn server_clock_system(
+    time: Res<Time>,
+    server_tick: Res<ServerTick>,
+    limits: Res<TrafficLimits>,
+    mut guard: ResMut<TrafficGuard>,
+    mut server: ResMut<RenetServer>,
+) {
+    let now = time.elapsed_seconds_f64();
+    for client_id in server.clients_id() {
+        let mut handled = 0;
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Clock) {
+            handled += 1;
+            if handled > limits.max_pings_per_tick {
+                continue;
+            }
+            let ping = match decode::<ClockPing>(&message) {
+                Ok(ping) => ping,
+                Err(e) => {
+                    if guard.strike(&mut server, &limits, client_id, &format!("Clock rejected: {}", e)) {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let pong = ClockPong {
+                client_time: ping.client_time,
+                server_time: now,
+                server_tick: server_tick.0,
+            };
+            match encode(&pong) {
+                Ok(bytes) => server.send_message(client_id, ServerChannel::Clock, bytes),
+                Err(e) => error!("{}", e),
+            }
+        }
+        if handled > limits.max_pings_per_tick {
+            guard.strike(&mut server, &limits, client_id, &format!("more than {} Clock messages in a tick", limits.max_pings_per_tick));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*A pong whose sample has the given rtt and offset, local time is the client time plus the rtt.*/
+    fn pong(client_time: f64, rtt: f64, offset: f64, server_tick: u32) -> (ClockPong, f64) {
+        let local_time = client_time + rtt;
+        let pong = ClockPong {
+            client_time,
+            server_time: local_time + offset - rtt / 2.,
+            server_tick,
+        };
+        (pong, local_time)
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn outliers_are_ignored() {
+        let config = ClockSyncConfig::default();
+        let mut clock = ServerClock::default();
+        let (good, local_time) = pong(1., 0.1, 4., 10);
+        // answered before it was asked, or after too long a round trip
+        assert!(!clock.add_sample(&good, good.client_time - 0.5, &config));
+        assert!(!clock.add_sample(&good, good.client_time + config.max_rtt + 0.1, &config));
+        let nan = ClockPong { server_time: f64::NAN, ..good };
+        assert!(!clock.add_sample(&nan, local_time, &config));
+        assert!(!clock.is_synced());
+
+        assert!(clock.add_sample(&good, local_time, &config));
+        assert!(clock.is_synced());
+        assert!(close(clock.rtt(), 0.1));
+        assert!(close(clock.offset(), 4.));
+        assert_eq!(clock.server_tick(), (10, good.server_time));
+    }
+
+    #[test]
+    fn samples_are_smoothed() {
+        let config = ClockSyncConfig::default();
+        let mut clock = ServerClock::default();
+        let (first, local_time) = pong(1., 0.1, 4., 10);
+        clock.add_sample(&first, local_time, &config);
+
+        // a slower round trip moves the rtt a little and leaves the offset to the fastest sample
+        let (slow, local_time) = pong(2., 0.3, 4.1, 20);
+        assert!(clock.add_sample(&slow, local_time, &config));
+        assert!(close(clock.rtt(), 0.12));
+        assert!(close(clock.offset(), 4.));
+
+        // a faster one is eased in
+        let (fast, local_time) = pong(3., 0.05, 4.1, 30);
+        assert!(clock.add_sample(&fast, local_time, &config));
+        assert!(close(clock.offset(), 4.02));
+
+        // a big error is fixed at once
+        let (jump, local_time) = pong(4., 0.01, 5., 40);
+        assert!(clock.add_sample(&jump, local_time, &config));
+        assert!(close(clock.offset(), 5.));
+        assert_eq!(clock.server_tick(), (40, jump.server_time));
+
+        // a late reply of an older ping says nothing new about the tick
+        let (late, local_time) = pong(3.95, 0.1, 4., 35);
+        assert!(late.server_time < jump.server_time);
+        assert!(clock.add_sample(&late, local_time, &config));
+        assert_eq!(clock.server_tick(), (40, jump.server_time));
+        assert!(close(clock.offset(), 5.));
+    }
+
+    #[test]
+    fn the_best_sample_leaves_the_window() {
+        let config = ClockSyncConfig { window: 2, ..Default::default() };
+        let mut clock = ServerClock::default();
+        for (client_time, rtt, offset) in [(1., 0.01, 4.), (2., 0.2, 5.), (3., 0.3, 5.)] {
+            let (pong, local_time) = pong(client_time, rtt, offset, 0);
+            clock.add_sample(&pong, local_time, &config);
+        }
+        assert!(close(clock.offset(), 5.));
+    }
+}
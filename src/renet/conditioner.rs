@@ -0,0 +1,238 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/*Peers of the relay that stay silent this long lose their upstream socket.*/
+const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+/*How long the relay thread sleeps when there is nothing to forward.*/
+const RELAY_IDLE: Duration = Duration::from_millis(1);
+
+/*Artificial network conditions, applied in both directions on every packet.*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkConditions {
+    pub latency_ms: u32,
+    /*Every packet gets up to this much extra or less delay, so packets can arrive out of order.*/
+    pub jitter_ms: u32,
+    pub loss_percent: f32,
+    pub duplicate_percent: f32,
+}
+
+impl LinkConditions {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, percent) in [("loss", self.loss_percent), ("duplicate", self.duplicate_percent)] {
+            if !(0. ..=100.).contains(&percent) {
+                return Err(format!("{} must be a percentage between 0 and 100", name));
+            }
+        }
+        Ok(())
+    }
+
+    fn roll(percent: f32) -> bool {
+        percent > 0. && fastrand::f32() * 100. < percent
+    }
+
+    fn delay(&self) -> Duration {
+        let jitter = self.jitter_ms as i64;
+        let ms = self.latency_ms as i64 + if jitter > 0 { fastrand::i64(-jitter..=jitter) } else { 0 };
+        Duration::from_millis(ms.max(0) as u64)
+    }
+}
+
+/*Which socket of the relay sends a packet once it is due.*/
+#[derive(Clone, Copy)]
+enum Route {
+    /*Back to a peer through the front socket.*/
+    Front(SocketAddr),
+    /*To the target through the upstream socket of a peer.*/
+    Upstream(SocketAddr),
+}
+
+struct Delayed {
+    due: Instant,
+    route: Route,
+    payload: Vec<u8>,
+}
+
+struct Upstream {
+    socket: UdpSocket,
+    last_seen: Instant,
+}
+
+/*UDP relay between the peers talking to the front socket and one target, every peer gets its own upstream socket so the target still tells them apart.*/
+struct Relay {
+    front: UdpSocket,
+    target: SocketAddr,
+    upstreams: HashMap<SocketAddr, Upstream>,
+    queue: Vec<Delayed>,
+    conditions: Arc<Mutex<LinkConditions>>,
+}
+
+impl Relay {
+    fn run(mut self, stop: Arc<AtomicBool>) {
+        let mut buffer = [0u8; 2048];
+        while !stop.load(Ordering::Relaxed) {
+            let conditions = *self.conditions.lock().unwrap();
+            let now = Instant::now();
+            let mut busy = false;
+
+            loop {
+                match self.front.recv_from(&mut buffer) {
+                    Ok((len, peer)) => {
+                        if !self.upstreams.contains_key(&peer) {
+                            let Some(socket) = bind_nonblocking(unspecified(self.target)) else {
+                                continue;
+                            };
+                            self.upstreams.insert(peer, Upstream { socket, last_seen: now });
+                        }
+                        self.upstreams.get_mut(&peer).unwrap().last_seen = now;
+                        self.schedule(&conditions, now, Route::Upstream(peer), &buffer[..len]);
+                        busy = true;
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    // a peer that went away makes some platforms report an error, nothing to do about it
+                    Err(_) => break,
+                }
+            }
+
+            let mut answers = vec![];
+            for (peer, upstream) in self.upstreams.iter() {
+                while let Ok((len, from)) = upstream.socket.recv_from(&mut buffer) {
+                    if from == self.target {
+                        answers.push((*peer, buffer[..len].to_vec()));
+                    }
+                }
+            }
+            for (peer, payload) in answers {
+                self.schedule(&conditions, now, Route::Front(peer), &payload);
+                busy = true;
+            }
+
+            let (due, pending): (Vec<Delayed>, Vec<Delayed>) = self.queue.drain(..).partition(|packet| packet.due <= now);
+            self.queue = pending;
+            for packet in due {
+                let _ = match packet.route {
+                    Route::Front(peer) => self.front.send_to(&packet.payload, peer),
+                    Route::Upstream(peer) => match self.upstreams.get(&peer) {
+                        Some(upstream) => upstream.socket.send_to(&packet.payload, self.target),
+                        None => continue,
+                    },
+                };
+            }
+
+            self.upstreams.retain(|_, upstream| now.duration_since(upstream.last_seen) < PEER_TIMEOUT);
+            if !busy {
+                std::thread::sleep(RELAY_IDLE);
+            }
+        }
+    }
+
+    fn schedule(&mut self, conditions: &LinkConditions, now: Instant, route: Route, payload: &[u8]) {
+        if LinkConditions::roll(conditions.loss_percent) {
+            return;
+        }
+        if LinkConditions::roll(conditions.duplicate_percent) {
+            self.queue.push(Delayed { due: now + conditions.delay(), route, payload: payload.to_vec() });
+        }
+        self.queue.push(Delayed { due: now + conditions.delay(), route, payload: payload.to_vec() });
+    }
+}
+
+fn unspecified(addr: SocketAddr) -> SocketAddr {
+    let ip = if addr.is_ipv4() { IpAddr::V4(Ipv4Addr::UNSPECIFIED) } else { IpAddr::V6(Ipv6Addr::UNSPECIFIED) };
+    SocketAddr::new(ip, 0)
+}
+
+fn bind_nonblocking(addr: SocketAddr) -> Option<UdpSocket> {
+    let socket = UdpSocket::bind(addr).ok()?;
+    socket.set_nonblocking(true).ok()?;
+    Some(socket)
+}
+
+/*Stops the relay thread when the conditioner lets go of it.*/
+struct RelayHandle {
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for RelayHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/*Network condition simulator, only present when it was asked for at launch. The conditions can be changed while running.*/
+#[derive(Resource)]
+pub struct LinkConditioner {
+    conditions: Arc<Mutex<LinkConditions>>,
+    relay: Option<RelayHandle>,
+}
+
+impl LinkConditioner {
+    pub fn new(conditions: LinkConditions) -> Self {
+        Self {
+            conditions: Arc::new(Mutex::new(conditions)),
+            relay: None,
+        }
+    }
+
+    pub fn conditions(&self) -> LinkConditions {
+        *self.conditions.lock().unwrap()
+    }
+
+    pub fn set_conditions(&self, conditions: LinkConditions) {
+        *self.conditions.lock().unwrap() = conditions;
+    }
+
+    /*Start relaying from front to target, replacing the previous relay. Returns the address to send to instead of target.*/
+    pub fn relay(&mut self, front: SocketAddr, target: SocketAddr) -> std::io::Result<SocketAddr> {
+        self.relay = None;
+        let front = UdpSocket::bind(front)?;
+        front.set_nonblocking(true)?;
+        let front_addr = front.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let relay = Relay {
+            front,
+            target,
+            upstreams: HashMap::new(),
+            queue: vec![],
+            conditions: self.conditions.clone(),
+        };
+        let thread_stop = stop.clone();
+        std::thread::Builder::new()
+            .name("link conditioner".to_string())
+            .spawn(move || relay.run(thread_stop))?;
+        self.relay = Some(RelayHandle { stop });
+        Ok(front_addr)
+    }
+}
+
+/*Loopback address of the same family, where a socket behind the relay listens.*/
+pub fn loopback(addr: SocketAddr) -> SocketAddr {
+    let ip = if addr.is_ipv4() { IpAddr::V4(Ipv4Addr::LOCALHOST) } else { IpAddr::V6(Ipv6Addr::LOCALHOST) };
+    SocketAddr::new(ip, 0)
+}
+
+/*Sliders for the conditions, shown next to the renet visualizer.*/
+#[cfg(feature = "graphics")]
+pub fn link_conditioner_window(ctx: &bevy_egui::egui::Context, conditioner: &LinkConditioner) {
+    use bevy_egui::egui;
+
+    let mut conditions = conditioner.conditions();
+    egui::Window::new("Network conditions").show(ctx, |ui| {
+        ui.add(egui::Slider::new(&mut conditions.latency_ms, 0..=1000).text("latency ms"));
+        ui.add(egui::Slider::new(&mut conditions.jitter_ms, 0..=500).text("jitter ms"));
+        ui.add(egui::Slider::new(&mut conditions.loss_percent, 0. ..=100.).text("loss %"));
+        ui.add(egui::Slider::new(&mut conditions.duplicate_percent, 0. ..=100.).text("duplicate %"));
+        if ui.button("Reset").clicked() {
+            conditions = LinkConditions::default();
+        }
+    });
+    if conditions != conditioner.conditions() {
+        conditioner.set_conditions(conditions);
+    }
+}
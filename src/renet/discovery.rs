@@ -0,0 +1,257 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+
+use crate::renet::error::{decode, encode};
+use crate::renet::handshake::PROTOCOL_VERSION;
+use crate::renet::server::ServerLobby;
+use crate::renet::{NetConfig, PROTOCOL_ID};
+
+pub const DEFAULT_DISCOVERY_PORT: u16 = 5100;
+
+/*Seconds between two broadcast queries of the client.*/
+const QUERY_INTERVAL: f64 = 1.;
+/*A server that did not answer for that long is dropped from the list.*/
+const SERVER_TIMEOUT: f64 = 3.5;
+
+/*Queries are broadcast on the discovery port, servers answer to the sender.*/
+#[derive(Debug, Serialize, Deserialize)]
+pub enum DiscoveryMessage {
+    Query,
+    Answer(ServerInfo),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub name: String,
+    pub track: String,
+    pub players: u32,
+    pub max_players: u32,
+    pub protocol_id: u64,
+    pub protocol_version: u32,
+    /*Game address, unspecified when the client should use the one the answer came from.*/
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+impl ServerInfo {
+    pub fn compatible(&self) -> bool {
+        self.protocol_id == PROTOCOL_ID && self.protocol_version == PROTOCOL_VERSION
+    }
+}
+
+/*What the server advertises besides its player count, set by the game.*/
+#[derive(Debug, Clone, Resource)]
+pub struct DiscoveryInfo {
+    pub name: String,
+    pub track: String,
+}
+
+#[derive(Resource)]
+pub struct DiscoveryResponder(UdpSocket);
+
+/*None when the discovery port is 0 or taken, the server still runs but can not be found.*/
+pub fn new_discovery_responder(port: u16) -> Option<DiscoveryResponder> {
+    if port == 0 {
+        return None;
+    }
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port))
+        .and_then(|socket| socket.set_nonblocking(true).map(|_| socket));
+    match socket {
+        Ok(socket) => Some(DiscoveryResponder(socket)),
+        Err(e) => {
+            warn!("LAN discovery disabled, can not bind port {}: {}", port, e);
+            None
+        }
+    }
+}
+
+pub fn server_discovery_system(
+    responder: Res<DiscoveryResponder>,
+    net_config: Res<NetConfig>,
+    info: Option<Res<DiscoveryInfo>>,
+    lobby: Res<ServerLobby>,
+) {
+    let mut buffer = [0u8; 512];
+    loop {
+        let (len, from) = match responder.0.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                debug!("Discovery receive failed: {}", e);
+                break;
+            }
+        };
+        // Anybody on the network can write here, ignore what does not decode.
+        let Ok(DiscoveryMessage::Query) = decode(&buffer[..len]) else {
+            continue;
+        };
+        let Some(address) = advertised_address(&net_config, from) else {
+            continue;
+        };
+        let answer = DiscoveryMessage::Answer(ServerInfo {
+            name: info.as_ref().map_or(String::new(), |info| info.name.clone()),
+            track: info.as_ref().map_or(String::new(), |info| info.track.clone()),
+            players: lobby.players.len() as u32,
+            max_players: net_config.max_clients as u32,
+            protocol_id: PROTOCOL_ID,
+            protocol_version: PROTOCOL_VERSION,
+            address,
+            port: net_config.port,
+        });
+        if let Ok(bytes) = encode(&answer) {
+            let _ = responder.0.send_to(&bytes, from);
+        }
+    }
+}
+
+/*
+Address a client asking from there reaches the game on. The public address when there is one,
+a server bound to loopback is only reachable from the same machine and others get no answer.
+*/
+fn advertised_address(net_config: &NetConfig, from: SocketAddr) -> Option<IpAddr> {
+    if let Some(public_address) = net_config.public_address {
+        return Some(public_address);
+    }
+    if net_config.address.is_loopback() && !from.ip().is_loopback() {
+        return None;
+    }
+    Some(net_config.address)
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredServer {
+    pub addr: SocketAddr,
+    pub info: ServerInfo,
+    pub last_seen: f64,
+}
+
+/*Servers that answered the last queries, in the order they were first seen.*/
+#[derive(Debug, Default, Resource)]
+pub struct DiscoveredServers {
+    pub servers: Vec<DiscoveredServer>,
+}
+
+#[derive(Resource)]
+pub struct DiscoveryProbe {
+    socket: UdpSocket,
+    next_query: f64,
+}
+
+pub fn new_discovery_probe() -> Option<DiscoveryProbe> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))
+        .and_then(|socket| socket.set_broadcast(true).map(|_| socket))
+        .and_then(|socket| socket.set_nonblocking(true).map(|_| socket));
+    match socket {
+        Ok(socket) => Some(DiscoveryProbe { socket, next_query: 0. }),
+        Err(e) => {
+            warn!("LAN discovery disabled: {}", e);
+            None
+        }
+    }
+}
+
+/*Broadcast a query every QUERY_INTERVAL and collect the answers.*/
+pub fn client_discovery_system(
+    time: Res<Time>,
+    net_config: Res<NetConfig>,
+    mut probe: ResMut<DiscoveryProbe>,
+    mut discovered: ResMut<DiscoveredServers>,
+) {
+    let now = time.elapsed_seconds_f64();
+    if now >= probe.next_query {
+        probe.next_query = now + QUERY_INTERVAL;
+        if let Ok(bytes) = encode(&DiscoveryMessage::Query) {
+            let broadcast = SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), net_config.discovery_port);
+            if let Err(e) = probe.socket.send_to(&bytes, broadcast) {
+                debug!("Discovery query failed: {}", e);
+            }
+        }
+    }
+
+    let mut changed = false;
+    let mut buffer = [0u8; 512];
+    loop {
+        let (len, from) = match probe.socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                debug!("Discovery receive failed: {}", e);
+                break;
+            }
+        };
+        let Ok(DiscoveryMessage::Answer(info)) = decode(&buffer[..len]) else {
+            continue;
+        };
+        let ip = if info.address.is_unspecified() { from.ip() } else { info.address };
+        let addr = SocketAddr::new(ip, info.port);
+        // last_seen moves with every answer, the list only changes when a server or what it says does
+        let servers = &mut discovered.bypass_change_detection().servers;
+        match servers.iter_mut().find(|server| server.addr == addr) {
+            Some(server) => {
+                server.last_seen = now;
+                if server.info != info {
+                    server.info = info;
+                    changed = true;
+                }
+            }
+            None => {
+                servers.push(DiscoveredServer { addr, info, last_seen: now });
+                changed = true;
+            }
+        }
+    }
+
+    if discovered.servers.iter().any(|server| now - server.last_seen > SERVER_TIMEOUT) {
+        discovered.bypass_change_detection().servers.retain(|server| now - server.last_seen <= SERVER_TIMEOUT);
+        changed = true;
+    }
+    if changed {
+        discovered.set_changed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(ip: &str) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), 40000)
+    }
+
+    fn config(address: &str, public_address: Option<&str>) -> NetConfig {
+        NetConfig {
+            address: address.parse().unwrap(),
+            public_address: public_address.map(|ip| ip.parse().unwrap()),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn public_address_wins() {
+        let net_config = config("0.0.0.0", Some("203.0.113.7"));
+        assert_eq!(advertised_address(&net_config, addr("192.168.1.30")), Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(advertised_address(&net_config, addr("127.0.0.1")), Some("203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn every_interface_lets_the_client_use_the_source() {
+        let net_config = config("0.0.0.0", None);
+        let address = advertised_address(&net_config, addr("192.168.1.30")).unwrap();
+        assert!(address.is_unspecified());
+    }
+
+    #[test]
+    fn loopback_only_answers_the_same_machine() {
+        let net_config = config("127.0.0.1", None);
+        assert_eq!(advertised_address(&net_config, addr("192.168.1.30")), None);
+        assert_eq!(advertised_address(&net_config, addr("127.0.0.1")), Some("127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn bound_interface_is_advertised() {
+        let net_config = config("192.168.1.20", None);
+        assert_eq!(advertised_address(&net_config, addr("192.168.1.30")), Some("192.168.1.20".parse().unwrap()));
+    }
+}
@@ -0,0 +1,78 @@
+use bevy_renet::renet::transport::{NetcodeError, TokenGenerationError};
+use std::fmt;
+
+use crate::renet::token::TokenError;
+
+/*Everything that can go wrong handling a message, on either side of the connection.*/
+#[derive(Debug)]
+pub enum NetError {
+    /*Bytes received from the network did not decode into the expected message.*/
+    Decode(bincode::Error),
+    /*A message we built could not be encoded.*/
+    Encode(bincode::Error),
+    /*A delta snapshot refers to a baseline we no longer have.*/
+    MissingBaseline(u32),
+    /*The player profile sent in the handshake was refused.*/
+    InvalidProfile(&'static str),
+    /*A command decoded fine but breaks the rules of the game.*/
+    InvalidCommand(&'static str),
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetError::Decode(e) => write!(f, "malformed message: {}", e),
+            NetError::Encode(e) => write!(f, "can not encode message: {}", e),
+            NetError::MissingBaseline(tick) => write!(f, "snapshot baseline {} is gone", tick),
+            NetError::InvalidProfile(reason) => write!(f, "invalid player profile: {}", reason),
+            NetError::InvalidCommand(reason) => write!(f, "invalid command: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+/*Why a client or a server could not be set up.*/
+#[derive(Debug)]
+pub enum NetworkError {
+    /*The UDP socket could not be bound or set up.*/
+    Socket(std::io::Error),
+    /*The link conditioner relay could not be started.*/
+    Relay(std::io::Error),
+    Token(TokenError),
+    TokenGeneration(TokenGenerationError),
+    /*The player profile does not fit in the connect user data.*/
+    Profile(NetError),
+    Transport(NetcodeError),
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkError::Socket(e) => write!(f, "can not open socket: {}", e),
+            NetworkError::Relay(e) => write!(f, "can not start the link conditioner relay: {}", e),
+            NetworkError::Token(e) => write!(f, "{}", e),
+            NetworkError::TokenGeneration(e) => write!(f, "can not generate connect token: {}", e),
+            NetworkError::Profile(e) => write!(f, "{}", e),
+            NetworkError::Transport(e) => write!(f, "can not set up transport: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+impl From<TokenError> for NetworkError {
+    fn from(e: TokenError) -> Self {
+        NetworkError::Token(e)
+    }
+}
+
+/*Decode a message received from the network.*/
+pub fn decode<'a, T: serde::Deserialize<'a>>(message: &'a [u8]) -> Result<T, NetError> {
+    bincode::deserialize(message).map_err(NetError::Decode)
+}
+
+/*Encode a message before sending it.*/
+pub fn encode<T: serde::Serialize>(message: &T) -> Result<Vec<u8>, NetError> {
+    bincode::serialize(message).map_err(NetError::Encode)
+}
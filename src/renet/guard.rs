@@ -0,0 +1,164 @@
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use std::collections::{HashMap, HashSet};
+
+/*Inputs a client sends per second at most on average, twice the default tick rate. Clients hold themselves to it.*/
+pub const MAX_INPUTS_PER_SECOND: f32 = 120.;
+/*Inputs a client may send at once on top of the average, to catch up after a hitch on either side.*/
+pub const INPUT_BURST: f32 = 30.;
+
+/*What a client may send, breaking a limit earns strikes and too many strikes get it kicked.*/
+#[derive(Debug, Clone, Resource)]
+pub struct TrafficLimits {
+    /*Input messages handled per client and second, whatever the frame rate of either side, the rest is dropped.*/
+    pub max_inputs_per_second: f32,
+    /*Input messages a client may send at once above that rate.*/
+    pub input_burst: f32,
+    /*Command messages handled per client and tick, the rest is dropped.*/
+    pub max_commands_per_tick: usize,
+    /*Clock pings answered per client and tick, the rest is dropped.*/
+    pub max_pings_per_tick: usize,
+    /*Strikes a client can hold before it is kicked.*/
+    pub max_strikes: f32,
+    /*Strikes forgiven every second, so the odd bad message is not fatal.*/
+    pub strike_decay: f32,
+}
+
+impl Default for TrafficLimits {
+    fn default() -> Self {
+        Self {
+            max_inputs_per_second: MAX_INPUTS_PER_SECOND,
+            input_burst: INPUT_BURST,
+            max_commands_per_tick: 8,
+            max_pings_per_tick: 4,
+            max_strikes: 30.,
+            strike_decay: 2.,
+        }
+    }
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct TrafficGuard {
+    strikes: HashMap<u64, f32>,
+    /*Inputs each client may still send, refilled at max_inputs_per_second up to input_burst.*/
+    input_tokens: HashMap<u64, f32>,
+    /*Why we kicked a client, reported when its ClientDisconnected event comes in.*/
+    kick_reasons: HashMap<u64, String>,
+    /*Client ids refused when they connect, until the server restarts.*/
+    banned: HashSet<u64>,
+}
+
+impl TrafficGuard {
+    /*Count a violation, kicks the client and returns true once it went over the limit.*/
+    pub fn strike(&mut self, server: &mut RenetServer, limits: &TrafficLimits, client_id: u64, reason: &str) -> bool {
+        if self.kick_reasons.contains_key(&client_id) {
+            return true;
+        }
+        let strikes = self.strikes.entry(client_id).or_default();
+        *strikes += 1.;
+        debug!("Strike {} for client {}: {}", strikes, client_id, reason);
+        if *strikes > limits.max_strikes {
+            self.kick(server, client_id, format!("too many violations, last one: {}", reason));
+            return true;
+        }
+        false
+    }
+
+    pub fn kick(&mut self, server: &mut RenetServer, client_id: u64, reason: String) {
+        warn!("Kicking client {}, {}", client_id, reason);
+        self.kick_reasons.insert(client_id, reason);
+        server.disconnect(client_id);
+    }
+
+    /*Returns false when the id was banned already.*/
+    pub fn ban(&mut self, client_id: u64) -> bool {
+        self.banned.insert(client_id)
+    }
+
+    /*Returns false when the id was not banned.*/
+    pub fn unban(&mut self, client_id: u64) -> bool {
+        self.banned.remove(&client_id)
+    }
+
+    pub fn is_banned(&self, client_id: u64) -> bool {
+        self.banned.contains(&client_id)
+    }
+
+    pub fn decay(&mut self, limits: &TrafficLimits, seconds: f32) {
+        let forgiven = limits.strike_decay * seconds;
+        self.strikes.retain(|_, strikes| {
+            *strikes -= forgiven;
+            *strikes > 0.
+        });
+        let refill = limits.max_inputs_per_second * seconds;
+        for tokens in self.input_tokens.values_mut() {
+            *tokens = (*tokens + refill).min(limits.input_burst);
+        }
+    }
+
+    /*Spend one input of the client, false when it sent more than its rate allows.*/
+    pub fn take_input(&mut self, limits: &TrafficLimits, client_id: u64) -> bool {
+        let tokens = self.input_tokens.entry(client_id).or_insert(limits.input_burst);
+        if *tokens < 1. {
+            return false;
+        }
+        *tokens -= 1.;
+        true
+    }
+
+    /*Forget a client that left, with the reason it was kicked if it was.*/
+    pub fn remove_client(&mut self, client_id: u64) -> Option<String> {
+        self.strikes.remove(&client_id);
+        self.input_tokens.remove(&client_id);
+        self.kick_reasons.remove(&client_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_burst_then_follow_the_rate() {
+        let limits = TrafficLimits::default();
+        let mut guard = TrafficGuard::default();
+        let burst = limits.input_burst as usize;
+        assert_eq!((0..burst * 2).filter(|_| guard.take_input(&limits, 7)).count(), burst);
+
+        // a second at any frame rate buys a second of inputs, up to the burst again
+        for _ in 0..240 {
+            guard.decay(&limits, 1. / 240.);
+        }
+        assert_eq!((0..burst * 2).filter(|_| guard.take_input(&limits, 7)).count(), burst);
+        guard.decay(&limits, 0.1);
+        let expected = (limits.max_inputs_per_second * 0.1) as usize;
+        assert_eq!((0..burst).filter(|_| guard.take_input(&limits, 7)).count(), expected);
+    }
+
+    #[test]
+    fn a_tick_rate_client_never_runs_dry() {
+        let limits = TrafficLimits::default();
+        let mut guard = TrafficGuard::default();
+        for _ in 0..600 {
+            guard.decay(&limits, 1. / 60.);
+            assert!(guard.take_input(&limits, 7));
+        }
+        // nor at twice that, on a server ticking slower
+        for _ in 0..600 {
+            guard.decay(&limits, 1. / 30.);
+            for _ in 0..4 {
+                assert!(guard.take_input(&limits, 7));
+            }
+        }
+    }
+
+    #[test]
+    fn clients_have_their_own_bucket() {
+        let limits = TrafficLimits::default();
+        let mut guard = TrafficGuard::default();
+        while guard.take_input(&limits, 7) {}
+        assert!(guard.take_input(&limits, 8));
+        guard.remove_client(7);
+        assert!(guard.take_input(&limits, 7));
+    }
+}
@@ -0,0 +1,178 @@
+use bevy::prelude::*;
+use bevy_renet::renet::transport::NetcodeClientTransport;
+use bevy_renet::renet::RenetClient;
+use serde::{Deserialize, Serialize};
+
+use crate::renet::client::ClientChannel;
+use crate::renet::error::{decode, encode};
+use crate::renet::server::ServerChannel;
+
+/*
+Bumped whenever the layout of a message changes. Builds of another version still connect,
+netcode drops a different PROTOCOL_ID without a word, and are refused with a reason instead.
+*/
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const BUILD_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/*A client that did not say hello after that many seconds is dropped.*/
+pub const HANDSHAKE_TIMEOUT: f64 = 5.;
+/*A refused client gets that long to read why and leave before it is kicked.*/
+pub const REFUSAL_GRACE: f64 = 2.;
+
+/*Optional parts of the protocol, a bit each. Adding one needs no version bump, unknown bits are ignored.*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Features(pub u32);
+
+impl Features {
+    /*Quantized snapshots, delta compressed against the last one acknowledged.*/
+    pub const DELTA_SNAPSHOTS: Features = Features(1);
+    pub const CHAT: Features = Features(1 << 1);
+    pub const CLOCK_SYNC: Features = Features(1 << 2);
+    /*The server keeps the car of a dropped client for it to come back to.*/
+    pub const SESSION_RESUME: Features = Features(1 << 3);
+
+    /*Everything this build speaks.*/
+    pub const SUPPORTED: Features = Features(0b1111);
+    /*A client without these can not play on this server.*/
+    pub const REQUIRED: Features = Features::DELTA_SNAPSHOTS;
+
+    const NAMES: [(Features, &'static str); 4] = [
+        (Features::DELTA_SNAPSHOTS, "delta snapshots"),
+        (Features::CHAT, "chat"),
+        (Features::CLOCK_SYNC, "clock sync"),
+        (Features::SESSION_RESUME, "session resume"),
+    ];
+
+    pub fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersection(self, other: Features) -> Features {
+        Features(self.0 & other.0)
+    }
+
+    pub fn difference(self, other: Features) -> Features {
+        Features(self.0 & !other.0)
+    }
+
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES.iter().filter(|(feature, _)| self.contains(*feature)).map(|(_, name)| *name).collect()
+    }
+}
+
+/*
+First message of a client on ClientChannel::Handshake. This and ServerHello keep their layout
+across protocol versions, they are how two versions find out they differ.
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientHello {
+    pub protocol_version: u32,
+    pub build: String,
+    pub features: Features,
+}
+
+impl ClientHello {
+    pub fn new() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            build: BUILD_VERSION.to_string(),
+            features: Features::SUPPORTED,
+        }
+    }
+}
+
+impl Default for ClientHello {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/*Answer of the server on ServerChannel::Handshake, features are the ones both sides speak.*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ServerHello {
+    Accepted {
+        protocol_version: u32,
+        build: String,
+        features: Features,
+    },
+    Refused {
+        protocol_version: u32,
+        build: String,
+        reason: String,
+    },
+}
+
+/*Why the server would not have this client, None when it speaks our protocol.*/
+pub fn check_hello(hello: &ClientHello) -> Option<String> {
+    if hello.protocol_version != PROTOCOL_VERSION {
+        let update = if hello.protocol_version < PROTOCOL_VERSION { "update the game" } else { "the server needs an update" };
+        return Some(format!(
+            "the server runs protocol {} (build {}), this game protocol {} (build {}), {}",
+            PROTOCOL_VERSION, BUILD_VERSION, hello.protocol_version, hello.build, update
+        ));
+    }
+    let missing = Features::REQUIRED.difference(hello.features);
+    if missing != Features::default() {
+        return Some(format!("the server needs {}", missing.names().join(", ")));
+    }
+    None
+}
+
+/*The client side of the handshake, reset for every new connection.*/
+#[derive(Debug, Default, Resource)]
+pub struct ServerHandshake {
+    pub hello_sent: bool,
+    /*Build of the server and the features both sides speak, once it took us in.*/
+    pub accepted: Option<(String, Features)>,
+    /*Why the server would not have us, the client does not retry then.*/
+    pub refusal: Option<String>,
+}
+
+impl ServerHandshake {
+    pub fn is_accepted(&self) -> bool {
+        self.accepted.is_some()
+    }
+
+    pub fn features(&self) -> Features {
+        self.accepted.as_ref().map_or(Features::default(), |(_, features)| *features)
+    }
+}
+
+/*Run condition, the client only reads and sends game messages once the server took it in.*/
+pub fn handshake_accepted(handshake: Option<Res<ServerHandshake>>) -> bool {
+    handshake.map_or(false, |handshake| handshake.is_accepted())
+}
+
+/*Say hello once connected and wait for the answer. A refused client disconnects and stays so.*/
+pub fn client_handshake_system(
+    mut client: ResMut<RenetClient>,
+    mut transport: ResMut<NetcodeClientTransport>,
+    mut handshake: ResMut<ServerHandshake>,
+) {
+    if !client.is_connected() || handshake.refusal.is_some() {
+        return;
+    }
+    if !handshake.hello_sent {
+        match encode(&ClientHello::new()) {
+            Ok(bytes) => client.send_message(ClientChannel::Handshake, bytes),
+            Err(e) => error!("{}", e),
+        }
+        handshake.hello_sent = true;
+    }
+    while let Some(message) = client.receive_message(ServerChannel::Handshake) {
+        let refusal = match decode::<ServerHello>(&message) {
+            Ok(ServerHello::Accepted { protocol_version, build, features }) => {
+                info!("Server build {} protocol {} took us in, with {}", build, protocol_version, features.names().join(", "));
+                handshake.accepted = Some((build, features));
+                continue;
+            }
+            Ok(ServerHello::Refused { reason, .. }) => reason,
+            Err(e) => format!("can not read the answer of the server: {}", e),
+        };
+        println!("Refused by the server: {}", refusal);
+        handshake.refusal = Some(refusal);
+        transport.disconnect();
+        break;
+    }
+}
@@ -0,0 +1,100 @@
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+/*How often the server replicates and how it spends every client's bandwidth.*/
+#[derive(Debug, Clone, Resource)]
+pub struct ReplicationConfig {
+    /*Snapshots per second sent to every client, independent of the frame rate.*/
+    pub send_rate: f64,
+    /*Entities this close to the client's own entity are in every snapshot.*/
+    pub near_distance: f32,
+    /*Past near_distance the share of snapshots falls linearly, down to min_relevance from here on.*/
+    pub far_distance: f32,
+    pub min_relevance: f32,
+    /*Bytes of entity updates in one snapshot, well under available_bytes_per_tick of the connection so reliable messages still fit. What does not fit waits with a higher priority.*/
+    pub snapshot_budget: usize,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            send_rate: 30.,
+            near_distance: 60.,
+            far_distance: 300.,
+            min_relevance: 0.2,
+            snapshot_budget: 1200,
+        }
+    }
+}
+
+impl ReplicationConfig {
+    /*Share of the snapshots an entity at distance gets, None when the client has nothing to measure from.*/
+    pub fn relevance(&self, distance: Option<f32>) -> f32 {
+        let Some(distance) = distance else {
+            return 1.;
+        };
+        if distance <= self.near_distance {
+            1.
+        } else if distance >= self.far_distance {
+            self.min_relevance
+        } else {
+            let t = (distance - self.near_distance) / (self.far_distance - self.near_distance);
+            1. + (self.min_relevance - 1.) * t
+        }
+    }
+}
+
+/*
+Priority accumulation per client and entity. Every snapshot adds the relevance of an entity,
+it is due once that reaches 1 and goes back to 0 when it is sent, so a distant entity comes
+every few snapshots and one left out for lack of budget comes first the next time.
+*/
+#[derive(Debug, Default, Resource)]
+pub struct ReplicationPriorities {
+    priorities: HashMap<u64, HashMap<Entity, f32>>,
+    since_snapshot: f64,
+}
+
+impl ReplicationPriorities {
+    /*True when a snapshot is due this frame.*/
+    pub fn snapshot_due(&mut self, config: &ReplicationConfig, delta_seconds: f64) -> bool {
+        self.since_snapshot += delta_seconds;
+        let interval = 1. / config.send_rate;
+        if self.since_snapshot < interval {
+            return false;
+        }
+        // a long frame sends one snapshot, not a burst to catch up
+        self.since_snapshot = (self.since_snapshot - interval).min(interval);
+        true
+    }
+
+    /*Entities due for client_id, highest priority first. Entities missing from relevances are forgotten.*/
+    pub fn due(&mut self, client_id: u64, relevances: impl IntoIterator<Item = (Entity, f32)>) -> Vec<Entity> {
+        let priorities = self.priorities.entry(client_id).or_default();
+        let mut accumulated = HashMap::new();
+        for (entity, relevance) in relevances {
+            let priority = priorities.get(&entity).copied().unwrap_or(0.) + relevance;
+            accumulated.insert(entity, priority);
+        }
+        *priorities = accumulated;
+
+        let mut due: Vec<(Entity, f32)> = priorities
+            .iter()
+            .filter(|(_, priority)| **priority >= 1.)
+            .map(|(entity, priority)| (*entity, *priority))
+            .collect();
+        due.sort_by(|a, b| b.1.total_cmp(&a.1));
+        due.into_iter().map(|(entity, _)| entity).collect()
+    }
+
+    pub fn sent(&mut self, client_id: u64, entity: Entity) {
+        if let Some(priority) = self.priorities.get_mut(&client_id).and_then(|priorities| priorities.get_mut(&entity)) {
+            *priority = 0.;
+        }
+    }
+
+    /*Drop what is kept for clients that left.*/
+    pub fn retain_clients(&mut self, client_ids: &[u64]) {
+        self.priorities.retain(|client_id, _| client_ids.contains(client_id));
+    }
+}
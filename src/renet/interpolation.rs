@@ -0,0 +1,213 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+use crate::renet::clock::ServerClock;
+
+/*Transform of a replicated entity at a given server time.*/
+#[derive(Debug, Clone, Copy)]
+pub struct TransformSnapshot {
+    pub time: f64,
+    pub translation: Vec3,
+    pub rotation: Quat,
+}
+
+#[derive(Resource)]
+pub struct InterpolationConfig {
+    /*How far in the past (seconds) remote entities are rendered, must cover a couple of snapshots.*/
+    pub delay: f64,
+    /*How long (seconds) we keep extrapolating when snapshots stop arriving.*/
+    pub max_extrapolation: f64,
+    /*How many snapshots are kept per entity.*/
+    pub buffer_len: usize,
+}
+
+impl Default for InterpolationConfig {
+    fn default() -> Self {
+        Self {
+            delay: 0.1,
+            max_extrapolation: 0.25,
+            buffer_len: 32,
+        }
+    }
+}
+
+/*Maps local time to server time, from the timestamps carried by snapshots. Stands in until ServerClock is synced.*/
+#[derive(Resource, Default)]
+pub struct SnapshotClock {
+    offset: f64,
+    synced: bool,
+}
+
+impl SnapshotClock {
+    pub fn observe(&mut self, server_time: f64, local_time: f64) {
+        let sample = server_time - local_time;
+        if !self.synced {
+            self.offset = sample;
+            self.synced = true;
+        } else if sample > self.offset {
+            // A packet can only arrive late, never early, so jump forward but drift backward slowly.
+            self.offset = sample;
+        } else {
+            self.offset += (sample - self.offset) * 0.01;
+        }
+    }
+    pub fn server_time(&self, local_time: f64) -> f64 {
+        local_time + self.offset
+    }
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+}
+
+#[derive(Component, Default)]
+pub struct SnapshotBuffer {
+    pub snapshots: VecDeque<TransformSnapshot>,
+}
+
+impl SnapshotBuffer {
+    pub fn push(&mut self, snapshot: TransformSnapshot, max_len: usize) {
+        // Unreliable channel, drop anything older than what we already have.
+        if let Some(last) = self.snapshots.back() {
+            if snapshot.time <= last.time {
+                return;
+            }
+        }
+        self.snapshots.push_back(snapshot);
+        while self.snapshots.len() > max_len {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /*Interpolate between the snapshots surrounding render_time, extrapolate a little past the last one.*/
+    pub fn sample(&self, render_time: f64, max_extrapolation: f64) -> Option<(Vec3, Quat)> {
+        let first = self.snapshots.front()?;
+        if render_time <= first.time {
+            return Some((first.translation, first.rotation));
+        }
+        for (a, b) in self.snapshots.iter().zip(self.snapshots.iter().skip(1)) {
+            if render_time >= a.time && render_time <= b.time {
+                let t = ((render_time - a.time) / (b.time - a.time)) as f32;
+                return Some((
+                    a.translation.lerp(b.translation, t),
+                    a.rotation.slerp(b.rotation, t),
+                ));
+            }
+        }
+
+        let last = self.snapshots.back()?;
+        let Some(prev) = self.snapshots.iter().rev().nth(1) else {
+            return Some((last.translation, last.rotation));
+        };
+        let ahead = (render_time - last.time).min(max_extrapolation);
+        let t = (ahead / (last.time - prev.time)) as f32;
+        let turn = last.rotation * prev.rotation.inverse();
+        Some((
+            last.translation + (last.translation - prev.translation) * t,
+            (Quat::IDENTITY.slerp(turn, t) * last.rotation).normalize(),
+        ))
+    }
+}
+
+pub fn interpolate_snapshots_system(
+    time: Res<Time>,
+    clock: Res<SnapshotClock>,
+    server_clock: Res<ServerClock>,
+    config: Res<InterpolationConfig>,
+    mut query: Query<(&SnapshotBuffer, &mut Transform)>,
+) {
+    let now = time.elapsed_seconds_f64();
+    // a snapshot arrives about half a round trip after the server stamped it
+    let arrival_time = if server_clock.is_synced() {
+        server_clock.server_time(now) - server_clock.rtt() / 2.
+    } else if clock.is_synced() {
+        clock.server_time(now)
+    } else {
+        return;
+    };
+    let render_time = arrival_time - config.delay;
+    for (buffer, mut transform) in query.iter_mut() {
+        if let Some((translation, rotation)) = buffer.sample(render_time, config.max_extrapolation) {
+            transform.translation = translation;
+            transform.rotation = rotation;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(time: f64, x: f32) -> TransformSnapshot {
+        TransformSnapshot {
+            time,
+            translation: Vec3::new(x, 0., 0.),
+            rotation: Quat::from_rotation_y(x),
+        }
+    }
+
+    fn buffer(snapshots: &[TransformSnapshot]) -> SnapshotBuffer {
+        let mut buffer = SnapshotBuffer::default();
+        for snapshot in snapshots {
+            buffer.push(*snapshot, 32);
+        }
+        buffer
+    }
+
+    #[test]
+    fn empty_buffer_has_no_sample() {
+        assert!(SnapshotBuffer::default().sample(1., 0.25).is_none());
+    }
+
+    #[test]
+    fn interpolates_between_snapshots() {
+        let buffer = buffer(&[snapshot(1., 0.), snapshot(2., 1.), snapshot(3., 3.)]);
+        let (translation, rotation) = buffer.sample(1.5, 0.25).unwrap();
+        assert!((translation.x - 0.5).abs() < 1e-5);
+        assert!(rotation.angle_between(Quat::from_rotation_y(0.5)) < 1e-4);
+        let (translation, _) = buffer.sample(2.5, 0.25).unwrap();
+        assert!((translation.x - 2.).abs() < 1e-5);
+        let (translation, _) = buffer.sample(2., 0.25).unwrap();
+        assert!((translation.x - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn holds_the_first_snapshot_before_the_buffer() {
+        let buffer = buffer(&[snapshot(1., 2.), snapshot(2., 4.)]);
+        let (translation, rotation) = buffer.sample(0.5, 0.25).unwrap();
+        assert_eq!(translation.x, 2.);
+        assert!(rotation.angle_between(Quat::from_rotation_y(2.)) < 1e-4);
+    }
+
+    #[test]
+    fn extrapolates_after_the_buffer_up_to_the_limit() {
+        let buffer = buffer(&[snapshot(1., 0.), snapshot(2., 1.)]);
+        let (translation, rotation) = buffer.sample(2.1, 0.25).unwrap();
+        assert!((translation.x - 1.1).abs() < 1e-5);
+        assert!(rotation.angle_between(Quat::from_rotation_y(1.1)) < 1e-4);
+        // stops moving once max_extrapolation is reached
+        let (translation, _) = buffer.sample(10., 0.25).unwrap();
+        assert!((translation.x - 1.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn a_single_snapshot_is_held() {
+        let buffer = buffer(&[snapshot(1., 3.)]);
+        let (translation, _) = buffer.sample(5., 0.25).unwrap();
+        assert_eq!(translation.x, 3.);
+    }
+
+    #[test]
+    fn drops_late_and_overflowing_snapshots() {
+        let mut buffer = buffer(&[snapshot(1., 0.), snapshot(2., 1.)]);
+        buffer.push(snapshot(1.5, 9.), 32);
+        buffer.push(snapshot(2., 9.), 32);
+        assert_eq!(buffer.snapshots.len(), 2);
+        assert_eq!(buffer.snapshots.back().unwrap().translation.x, 1.);
+
+        for i in 3..10 {
+            buffer.push(snapshot(i as f64, i as f32), 4);
+        }
+        assert_eq!(buffer.snapshots.len(), 4);
+        assert_eq!(buffer.snapshots.front().unwrap().time, 6.);
+    }
+}
@@ -1,23 +1,112 @@
 use bevy::prelude::*;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use bevy_renet::renet::{ConnectionConfig,transport::NETCODE_KEY_BYTES};
 
 pub mod server;
 pub mod client;
+pub mod clock;
+pub mod conditioner;
+pub mod discovery;
+pub mod error;
+pub mod guard;
+pub mod handshake;
+pub mod interest;
+pub mod interpolation;
+pub mod profile;
+pub mod quantize;
+pub mod reconnect;
+pub mod token;
 
 use server::*;
 use client::*;
 
 use crate::game_asset::GameAssets;
+use crate::input::controller::TickedInputState;
+use crate::renet::profile::PlayerProfile;
+use crate::renet::conditioner::LinkConditions;
+use crate::renet::interest::ReplicationConfig;
 
 pub const PRIVATE_KEY: &[u8; NETCODE_KEY_BYTES] = b"an example very very secret key."; // 32-bytes
+/*Netcode drops a client of another protocol id without a word, so it stays put, handshake::PROTOCOL_VERSION tells builds apart.*/
 pub const PROTOCOL_ID: u64 = 7;
 
-#[derive(PartialEq)]
+/*Unsecure connections skip connect tokens, only meant for local development.*/
+pub fn unsecure_requested() -> bool {
+    std::env::var("RENET_UNSECURE").is_ok()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 pub enum NetworkMode
 {
     Standalone,
     Client,
-    Server
+    Server,
+    /*Listen server, the server also plays with a local car.*/
+    Host
+}
+
+impl std::str::FromStr for NetworkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "standalone" => Ok(NetworkMode::Standalone),
+            "client" => Ok(NetworkMode::Client),
+            "server" => Ok(NetworkMode::Server),
+            "host" => Ok(NetworkMode::Host),
+            other => Err(format!("unknown network mode {}", other)),
+        }
+    }
+}
+
+/*Where the server listens or the client connects, and who the client is.*/
+#[derive(Debug, Clone, Resource)]
+pub struct NetConfig {
+    /*Where the server binds, or the server a client connects to.*/
+    pub address: IpAddr,
+    pub port: u16,
+    /*Address clients reach the server on when it is not the bound one, behind NAT or bound to every interface.*/
+    pub public_address: Option<IpAddr>,
+    /*Remote clients let in, spectators included. The host player comes on top.*/
+    pub max_clients: usize,
+    pub profile: PlayerProfile,
+    /*UDP port of the LAN discovery, the same on every server and client. 0 turns discovery off.*/
+    pub discovery_port: u16,
+    /*Simulated latency and loss, None unless it was asked for at launch.*/
+    pub conditions: Option<LinkConditions>,
+    /*Snapshot rate and bandwidth of the server, per client.*/
+    pub replication: ReplicationConfig,
+    /*Seconds the server keeps the car of a dropped client for it to resume, 0 removes it at once.*/
+    pub resume_grace: f32,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 5000,
+            public_address: None,
+            max_clients: 64,
+            profile: PlayerProfile::default(),
+            discovery_port: discovery::DEFAULT_DISCOVERY_PORT,
+            conditions: None,
+            replication: ReplicationConfig::default(),
+            resume_grace: 30.,
+        }
+    }
+}
+
+impl NetConfig {
+    pub fn server_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+
+    /*What connect tokens list and LAN discovery advertises, the bound address unless a public one is set.*/
+    pub fn public_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.public_address.unwrap_or(self.address), self.port)
+    }
 }
 
 #[derive(Debug, Component)]
@@ -25,6 +114,19 @@ pub struct NetPlayer {
     pub id: u64,
 }
 
+/*Tick of the last input the server applied to this player, echoed back in every snapshot.*/
+#[derive(Debug, Default, Component)]
+pub struct LastInputTick(pub u32);
+
+/*Inputs of a player received and not simulated yet, applied one per tick like its client predicted them.*/
+#[derive(Debug, Default, Component)]
+pub struct InputQueue(pub VecDeque<TickedInputState>);
+
+/*True when tick a was sampled after tick b, tolerating wrap around.*/
+pub fn tick_after(a : u32,b : u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
 pub fn connection_config() -> ConnectionConfig {
     ConnectionConfig {
         available_bytes_per_tick: 1024 * 1024,
@@ -35,6 +137,34 @@ pub fn connection_config() -> ConnectionConfig {
 
 
 pub trait PlayerSpawner : Send + Sync + 'static {
-    fn spawn_authority(cmd: &mut Commands,game_asset : &Res<GameAssets>,archetype : u128,players : &Query<(Entity, &NetPlayer, &Transform)>) -> (bool,Entity,Transform);
-    fn spawn_proxy(cmd: &mut Commands,game_asset : &Res<GameAssets>,archetype : u128,transform : Transform,local_player : bool) -> (bool,Entity);
-}
\ No newline at end of file
+    /*game_asset is None on a headless server, the authority then has no visuals.*/
+    fn spawn_authority(cmd: &mut Commands,game_asset : Option<&GameAssets>,profile : &PlayerProfile,players : &Query<(Entity, &NetPlayer, &Transform)>) -> (bool,Entity,Transform);
+    /*Authority of the host player in host mode, driven by the local input instead of the network.*/
+    fn spawn_local(cmd: &mut Commands,game_asset : &GameAssets,profile : &PlayerProfile,players : &Query<(Entity, &NetPlayer, &Transform)>) -> (bool,Entity,Transform);
+    /*game_asset is None on a headless client, the proxy then has no visuals.*/
+    fn spawn_proxy(cmd: &mut Commands,game_asset : Option<&GameAssets>,profile : &PlayerProfile,transform : Transform,local_player : bool) -> (bool,Entity);
+    /*Why the player of profile could not be spawned, checked before the client is let in so it is refused with the reason.*/
+    fn check_profile(profile : &PlayerProfile) -> Result<(), String>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tick_after_orders_ticks() {
+        assert!(tick_after(2, 1));
+        assert!(!tick_after(1, 2));
+        assert!(!tick_after(5, 5));
+    }
+
+    #[test]
+    fn tick_after_wraps_around() {
+        assert!(tick_after(0, u32::MAX));
+        assert!(tick_after(3, u32::MAX - 3));
+        assert!(!tick_after(u32::MAX, 0));
+        // half the range away is as far as the order is known
+        assert!(tick_after(i32::MAX as u32, 0));
+        assert!(!tick_after(i32::MAX as u32 + 1, 0));
+    }
+}
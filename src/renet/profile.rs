@@ -0,0 +1,80 @@
+use bevy_renet::renet::transport::NETCODE_USER_DATA_BYTES;
+use serde::{Deserialize, Serialize};
+
+use crate::renet::error::NetError;
+
+pub const MAX_NAME_CHARS: usize = 20;
+
+/*Who a player is, sent once in the netcode user data of the handshake.*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerProfile {
+    pub name: String,
+    pub color: [u8; 3],
+    pub archetype: u128,
+    /*Spectators get every car replicated but no car of their own.*/
+    pub spectator: bool,
+}
+
+impl Default for PlayerProfile {
+    fn default() -> Self {
+        Self {
+            name: "Player".to_string(),
+            color: [255, 255, 255],
+            archetype: 0,
+            spectator: false,
+        }
+    }
+}
+
+impl PlayerProfile {
+    /*Stand-in for clients that connect without any user data.*/
+    pub fn anonymous(client_id: u64) -> Self {
+        Self {
+            name: format!("Player {}", client_id % 10000),
+            ..Default::default()
+        }
+    }
+
+    pub fn to_user_data(&self) -> Result<[u8; NETCODE_USER_DATA_BYTES], NetError> {
+        let bytes = bincode::serialize(self).map_err(NetError::Encode)?;
+        if bytes.len() > NETCODE_USER_DATA_BYTES {
+            return Err(NetError::InvalidProfile("profile does not fit in the user data"));
+        }
+        let mut user_data = [0u8; NETCODE_USER_DATA_BYTES];
+        user_data[..bytes.len()].copy_from_slice(&bytes);
+        Ok(user_data)
+    }
+
+    /*Decode and validate what a client sent, all zero user data gets an anonymous profile.*/
+    pub fn from_user_data(client_id: u64, user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> Result<Self, NetError> {
+        if user_data.iter().all(|b| *b == 0) {
+            return Ok(Self::anonymous(client_id));
+        }
+        let mut profile: Self = bincode::deserialize(user_data).map_err(NetError::Decode)?;
+        profile.name = profile.name.trim().to_string();
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    pub fn validate(&self) -> Result<(), NetError> {
+        if self.name.is_empty() {
+            return Err(NetError::InvalidProfile("empty name"));
+        }
+        if self.name.chars().count() > MAX_NAME_CHARS {
+            return Err(NetError::InvalidProfile("name too long"));
+        }
+        if self.name.chars().any(|c| c.is_control()) {
+            return Err(NetError::InvalidProfile("name has control characters"));
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_color(value: &str) -> Option<[u8; 3]> {
+    let value = value.trim().trim_start_matches('#');
+    if value.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(value, 16).ok()?;
+    Some([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+}
@@ -0,0 +1,184 @@
+use bevy::prelude::*;
+use std::f32::consts::{FRAC_1_SQRT_2, TAU};
+
+/*World volume positions are quantized into, anything outside is clamped to the border.*/
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct QuantizationBounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl QuantizationBounds {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /*Bounds enclosing every point, grown by margin on each side.*/
+    pub fn from_points(points: impl Iterator<Item = Vec3>, margin: Vec3) -> Self {
+        let (min, max) = points.fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), p| (min.min(p), max.max(p)),
+        );
+        Self::new(min - margin, max + margin)
+    }
+
+    pub fn quantize_position(&self, position: Vec3) -> [u16; 3] {
+        let size = (self.max - self.min).max(Vec3::splat(f32::EPSILON));
+        let unit = ((position - self.min) / size).clamp(Vec3::ZERO, Vec3::ONE);
+        (unit * u16::MAX as f32).round().to_array().map(|v| v as u16)
+    }
+
+    pub fn dequantize_position(&self, quantized: [u16; 3]) -> Vec3 {
+        let unit = Vec3::from_array(quantized.map(|v| v as f32)) / u16::MAX as f32;
+        self.min + unit * (self.max - self.min)
+    }
+}
+
+const QUAT_COMPONENT_BITS: u32 = 10;
+const QUAT_COMPONENT_MAX: u32 = (1 << QUAT_COMPONENT_BITS) - 1;
+const QUAT_COMPONENT_SHIFTS: [u32; 3] = [2 * QUAT_COMPONENT_BITS, QUAT_COMPONENT_BITS, 0];
+
+/*
+Smallest three : the largest component is dropped (rebuilt from the unit length) and its index
+stored in the 2 high bits, the three others are in [-1/sqrt(2), 1/sqrt(2)] and take 10 bits each.
+*/
+pub fn compress_quat(rotation: Quat) -> u32 {
+    let components = rotation.normalize().to_array();
+    let mut largest = 0;
+    for i in 1..4 {
+        if components[i].abs() > components[largest].abs() {
+            largest = i;
+        }
+    }
+    // q and -q are the same rotation, flip so the dropped component is positive.
+    let sign = if components[largest] < 0. { -1. } else { 1. };
+
+    let mut packed = (largest as u32) << (3 * QUAT_COMPONENT_BITS);
+    let mut shifts = QUAT_COMPONENT_SHIFTS.iter();
+    for (i, component) in components.iter().enumerate() {
+        if i == largest {
+            continue;
+        }
+        let unit = ((component * sign).clamp(-FRAC_1_SQRT_2, FRAC_1_SQRT_2) + FRAC_1_SQRT_2)
+            / (2. * FRAC_1_SQRT_2);
+        let quantized = (unit * QUAT_COMPONENT_MAX as f32).round() as u32;
+        packed |= quantized << shifts.next().unwrap();
+    }
+    packed
+}
+
+pub fn decompress_quat(packed: u32) -> Quat {
+    let largest = (packed >> (3 * QUAT_COMPONENT_BITS)) as usize & 3;
+    let mut components = [0f32; 4];
+    let mut sum_sq = 0.;
+    let mut shifts = QUAT_COMPONENT_SHIFTS.iter();
+    for (i, component) in components.iter_mut().enumerate() {
+        if i == largest {
+            continue;
+        }
+        let quantized = (packed >> shifts.next().unwrap()) & QUAT_COMPONENT_MAX;
+        let unit = quantized as f32 / QUAT_COMPONENT_MAX as f32;
+        *component = unit * 2. * FRAC_1_SQRT_2 - FRAC_1_SQRT_2;
+        sum_sq += *component * *component;
+    }
+    components[largest] = (1. - sum_sq).max(0.).sqrt();
+    Quat::from_array(components).normalize()
+}
+
+/*Map a value in [-range, range] to the full i16 range.*/
+pub fn quantize_f32(value: f32, range: f32) -> i16 {
+    ((value / range).clamp(-1., 1.) * i16::MAX as f32).round() as i16
+}
+
+pub fn dequantize_f32(quantized: i16, range: f32) -> f32 {
+    quantized as f32 / i16::MAX as f32 * range
+}
+
+pub fn quantize_vec3(value: Vec3, range: f32) -> [i16; 3] {
+    value.to_array().map(|v| quantize_f32(v, range))
+}
+
+pub fn dequantize_vec3(quantized: [i16; 3], range: f32) -> Vec3 {
+    Vec3::from_array(quantized.map(|v| dequantize_f32(v, range)))
+}
+
+/*Any angle, wrapped to one turn and spread over the u16 range.*/
+pub fn quantize_angle(angle: f32) -> u16 {
+    ((angle.rem_euclid(TAU) / TAU) * 65536.).round() as u32 as u16
+}
+
+pub fn dequantize_angle(quantized: u16) -> f32 {
+    quantized as f32 / 65536. * TAU
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    // 10 bits over [-1/sqrt(2), 1/sqrt(2)], well under a degree
+    const QUAT_TOLERANCE: f32 = 0.01;
+
+    fn assert_round_trip(rotation: Quat) {
+        let decompressed = decompress_quat(compress_quat(rotation));
+        assert!(
+            rotation.angle_between(decompressed) < QUAT_TOLERANCE,
+            "{rotation:?} came back as {decompressed:?}"
+        );
+    }
+
+    #[test]
+    fn quat_round_trip() {
+        assert_round_trip(Quat::IDENTITY);
+        assert_round_trip(Quat::from_euler(EulerRot::YXZ, 0.3, -1.2, 2.5));
+        assert_round_trip(Quat::from_axis_angle(Vec3::new(1., 2., -3.).normalize(), 1.7));
+    }
+
+    #[test]
+    fn quat_round_trip_negative_w() {
+        // -q is the same rotation as q, whichever sign comes out
+        let rotation = Quat::from_euler(EulerRot::YXZ, 0.3, -1.2, 2.5);
+        assert_round_trip(-rotation);
+        assert_round_trip(-Quat::IDENTITY);
+        let decompressed = decompress_quat(compress_quat(-Quat::IDENTITY));
+        assert!((decompressed.w.abs() - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn quat_round_trip_near_axis() {
+        // half turns drop w, the largest component is then x, y or z
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            assert_round_trip(Quat::from_axis_angle(axis, PI));
+            assert_round_trip(Quat::from_axis_angle(axis, PI - 0.001));
+            assert_round_trip(Quat::from_axis_angle(axis, -PI + 0.001));
+            assert_round_trip(Quat::from_axis_angle(axis, 0.001));
+            assert_round_trip(Quat::from_axis_angle(axis, FRAC_PI_2));
+        }
+    }
+
+    #[test]
+    fn position_round_trip_inside_bounds() {
+        let bounds = QuantizationBounds::new(Vec3::new(-100., -10., -50.), Vec3::new(100., 40., 50.));
+        let position = Vec3::new(12.34, 5.6, -49.);
+        let dequantized = bounds.dequantize_position(bounds.quantize_position(position));
+        // 200 m over 16 bits
+        assert!((dequantized - position).abs().max_element() < 0.005);
+    }
+
+    #[test]
+    fn position_clamps_to_bounds() {
+        let bounds = QuantizationBounds::new(Vec3::new(-100., -10., -50.), Vec3::new(100., 40., 50.));
+        assert_eq!(bounds.quantize_position(bounds.min), [0; 3]);
+        assert_eq!(bounds.quantize_position(bounds.max), [u16::MAX; 3]);
+        assert_eq!(bounds.quantize_position(Vec3::new(-1000., -1000., -1000.)), [0; 3]);
+        assert_eq!(bounds.quantize_position(Vec3::new(1000., 1000., 1000.)), [u16::MAX; 3]);
+        let clamped = bounds.dequantize_position(bounds.quantize_position(Vec3::new(500., 0., -500.)));
+        assert!((clamped - Vec3::new(100., 0., -50.)).abs().max_element() < 0.005);
+    }
+
+    #[test]
+    fn angle_wraps() {
+        assert_eq!(quantize_angle(0.), quantize_angle(TAU));
+        assert_eq!(quantize_angle(-FRAC_PI_2), quantize_angle(3. * FRAC_PI_2));
+    }
+}
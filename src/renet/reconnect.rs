@@ -0,0 +1,276 @@
+use bevy::prelude::*;
+use bevy_renet::renet::{RenetClient, transport::NetcodeTransportError};
+use std::net::SocketAddr;
+
+#[cfg(feature = "graphics")]
+use crate::game_asset::GameAssets;
+use crate::renet::NetConfig;
+use crate::renet::conditioner::LinkConditioner;
+use crate::renet::client::{new_renet_client, ClientIdentity, ClientLobby, ClientSocket, NetworkMapping, SnapshotAck};
+use crate::renet::handshake::ServerHandshake;
+use crate::renet::error::NetworkError;
+use crate::renet::token::TokenError;
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionStatus {
+    /*Waiting for the handshake, attempt is 0 for the very first connection.*/
+    Connecting { attempt: u32 },
+    Connected,
+    /*Lost the server, next attempt at retry_at (seconds of app time).*/
+    Reconnecting { attempt: u32, retry_at: f64 },
+    /*The server would not have us, see ServerHandshake::refusal. Only joining a server gets out of it.*/
+    Refused,
+    /*The connect token ran out, retrying with it is pointless. Only a fresh token gets out of it.*/
+    TokenExpired,
+}
+
+impl Default for ConnectionStatus {
+    fn default() -> Self {
+        ConnectionStatus::Connecting { attempt: 0 }
+    }
+}
+
+#[derive(Resource)]
+pub struct ReconnectConfig {
+    /*Delay before the first retry, doubled on every failed attempt.*/
+    pub base_delay: f64,
+    pub max_delay: f64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            base_delay: 0.5,
+            max_delay: 10.,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    pub fn delay(&self, attempt: u32) -> f64 {
+        (self.base_delay * 2f64.powi(attempt.min(16) as i32)).min(self.max_delay)
+    }
+}
+
+#[derive(Component)]
+pub struct ReconnectingText;
+
+/*Drop the current connection and join another server, like a server picked in the menu.*/
+#[derive(Debug, Event)]
+pub struct JoinServer(pub SocketAddr);
+
+/*Track the connection, a transport error or a dropped connection schedules a reconnect.*/
+pub fn client_connection_status_system(
+    mut transport_errors: EventReader<NetcodeTransportError>,
+    client: Res<RenetClient>,
+    time: Res<Time>,
+    config: Res<ReconnectConfig>,
+    handshake: Res<ServerHandshake>,
+    mut status: ResMut<ConnectionStatus>,
+) {
+    if handshake.refusal.is_some() {
+        if *status != ConnectionStatus::Refused {
+            *status = ConnectionStatus::Refused;
+        }
+        return;
+    }
+    let mut failed = false;
+    for e in transport_errors.iter() {
+        error!("Network error: {}", e);
+        failed = true;
+    }
+    if client.is_disconnected() {
+        failed = true;
+    }
+
+    match *status {
+        ConnectionStatus::Connecting { attempt } => {
+            if failed {
+                let delay = config.delay(attempt);
+                warn!("Connection attempt {} failed, retrying in {:.1}s", attempt + 1, delay);
+                *status = ConnectionStatus::Reconnecting {
+                    attempt: attempt + 1,
+                    retry_at: time.elapsed_seconds_f64() + delay,
+                };
+            } else if client.is_connected() {
+                info!("Connected to server");
+                *status = ConnectionStatus::Connected;
+            }
+        }
+        ConnectionStatus::Connected => {
+            if failed {
+                warn!("Lost connection to server: {:?}", client.disconnect_reason());
+                *status = ConnectionStatus::Reconnecting {
+                    attempt: 1,
+                    retry_at: time.elapsed_seconds_f64() + config.delay(0),
+                };
+            }
+        }
+        ConnectionStatus::Reconnecting { .. } | ConnectionStatus::Refused | ConnectionStatus::TokenExpired => {}
+    }
+}
+
+/*
+Once the backoff expired, open a fresh connection with the same identity.
+Known players are kept but marked stale, client_sync_players re-binds them to their new server
+entity when the server announces them again and drops whatever was not announced. A spectator
+has no car of its own to mark the end of the announcements, it drops them all once reconnected.
+*/
+pub fn client_reconnect_system(
+    mut cmd: Commands,
+    time: Res<Time>,
+    identity: Res<ClientIdentity>,
+    net_config: Res<NetConfig>,
+    mut status: ResMut<ConnectionStatus>,
+    mut handshake: ResMut<ServerHandshake>,
+    mut lobby: ResMut<ClientLobby>,
+    mut network_mapping: ResMut<NetworkMapping>,
+    mut snapshot_ack: ResMut<SnapshotAck>,
+    mut socket: ResMut<ClientSocket>,
+    mut conditioner: Option<ResMut<LinkConditioner>>,
+    config: Res<ReconnectConfig>,
+) {
+    let ConnectionStatus::Reconnecting { attempt, retry_at } = *status else {
+        return;
+    };
+    if time.elapsed_seconds_f64() < retry_at {
+        return;
+    }
+
+    info!("Reconnecting to server, attempt {}", attempt);
+    for player in lobby.players.values_mut() {
+        player.stale = true;
+    }
+    network_mapping.0.clear();
+    snapshot_ack.0 = 0;
+    *handshake = ServerHandshake::default();
+
+    *status = connect(&mut cmd, &identity, &net_config, &mut socket, conditioner.as_deref_mut(), attempt, time.elapsed_seconds_f64(), &config);
+}
+
+/*
+Open a connection, the status to carry on with. Connect tokens are read again on every attempt,
+an expired one is final while a missing or garbled one may be fixed before the next attempt.
+Every attempt goes out from the same socket, the server knows a token by the address it came from.
+*/
+fn connect(
+    cmd: &mut Commands,
+    identity: &ClientIdentity,
+    net_config: &NetConfig,
+    socket: &mut ClientSocket,
+    conditioner: Option<&mut LinkConditioner>,
+    attempt: u32,
+    now: f64,
+    config: &ReconnectConfig,
+) -> ConnectionStatus {
+    match new_renet_client(identity, net_config, socket, conditioner) {
+        Ok((client, transport)) => {
+            cmd.insert_resource(client);
+            cmd.insert_resource(transport);
+            ConnectionStatus::Connecting { attempt }
+        }
+        Err(e) => connection_failed(e, attempt, now, config),
+    }
+}
+
+/*Status after a connection could not even be set up, a retry unless the token expired.*/
+pub(crate) fn connection_failed(e: NetworkError, attempt: u32, now: f64, config: &ReconnectConfig) -> ConnectionStatus {
+    if let NetworkError::Token(TokenError::Expired) = e {
+        error!("{}", e);
+        return ConnectionStatus::TokenExpired;
+    }
+    let delay = config.delay(attempt);
+    error!("Can not connect: {}, retrying in {:.1}s", e, delay);
+    ConnectionStatus::Reconnecting {
+        attempt: attempt + 1,
+        retry_at: now + delay,
+    }
+}
+
+pub fn client_join_server_system(
+    mut cmd: Commands,
+    time: Res<Time>,
+    config: Res<ReconnectConfig>,
+    identity: Res<ClientIdentity>,
+    mut join_events: EventReader<JoinServer>,
+    mut net_config: ResMut<NetConfig>,
+    mut status: ResMut<ConnectionStatus>,
+    mut handshake: ResMut<ServerHandshake>,
+    mut lobby: ResMut<ClientLobby>,
+    mut network_mapping: ResMut<NetworkMapping>,
+    mut snapshot_ack: ResMut<SnapshotAck>,
+    mut socket: ResMut<ClientSocket>,
+    mut conditioner: Option<ResMut<LinkConditioner>>,
+) {
+    let Some(JoinServer(addr)) = join_events.iter().last() else {
+        return;
+    };
+
+    info!("Joining server {}", addr);
+    net_config.address = addr.ip();
+    net_config.port = addr.port();
+    for (_, player_info) in lobby.players.drain() {
+        cmd.entity(player_info.client_entity).despawn_recursive();
+    }
+    network_mapping.0.clear();
+    snapshot_ack.0 = 0;
+    *handshake = ServerHandshake::default();
+
+    *status = connect(&mut cmd, &identity, &net_config, &mut socket, conditioner.as_deref_mut(), 0, time.elapsed_seconds_f64(), &config);
+}
+
+#[cfg(feature = "graphics")]
+pub fn client_reconnect_ui_system(
+    mut cmd: Commands,
+    status: Res<ConnectionStatus>,
+    handshake: Res<ServerHandshake>,
+    game_asset: Res<GameAssets>,
+    mut texts: Query<(Entity, &mut Text), With<ReconnectingText>>,
+) {
+    if !status.is_changed() {
+        return;
+    }
+    let message = match *status {
+        ConnectionStatus::Connected => None,
+        ConnectionStatus::Refused => handshake.refusal.as_ref().map(|reason| format!("Refused by the server: {}", reason)),
+        ConnectionStatus::TokenExpired => Some("Connect token expired, restart with a fresh one".to_string()),
+        ConnectionStatus::Connecting { attempt: 0 } => None,
+        ConnectionStatus::Connecting { attempt } | ConnectionStatus::Reconnecting { attempt, .. } => {
+            Some(format!("Reconnecting... (attempt {})", attempt))
+        }
+    };
+
+    let Some(message) = message else {
+        for (entity, _) in texts.iter() {
+            cmd.entity(entity).despawn_recursive();
+        }
+        return;
+    };
+    if let Ok((_, mut text)) = texts.get_single_mut() {
+        text.sections[0].value = message;
+        return;
+    }
+    cmd.spawn(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(45.),
+            width: Val::Percent(100.),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        text: Text {
+            alignment: TextAlignment::Center,
+            sections: vec![TextSection {
+                value: message,
+                style: TextStyle {
+                    font: game_asset.dash_font.clone(),
+                    font_size: 32.0,
+                    color: Color::SALMON,
+                },
+            }],
+            ..default()
+        },
+        ..default()
+    })
+    .insert(ReconnectingText);
+}
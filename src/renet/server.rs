@@ -2,12 +2,23 @@ use bevy::prelude::*;
 use bincode::Error;
 use serde::{Deserialize, Serialize};
 use std::{time::Duration, marker::PhantomData};
+#[cfg(feature = "graphics")]
 use bevy_egui::EguiPlugin;
 
 use crate::game_asset::GameAssets;
 use crate::gamestate::GameState;
-use crate::renet::{NetPlayer,PlayerSpawner};
+use crate::renet::{NetConfig,NetPlayer,LastInputTick,InputQueue,PlayerSpawner};
 use crate::renet::client::ClientChannel;
+use crate::renet::error::{decode, encode, NetError, NetworkError};
+use crate::renet::guard::{TrafficGuard, TrafficLimits};
+use crate::renet::conditioner::{loopback, LinkConditioner};
+use crate::renet::clock::server_clock_system;
+use crate::renet::handshake::{check_hello, ClientHello, Features, ServerHello, BUILD_VERSION, HANDSHAKE_TIMEOUT, PROTOCOL_VERSION, REFUSAL_GRACE};
+use crate::renet::interest::ReplicationPriorities;
+use crate::renet::discovery::{new_discovery_responder, server_discovery_system};
+use crate::renet::profile::PlayerProfile;
+use crate::race::RaceMessage;
+use crate::chat::ChatMessage;
 
 use bevy_renet::{
     renet::{
@@ -18,24 +29,29 @@ use bevy_renet::{
     RenetServerPlugin,
 };
 
+/*An Err means the client sent something we can not make sense of, it gets kicked.*/
 pub trait PlayerCommandProcessor : Send + Sync + 'static {
-    fn process_command(client_id : u64,cmd: &mut Commands,message : &Bytes,lobby : &mut ResMut<ServerLobby>);
+    fn process_command(client_id : u64,cmd: &mut Commands,message : &Bytes,lobby : &mut ResMut<ServerLobby>) -> Result<(),NetError>;
 }  
 
 pub struct NullPlayerCommandProcessor;
 impl PlayerCommandProcessor for NullPlayerCommandProcessor{
-    fn process_command(_client_id : u64,_cmd: &mut Commands,_message : &Bytes,_lobby : &mut ResMut<ServerLobby>){}
+    fn process_command(_client_id : u64,_cmd: &mut Commands,_message : &Bytes,_lobby : &mut ResMut<ServerLobby>) -> Result<(),NetError> { Ok(()) }
 }
 
 pub struct NullPlayerInputProcessor;
 impl PlayerCommandProcessor for NullPlayerInputProcessor{
-    fn process_command(_client_id : u64,_cmd: &mut Commands,_message : &Bytes,_lobby : &mut ResMut<ServerLobby>){}
+    fn process_command(_client_id : u64,_cmd: &mut Commands,_message : &Bytes,_lobby : &mut ResMut<ServerLobby>) -> Result<(),NetError> { Ok(()) }
 }
 
 
 pub enum ServerChannel {
     ServerMessages,
     NetworkedEntities,
+    /*Replies to the clock sync pings of ClientChannel::Clock.*/
+    Clock,
+    /*ServerHello, the answer to the ClientHello of a new client.*/
+    Handshake,
 }
 
 
@@ -47,10 +63,13 @@ pub enum ServerMessages {
         translation: [f32; 3],
         rotation:  [f32; 4],
         scale:[f32; 3],
+        profile: PlayerProfile,
     },
     PlayerRemove {
         id: u64,
     },
+    Race(RaceMessage),
+    Chat(ChatMessage),
 }
 
 impl From<ServerChannel> for u8 {
@@ -58,6 +77,8 @@ impl From<ServerChannel> for u8 {
         match channel_id {
             ServerChannel::NetworkedEntities => 0,
             ServerChannel::ServerMessages => 1,
+            ServerChannel::Clock => 2,
+            ServerChannel::Handshake => 3,
         }
     }
 }
@@ -77,46 +98,122 @@ impl ServerChannel {
                     resend_time: Duration::from_millis(200),
                 },
             },
+            ChannelConfig {
+                channel_id: Self::Clock.into(),
+                max_memory_usage_bytes: 1024 * 1024,
+                send_type: SendType::Unreliable,
+            },
+            ChannelConfig {
+                channel_id: Self::Handshake.into(),
+                max_memory_usage_bytes: 1024 * 1024,
+                send_type: SendType::ReliableOrdered {
+                    resend_time: Duration::from_millis(200),
+                },
+            },
         ]
     }
 }
-use crate::renet::{connection_config, PROTOCOL_ID};
+use crate::renet::{connection_config, unsecure_requested, PROTOCOL_ID};
+use crate::renet::token::private_key_from_env;
 use std::{collections::HashMap, net::UdpSocket, time::SystemTime};
 
 use crate::input::controller::*;
 
+/*Client id of the player on the server itself in host mode, refused for remote clients.*/
+pub const HOST_CLIENT_ID: u64 = 0;
+
+/*Inputs queued beyond that many ticks are dropped, the player would lag further and further behind.*/
+pub const MAX_QUEUED_INPUTS: usize = 6;
+
+/*Applies the queued inputs in FixedUpdate, the game simulates the tick after it.*/
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemSet)]
+pub struct ServerInputSet;
+
+/*Present when the server also plays, see NetServerPlugin::host.*/
+#[derive(Debug, Default, Resource)]
+pub struct HostPlayer;
+
+/*Counts the FixedUpdate ticks simulated by the server, every snapshot is stamped with the tick it shows.*/
+#[derive(Debug, Default, Resource)]
+pub struct ServerTick(pub u32);
+
+pub fn server_tick_system(mut server_tick: ResMut<ServerTick>) {
+    server_tick.0 = server_tick.0.wrapping_add(1);
+}
+
 #[derive(Debug, Default, Resource)]
 pub struct ServerLobby {
     pub players: HashMap<u64, Entity>,
+    /*Validated profile of every connected client, from the handshake user data.*/
+    pub profiles: HashMap<u64, PlayerProfile>,
+    /*Last snapshot tick each client acknowledged, the baseline for its deltas.*/
+    pub snapshot_acks: HashMap<u64, u32>,
+    /*Players whose client dropped, their car and profile are kept until this server time so they can resume.*/
+    pub suspended: HashMap<u64, f64>,
+    /*Clients connected but not joined yet, they have until this server time to say hello.*/
+    pub handshakes: HashMap<u64, f64>,
+    /*Clients told why they can not join, kicked at this server time if they are still there.*/
+    pub refused: HashMap<u64, (f64, String)>,
+    /*Features every joined client speaks along with the server.*/
+    pub features: HashMap<u64, Features>,
+}
+
+/*A client passed the handshake and is in the lobby, as a driver or a spectator.*/
+#[derive(Debug, Clone, Copy, Event)]
+pub struct PlayerJoined {
+    pub client_id: u64,
+}
+
+/*A player left for good, when its client disconnected or once its resume grace period ran out.*/
+#[derive(Debug, Clone, Copy, Event)]
+pub struct PlayerLeft {
+    pub client_id: u64,
 }
 
-fn new_renet_server() -> (RenetServer, NetcodeServerTransport) {
+fn new_renet_server(net_config : &NetConfig,conditioner : Option<&mut LinkConditioner>) -> Result<(RenetServer, NetcodeServerTransport), NetworkError> {
     let server = RenetServer::new(connection_config());
 
-    let addr = if let Ok(addr) = std::env::var("RENET_SERVER_SOCKET") {
-        addr
+    let bind_addr = net_config.server_addr();
+    let public_addr = net_config.public_addr();
+    if public_addr == bind_addr {
+        println!("Listening on {}", bind_addr);
     } else {
-        let default = "127.0.0.1:5000".to_string();
-        println!("RENET_SERVER_SOCKET not set, setting default: {}", &default);
-        default
+        println!("Listening on {}, clients connect to {}", bind_addr, public_addr);
+    }
+    let socket = match conditioner {
+        // the relay takes the bound address and the transport listens behind it
+        Some(conditioner) => {
+            let socket = UdpSocket::bind(loopback(bind_addr)).map_err(NetworkError::Socket)?;
+            let local_addr = socket.local_addr().map_err(NetworkError::Socket)?;
+            conditioner.relay(bind_addr, local_addr).map_err(NetworkError::Relay)?;
+            println!("Simulating network conditions for every client");
+            socket
+        }
+        None => UdpSocket::bind(bind_addr).map_err(NetworkError::Socket)?,
     };
-
-    let public_addr = addr.parse().unwrap();
-    let socket = UdpSocket::bind(public_addr).unwrap();
     let current_time: std::time::Duration = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
+    let authentication = if unsecure_requested() {
+        println!("RENET_UNSECURE set, accepting clients without connect token");
+        ServerAuthentication::Unsecure
+    } else {
+        // never fall back to the example key, anyone could sign tokens for this server
+        ServerAuthentication::Secure {
+            private_key: private_key_from_env()?,
+        }
+    };
     let server_config = ServerConfig {
         current_time,
-        max_clients: 64,
+        max_clients: net_config.max_clients,
         protocol_id: PROTOCOL_ID,
         public_addresses: vec![public_addr],
-        authentication: ServerAuthentication::Unsecure,
+        authentication,
     };
 
-    let transport = NetcodeServerTransport::new(server_config, socket).unwrap();
+    let transport = NetcodeServerTransport::new(server_config, socket).map_err(NetworkError::Socket)?;
 
-    (server, transport)
+    Ok((server, transport))
 }
 
 #[cfg(feature = "graphics")]
@@ -124,13 +221,26 @@ fn update_visulizer_system(
     mut egui_contexts: bevy_egui::EguiContexts,
     mut visualizer: ResMut<renet_visualizer::RenetServerVisualizer<200>>,
     server: Res<RenetServer>,
+    host: Option<Res<HostPlayer>>,
+    conditioner: Option<Res<LinkConditioner>>,
+    mut toggled: Local<bool>,
+    keyboard_input: Res<Input<KeyCode>>,
 ) {
     visualizer.update(&server);
-    visualizer.show_window(egui_contexts.ctx_mut());
+    if keyboard_input.just_pressed(KeyCode::F1) {
+        *toggled = !*toggled;
+    }
+    // Always up on a dedicated server, behind F1 when the host is playing.
+    if *toggled == host.is_some() {
+        visualizer.show_window(egui_contexts.ctx_mut());
+        if let Some(conditioner) = conditioner {
+            crate::renet::conditioner::link_conditioner_window(egui_contexts.ctx_mut(), &conditioner);
+        }
+    }
 }
 
 /*Form player create message*/
-fn form_player_create_message(id : u64,entity : Entity,transform : Transform) -> Result<Vec<u8>,Error> {
+fn form_player_create_message(id : u64,entity : Entity,transform : Transform,profile : &PlayerProfile) -> Result<Vec<u8>,Error> {
     let translation: [f32; 3] = transform.translation.into();
     let rotation: [f32; 4] = transform.rotation.to_array();
     let scale: [f32; 3] = transform.scale.into();
@@ -140,95 +250,376 @@ fn form_player_create_message(id : u64,entity : Entity,transform : Transform) ->
         entity,
         translation,
         rotation,
-        scale
+        scale,
+        profile: profile.clone(),
     })
 }
 
 pub fn server_process_client_connections<PS : PlayerSpawner>(
     mut server_events: EventReader<ServerEvent>,
+    mut players_joined: EventWriter<PlayerJoined>,
+    mut players_left: EventWriter<PlayerLeft>,
     mut cmd: Commands,
-    game_asset : Res<GameAssets>,
+    time: Res<Time>,
+    game_asset : Option<Res<GameAssets>>,
     mut lobby: ResMut<ServerLobby>,
     mut server: ResMut<RenetServer>,
+    transport: Res<NetcodeServerTransport>,
+    mut guard: ResMut<TrafficGuard>,
+    net_config: Res<NetConfig>,
+    host: Option<Res<HostPlayer>>,
     players: Query<(Entity, &NetPlayer, &Transform)>,
     #[cfg(feature = "graphics")] 
-    mut visualizer: ResMut<renet_visualizer::RenetServerVisualizer<200>>,
+    mut visualizer: Option<ResMut<renet_visualizer::RenetServerVisualizer<200>>>,
 ) 
 {
+    let now = time.elapsed_seconds_f64();
     for event in server_events.iter() {
         match event {
             ServerEvent::ClientConnected { client_id } => {
-                println!("Player {} connected.", client_id);
                 #[cfg(feature = "graphics")]
-                visualizer.add_client(*client_id);
-
-                for (entity, player, transform) in players.iter() {
-                    let message = form_player_create_message(player.id,entity,*transform).unwrap();
-                    server.send_message(*client_id, ServerChannel::ServerMessages, message);
-                }
-
-                let archetype : u128 = 0;
-                let (success,entity,transform) = PS::spawn_authority(&mut cmd,&game_asset,archetype,&players);
-                if success
-                {
-                    cmd.entity(entity)
-                    .insert(NetPlayer { id: *client_id })
-                    .insert(PlayerInputState::default());
-
-                    lobby.players.insert(*client_id, entity);
-                    let message = form_player_create_message(*client_id,entity,transform).unwrap();
-                    server.broadcast_message(ServerChannel::ServerMessages, message);
-                }
-                else {
-                    error!("Can not spawn player entity with Arch : {} , for Client {}",archetype,client_id);
+                if let Some(visualizer) = visualizer.as_mut() {
+                    visualizer.add_client(*client_id);
                 }
-                
+                // Nothing is sent its way until it said which protocol it speaks.
+                lobby.handshakes.insert(*client_id, now + HANDSHAKE_TIMEOUT);
             }
             ServerEvent::ClientDisconnected { client_id, reason } => {
-                println!("Player {} disconnected: {}", client_id, reason);
+                let kicked = match guard.remove_client(*client_id) {
+                    Some(kick_reason) => {
+                        println!("Player {} disconnected: kicked, {}", client_id, kick_reason);
+                        true
+                    }
+                    None => {
+                        println!("Player {} disconnected: {}", client_id, reason);
+                        false
+                    }
+                };
                 #[cfg(feature = "graphics")]
-                visualizer.remove_client(*client_id);
-                if let Some(player_entity) = lobby.players.remove(client_id) {
-                    cmd.entity(player_entity).despawn();
+                if let Some(visualizer) = visualizer.as_mut() {
+                    visualizer.remove_client(*client_id);
+                }
+                lobby.snapshot_acks.remove(client_id);
+                // Never joined, there is no player to remove.
+                if lobby.handshakes.remove(client_id).is_some() || lobby.refused.remove(client_id).is_some() {
+                    continue;
+                }
+                let player_entity = lobby.players.get(client_id).copied();
+                let resumable = lobby.features.get(client_id).map_or(false, |features| features.contains(Features::SESSION_RESUME));
+                match player_entity {
+                    Some(player_entity) if !kicked && resumable && net_config.resume_grace > 0. => {
+                        // Nobody drives it any more, it coasts to a stop.
+                        cmd.entity(player_entity)
+                            .insert(PlayerInputState::default())
+                            .insert(InputQueue::default())
+                            .remove::<LastInputTick>();
+                        lobby.suspended.insert(*client_id, now + net_config.resume_grace as f64);
+                        println!("Keeping the car of player {} for {}s.", client_id, net_config.resume_grace);
+                    }
+                    _ => remove_player(&mut cmd, &mut lobby, &mut server, *client_id, &mut players_left),
                 }
+            }
+        }
+    }
 
-                let message = bincode::serialize(&ServerMessages::PlayerRemove { id: *client_id }).unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
+    let pending: Vec<(u64, f64)> = lobby.handshakes.iter().map(|(client_id, until)| (*client_id, *until)).collect();
+    for (client_id, until) in pending {
+        let Some(message) = server.receive_message(client_id, ClientChannel::Handshake) else {
+            if until <= now {
+                lobby.handshakes.remove(&client_id);
+                guard.kick(&mut server, client_id, "no handshake, too old a client".to_string());
+            }
+            continue;
+        };
+        lobby.handshakes.remove(&client_id);
+        let hello = match decode::<ClientHello>(&message) {
+            Ok(hello) => hello,
+            Err(e) => {
+                refuse(&mut lobby, &mut server, client_id, format!("unreadable handshake, {}", e), now);
+                continue;
             }
+        };
+        let profile = match check_join::<PS>(&hello, client_id, &lobby, &transport, &guard, &net_config, host.is_some()) {
+            Ok(profile) => profile,
+            Err(reason) => {
+                refuse(&mut lobby, &mut server, client_id, reason, now);
+                continue;
+            }
+        };
+        let features = hello.features.intersection(Features::SUPPORTED);
+        let accepted = ServerHello::Accepted {
+            protocol_version: PROTOCOL_VERSION,
+            build: BUILD_VERSION.to_string(),
+            features,
+        };
+        match encode(&accepted) {
+            Ok(bytes) => server.send_message(client_id, ServerChannel::Handshake, bytes),
+            Err(e) => error!("{}", e),
+        }
+        lobby.features.insert(client_id, features);
+        println!("Player {} connected as {}{}, build {}.", client_id, profile.name, if profile.spectator { ", spectating" } else { "" }, hello.build);
+
+        // Back as a spectator, the old car has no driver to wait for.
+        let resuming = lobby.suspended.contains_key(&client_id);
+        if resuming && profile.spectator {
+            remove_player(&mut cmd, &mut lobby, &mut server, client_id, &mut players_left);
+        }
+
+        // Announces our own car too when resuming, the client binds to it again.
+        for (entity, player, transform) in players.iter() {
+            let Some(player_profile) = lobby.profiles.get(&player.id) else { continue };
+            let message = form_player_create_message(player.id,entity,*transform,player_profile).unwrap();
+            server.send_message(client_id, ServerChannel::ServerMessages, message);
+        }
+
+        // The car keeps the profile it was built with.
+        if lobby.suspended.remove(&client_id).is_some() {
+            println!("Player {} is back, resuming its car.", client_id);
+            players_joined.send(PlayerJoined { client_id });
+            continue;
+        }
+
+        // Spectators only watch, snapshots go to every client anyway.
+        if profile.spectator {
+            lobby.profiles.insert(client_id, profile);
+            players_joined.send(PlayerJoined { client_id });
+            continue;
+        }
+
+        let (success,entity,transform) = PS::spawn_authority(&mut cmd,game_asset.as_deref(),&profile,&players);
+        if success
+        {
+            cmd.entity(entity)
+            .insert(NetPlayer { id: client_id })
+            .insert(PlayerInputState::default())
+            .insert(InputQueue::default());
+
+            lobby.players.insert(client_id, entity);
+            let message = form_player_create_message(client_id,entity,transform,&profile).unwrap();
+            server.broadcast_message(ServerChannel::ServerMessages, message);
+            lobby.profiles.insert(client_id, profile);
+            players_joined.send(PlayerJoined { client_id });
+        }
+        else {
+            guard.kick(&mut server, client_id, format!("can not spawn player entity with Arch : {}",profile.archetype));
+        }
+    }
+
+    // Refused clients that did not leave on their own.
+    let overdue: Vec<u64> = lobby.refused.iter().filter(|(_, (until, _))| *until <= now).map(|(client_id, _)| *client_id).collect();
+    for client_id in overdue {
+        if let Some((_, reason)) = lobby.refused.remove(&client_id) {
+            guard.kick(&mut server, client_id, format!("refused, {}", reason));
         }
     }
 }
 
-pub fn server_process_client_command<Processor : PlayerCommandProcessor> (
+/*The profile a client joins with, or why it can not join.*/
+fn check_join<PS: PlayerSpawner>(
+    hello: &ClientHello,
+    client_id: u64,
+    lobby: &ServerLobby,
+    transport: &NetcodeServerTransport,
+    guard: &TrafficGuard,
+    net_config: &NetConfig,
+    host: bool,
+) -> Result<PlayerProfile, String> {
+    if let Some(reason) = check_hello(hello) {
+        return Err(reason);
+    }
+    if client_id == HOST_CLIENT_ID && host {
+        return Err("the id belongs to the host".to_string());
+    }
+    if guard.is_banned(client_id) {
+        return Err("banned from this server".to_string());
+    }
+    // A suspended player still holds its slot. The transport accepts up to max_clients of the launch, the limit can be lowered while running.
+    // The host player is not a client of the transport and takes no slot.
+    let clients = lobby.profiles.keys().filter(|id| !(host && **id == HOST_CLIENT_ID)).count();
+    if !lobby.suspended.contains_key(&client_id) && clients >= net_config.max_clients {
+        return Err("the server is full".to_string());
+    }
+    let profile = match transport.user_data(client_id) {
+        Some(user_data) => PlayerProfile::from_user_data(client_id, &user_data),
+        None => Ok(PlayerProfile::anonymous(client_id)),
+    };
+    let profile = profile.map_err(|e| e.to_string())?;
+    // spectators get no car
+    if !profile.spectator {
+        PS::check_profile(&profile)?;
+    }
+    Ok(profile)
+}
+
+/*Tell the client why it can not join and give it a moment to leave, the disconnect would drop the message.*/
+fn refuse(lobby: &mut ServerLobby, server: &mut RenetServer, client_id: u64, reason: String, now: f64) {
+    println!("Refusing client {}: {}", client_id, reason);
+    let refused = ServerHello::Refused {
+        protocol_version: PROTOCOL_VERSION,
+        build: BUILD_VERSION.to_string(),
+        reason: reason.clone(),
+    };
+    match encode(&refused) {
+        Ok(bytes) => server.send_message(client_id, ServerChannel::Handshake, bytes),
+        Err(e) => error!("{}", e),
+    }
+    lobby.refused.insert(client_id, (now + REFUSAL_GRACE, reason));
+}
+
+/*The player is gone for good, its car is despawned and everybody told.*/
+fn remove_player(cmd: &mut Commands, lobby: &mut ServerLobby, server: &mut RenetServer, client_id: u64, players_left: &mut EventWriter<PlayerLeft>) {
+    lobby.suspended.remove(&client_id);
+    lobby.features.remove(&client_id);
+    lobby.profiles.remove(&client_id);
+    if let Some(player_entity) = lobby.players.remove(&client_id) {
+        cmd.entity(player_entity).despawn();
+        let message = bincode::serialize(&ServerMessages::PlayerRemove { id: client_id }).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages, message);
+    }
+    players_left.send(PlayerLeft { client_id });
+}
+
+/*Drop the cars of players who did not come back within the grace period.*/
+pub fn server_expire_suspended_players(
     mut cmd: Commands,
+    time: Res<Time>,
     mut lobby: ResMut<ServerLobby>,
     mut server: ResMut<RenetServer>,
+    mut players_left: EventWriter<PlayerLeft>,
 ) {
-   
-    for client_id in server.clients_id() {
-        while let Some(message) = server.receive_message(client_id, ClientChannel::Command) {
-            Processor::process_command(client_id,&mut cmd,&message,&mut lobby);
-        }
+    let now = time.elapsed_seconds_f64();
+    let expired: Vec<u64> = lobby.suspended.iter().filter(|(_, until)| **until <= now).map(|(client_id, _)| *client_id).collect();
+    for client_id in expired {
+        println!("Player {} did not come back in time.", client_id);
+        remove_player(&mut cmd, &mut lobby, &mut server, client_id, &mut players_left);
     }
 }
 
-pub fn server_process_client_input<Processor : PlayerCommandProcessor> (
+/*The host car is announced like a client one, clients connecting later learn about it from the lobby.*/
+pub fn server_spawn_host_player<PS : PlayerSpawner>(
     mut cmd: Commands,
+    game_asset: Res<GameAssets>,
+    net_config: Res<NetConfig>,
     mut lobby: ResMut<ServerLobby>,
     mut server: ResMut<RenetServer>,
+    players: Query<(Entity, &NetPlayer, &Transform)>,
+) {
+    // Back from the pause, the car is still there.
+    if lobby.players.contains_key(&HOST_CLIENT_ID) {
+        return;
+    }
+    let profile = net_config.profile.clone();
+    let (success,entity,transform) = PS::spawn_local(&mut cmd,&game_asset,&profile,&players);
+    if !success {
+        error!("Can not spawn host player with Arch : {}",profile.archetype);
+        return;
+    }
+    println!("Hosting as {}.", profile.name);
+    cmd.entity(entity).insert(NetPlayer { id: HOST_CLIENT_ID });
+    lobby.players.insert(HOST_CLIENT_ID, entity);
+    let message = form_player_create_message(HOST_CLIENT_ID,entity,transform,&profile).unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages, message);
+    lobby.profiles.insert(HOST_CLIENT_ID, profile);
+}
+
+/*
+Hand the messages of a client to the processor while allow lets it, drop the rest. A rejected
+message or a flood is a strike, the guard kicks the client once it has too many. Clients still
+in the handshake or refused have not joined, whatever they send is dropped unread.
+*/
+fn process_client_channel<Processor : PlayerCommandProcessor>(
+    cmd: &mut Commands,
+    lobby: &mut ResMut<ServerLobby>,
+    server: &mut RenetServer,
+    guard: &mut TrafficGuard,
+    limits: &TrafficLimits,
+    channel: ClientChannel,
+    flood: &str,
+    mut allow: impl FnMut(&mut TrafficGuard, u64, usize) -> bool,
 ) {
-   
     for client_id in server.clients_id() {
-        while let Some(message) = server.receive_message(client_id, ClientChannel::Input) {
-            Processor::process_command(client_id,&mut cmd,&message,&mut lobby);
+        if lobby.handshakes.contains_key(&client_id) || lobby.refused.contains_key(&client_id) {
+            while server.receive_message(client_id, channel).is_some() {}
+            continue;
+        }
+        let mut handled = 0;
+        let mut flooded = false;
+        while let Some(message) = server.receive_message(client_id, channel) {
+            if !allow(guard, client_id, handled) {
+                flooded = true;
+                continue;
+            }
+            handled += 1;
+            if let Err(e) = Processor::process_command(client_id,cmd,&message,lobby) {
+                if guard.strike(server, limits, client_id, &format!("{:?} rejected: {}", channel, e)) {
+                    break;
+                }
+            }
+        }
+        if flooded {
+            guard.strike(server, limits, client_id, flood);
         }
     }
 }
 
+pub fn server_process_client_command<Processor : PlayerCommandProcessor> (
+    mut cmd: Commands,
+    mut lobby: ResMut<ServerLobby>,
+    mut server: ResMut<RenetServer>,
+    mut guard: ResMut<TrafficGuard>,
+    limits: Res<TrafficLimits>,
+) {
+    let max_per_tick = limits.max_commands_per_tick;
+    let flood = format!("more than {} commands in a tick", max_per_tick);
+    process_client_channel::<Processor>(&mut cmd, &mut lobby, &mut server, &mut guard, &limits, ClientChannel::Command, &flood,
+        |_, _, handled| handled < max_per_tick);
+}
+
+pub fn server_process_client_input<Processor : PlayerCommandProcessor> (
+    mut cmd: Commands,
+    time: Res<Time>,
+    mut lobby: ResMut<ServerLobby>,
+    mut server: ResMut<RenetServer>,
+    mut guard: ResMut<TrafficGuard>,
+    limits: Res<TrafficLimits>,
+) {
+    guard.decay(&limits, time.delta_seconds());
+    let flood = format!("more than {} inputs a second", limits.max_inputs_per_second);
+    process_client_channel::<Processor>(&mut cmd, &mut lobby, &mut server, &mut guard, &limits, ClientChannel::Input, &flood,
+        |guard, client_id, _| guard.take_input(&limits, client_id));
+}
+
 
 
 
+/*
+One queued input per player and tick, so a snapshot carries the tick of the input its state was
+simulated with. Without a new input the car keeps driving on the last one.
+*/
+pub fn server_apply_inputs_system(
+    mut cmd: Commands,
+    mut players: Query<(Entity, &mut InputQueue, &mut PlayerInputState, Option<&mut LastInputTick>)>,
+) {
+    for (entity, mut queue, mut input, last_tick) in players.iter_mut() {
+        while queue.0.len() > MAX_QUEUED_INPUTS {
+            queue.0.pop_front();
+        }
+        let Some(ticked_input) = queue.0.pop_front() else {
+            continue;
+        };
+        *input = ticked_input.input;
+        match last_tick {
+            Some(mut last_tick) => last_tick.0 = ticked_input.tick,
+            None => {
+                cmd.entity(entity).insert(LastInputTick(ticked_input.tick));
+            }
+        }
+    }
+}
+
 pub struct NetServerPlugin<PS : PlayerSpawner,I : PlayerCommandProcessor,C : PlayerCommandProcessor>{
+    /*Also spawn a car for the local player, the listen server of NetworkMode::Host.*/
+    pub host : bool,
     _ps : PhantomData<PS>,
     _i : PhantomData<I>,
     _c : PhantomData<C>
@@ -236,12 +627,21 @@ pub struct NetServerPlugin<PS : PlayerSpawner,I : PlayerCommandProcessor,C : Pla
 impl<PS : PlayerSpawner,I : PlayerCommandProcessor,C : PlayerCommandProcessor> Default for NetServerPlugin<PS,I,C> {
     fn default() -> Self { 
         NetServerPlugin::<PS,I,C> {
+            host : false,
             _ps : PhantomData,
             _i : PhantomData,
             _c : PhantomData
         }
     }
 }
+impl<PS : PlayerSpawner,I : PlayerCommandProcessor,C : PlayerCommandProcessor> NetServerPlugin<PS,I,C> {
+    pub fn host() -> Self {
+        NetServerPlugin::<PS,I,C> {
+            host : true,
+            ..Default::default()
+        }
+    }
+}
 
 impl<PS : PlayerSpawner,I : PlayerCommandProcessor,C : PlayerCommandProcessor> Plugin for NetServerPlugin<PS,I,C> {
     fn build(&self, app: &mut App) {
@@ -249,23 +649,60 @@ impl<PS : PlayerSpawner,I : PlayerCommandProcessor,C : PlayerCommandProcessor> P
         app.add_plugins((
             RenetServerPlugin,
             NetcodeServerPlugin,
-            EguiPlugin,
         ))
         .insert_resource(ServerLobby::default())
+        .insert_resource(ServerTick::default())
+        .add_event::<PlayerJoined>()
+        .add_event::<PlayerLeft>()
+        .init_resource::<TrafficGuard>()
+        .init_resource::<TrafficLimits>()
         .add_systems(Update,
             (server_process_client_connections::<PS>,
+                     server_expire_suspended_players.after(server_process_client_connections::<PS>),
                      server_process_client_command::<C>,
                      server_process_client_input::<I>).run_if(
                       in_state(GameState::Playing)
-                                .or_else(in_state(GameState::Pause))));
+                                .or_else(in_state(GameState::Pause))))
+        .add_systems(FixedUpdate, (server_tick_system, server_apply_inputs_system.in_set(ServerInputSet)).chain())
+        // clients sync their clock before they play
+        .add_systems(Update, server_clock_system);
+
+        if self.host {
+            app.insert_resource(HostPlayer)
+                .add_systems(OnEnter(GameState::Playing), server_spawn_host_player::<PS>);
+        }
                             
+        // The visualizer needs a window, a headless server goes without.
         #[cfg(feature = "graphics")]
-        {
-            app.add_systems(Update, update_visulizer_system)
+        if crate::has_renderer(app) {
+            app.add_plugins(EguiPlugin)
+            .add_systems(Update, update_visulizer_system)
             .insert_resource(renet_visualizer::RenetServerVisualizer::<200>::default());
         }
 
-        let (server, transport) = new_renet_server();
-        app.insert_resource(server).insert_resource(transport);
+        let net_config = app.world.get_resource::<NetConfig>().cloned().unwrap_or_default();
+        if let Some(responder) = new_discovery_responder(net_config.discovery_port) {
+            app.insert_resource(responder)
+                .add_systems(Update, server_discovery_system);
+        }
+        app.insert_resource(net_config.replication.clone())
+            .init_resource::<ReplicationPriorities>()
+            .insert_resource(net_config);
+    }
+}
+
+/*
+Open the server socket of the NetConfig and insert RenetServer with its transport, after
+NetServerPlugin. Nothing can be served without them, the caller decides what a failure means.
+*/
+pub fn start_renet_server(app: &mut App) -> Result<(), NetworkError> {
+    let net_config = app.world.get_resource::<NetConfig>().cloned().unwrap_or_default();
+    let mut conditioner = net_config.conditions.map(LinkConditioner::new);
+    let (server, transport) = new_renet_server(&net_config,conditioner.as_mut())?;
+    if let Some(conditioner) = conditioner {
+        app.insert_resource(conditioner);
     }
+    app.insert_resource(server)
+        .insert_resource(transport);
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,134 @@
+use bevy_renet::renet::transport::{ConnectToken, NetcodeError, TokenGenerationError, NETCODE_KEY_BYTES, NETCODE_USER_DATA_BYTES};
+use std::{fmt, fs::File, io::Write, net::SocketAddr, path::Path, time::{Duration, SystemTime}};
+
+use crate::renet::{unsecure_requested, PROTOCOL_ID};
+
+/*How long a freshly issued token can be used to start a connection.*/
+pub const TOKEN_EXPIRE_SECONDS: u64 = 300;
+/*Seconds without packets before either side drops the connection.*/
+pub const TOKEN_TIMEOUT_SECONDS: i32 = 15;
+
+#[derive(Debug)]
+pub enum TokenError {
+    Missing,
+    Io(std::io::Error),
+    NotHex,
+    Netcode(NetcodeError),
+    /*Past TOKEN_EXPIRE_SECONDS, the server would drop every attempt made with it.*/
+    Expired,
+    MissingKey,
+    InvalidKey,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Missing => write!(f, "no connect token, set RENET_CONNECT_TOKEN or RENET_CONNECT_TOKEN_FILE (or RENET_UNSECURE for local development)"),
+            TokenError::Io(e) => write!(f, "can not read connect token: {}", e),
+            TokenError::NotHex => write!(f, "connect token is not hex encoded"),
+            TokenError::Netcode(e) => write!(f, "invalid connect token: {}", e),
+            TokenError::Expired => write!(f, "connect token expired, issue a fresh one with the token binary"),
+            TokenError::MissingKey => write!(f, "no private key, set RENET_PRIVATE_KEY (or RENET_UNSECURE for local development)"),
+            TokenError::InvalidKey => write!(f, "RENET_PRIVATE_KEY is not {} hex encoded bytes", NETCODE_KEY_BYTES),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+/*Issue a token for client_id, valid for every server address listed.*/
+pub fn issue_connect_token(
+    client_id: u64,
+    server_addresses: Vec<SocketAddr>,
+    user_data: Option<&[u8; NETCODE_USER_DATA_BYTES]>,
+    private_key: &[u8; NETCODE_KEY_BYTES],
+) -> Result<ConnectToken, TokenGenerationError> {
+    let current_time: Duration = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    ConnectToken::generate(
+        current_time,
+        PROTOCOL_ID,
+        TOKEN_EXPIRE_SECONDS,
+        client_id,
+        TOKEN_TIMEOUT_SECONDS,
+        server_addresses,
+        user_data,
+        private_key,
+    )
+}
+
+/*Tokens travel as hex text so they can be pasted in an env var or a chat window.*/
+pub fn token_to_string(token: &ConnectToken) -> String {
+    let mut bytes: Vec<u8> = vec![];
+    token.write(&mut bytes).unwrap();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn token_from_string(value: &str) -> Result<ConnectToken, TokenError> {
+    let bytes = hex_decode(value.trim()).ok_or(TokenError::NotHex)?;
+    ConnectToken::read(&mut bytes.as_slice()).map_err(TokenError::Netcode)
+}
+
+pub fn write_token_file(token: &ConnectToken, path: &Path) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(token_to_string(token).as_bytes())
+}
+
+pub fn read_token_file(path: &Path) -> Result<ConnectToken, TokenError> {
+    let content = std::fs::read_to_string(path).map_err(TokenError::Io)?;
+    token_from_string(&content)
+}
+
+/*
+Connect token from RENET_CONNECT_TOKEN (hex string) or RENET_CONNECT_TOKEN_FILE (path).
+Read again on every reconnect, so a token file refreshed meanwhile is picked up.
+*/
+pub fn connect_token_from_env() -> Result<ConnectToken, TokenError> {
+    let token = if let Ok(value) = std::env::var("RENET_CONNECT_TOKEN") {
+        token_from_string(&value)?
+    } else if let Ok(path) = std::env::var("RENET_CONNECT_TOKEN_FILE") {
+        read_token_file(Path::new(&path))?
+    } else {
+        return Err(TokenError::Missing);
+    };
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    if token.expire_timestamp <= now.as_secs() {
+        return Err(TokenError::Expired);
+    }
+    Ok(token)
+}
+
+/*
+Private key from RENET_PRIVATE_KEY (64 hex chars). Tokens signed with the built in example key
+are only good for local development, it is used when RENET_UNSECURE is set and no key is given.
+*/
+pub fn private_key_from_env() -> Result<[u8; NETCODE_KEY_BYTES], TokenError> {
+    let Ok(value) = std::env::var("RENET_PRIVATE_KEY") else {
+        if unsecure_requested() {
+            println!("RENET_PRIVATE_KEY not set, using the example private key");
+            return Ok(*crate::renet::PRIVATE_KEY);
+        }
+        return Err(TokenError::MissingKey);
+    };
+    match hex_decode(value.trim()) {
+        Some(bytes) if bytes.len() == NETCODE_KEY_BYTES => {
+            let mut key = [0u8; NETCODE_KEY_BYTES];
+            key.copy_from_slice(&bytes);
+            Ok(key)
+        }
+        _ => Err(TokenError::InvalidKey),
+    }
+}
+
+fn hex_decode(value: &str) -> Option<Vec<u8>> {
+    if value.len() % 2 != 0 {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,224 @@
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use serde::Serialize;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::race::{RacePhase, RaceSession};
+use crate::renet::discovery::DiscoveryInfo;
+use crate::renet::server::ServerLobby;
+use crate::renet::NetConfig;
+use crate::TickRate;
+
+/*Seconds between two refreshes of the report, also the window of the tick timings.*/
+const REPORT_INTERVAL: f64 = 1.;
+
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    pub name: String,
+    pub track: String,
+    pub uptime_seconds: f64,
+    pub race: RaceStatus,
+    pub max_players: usize,
+    pub players: Vec<PlayerStatus>,
+    pub ticks: TickStatus,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RaceStatus {
+    pub phase: RacePhase,
+    pub phase_elapsed: f32,
+    /*0 when the phase has no fixed length.*/
+    pub phase_duration: f32,
+    pub laps: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlayerStatus {
+    pub id: u64,
+    pub name: String,
+    pub spectator: bool,
+    /*Dropped out, the car waits for the client to resume.*/
+    pub reconnecting: bool,
+    pub ready: bool,
+    pub lap: Option<i32>,
+    pub place: Option<usize>,
+    /*From RenetServer::network_info, None for the host player and clients away.*/
+    pub rtt: Option<f64>,
+    pub packet_loss: Option<f64>,
+    pub bytes_sent_per_second: Option<f64>,
+    pub bytes_received_per_second: Option<f64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TickStatus {
+    pub target_rate: f64,
+    /*Ticks per second over the last report interval.*/
+    pub rate: f64,
+    /*Wall clock time spent inside a tick, in milliseconds.*/
+    pub mean_ms: f64,
+    pub max_ms: f64,
+}
+
+/*Wall clock time of every tick, from the First to the Last schedule.*/
+#[derive(Debug, Default, Resource)]
+pub struct TickTimings {
+    started: Option<Instant>,
+    ticks: u32,
+    total: Duration,
+    max: Duration,
+}
+
+impl TickTimings {
+    /*Mean and max over the ticks since the last call, then starts over.*/
+    fn take(&mut self, target_rate: f64, seconds: f64) -> TickStatus {
+        let status = TickStatus {
+            target_rate,
+            rate: self.ticks as f64 / seconds,
+            mean_ms: if self.ticks == 0 { 0. } else { self.total.as_secs_f64() * 1000. / self.ticks as f64 },
+            max_ms: self.max.as_secs_f64() * 1000.,
+        };
+        self.ticks = 0;
+        self.total = Duration::ZERO;
+        self.max = Duration::ZERO;
+        status
+    }
+}
+
+pub fn tick_start_system(mut timings: ResMut<TickTimings>) {
+    timings.started = Some(Instant::now());
+}
+
+pub fn tick_end_system(mut timings: ResMut<TickTimings>) {
+    let Some(started) = timings.started.take() else {
+        return;
+    };
+    let spent = started.elapsed();
+    timings.ticks += 1;
+    timings.total += spent;
+    timings.max = timings.max.max(spent);
+}
+
+/*
+Local HTTP endpoint for monitoring. Every request gets the latest report as JSON, served
+from its own thread so a slow scraper never holds the game loop up.
+*/
+#[derive(Resource)]
+pub struct StatusEndpoint {
+    report: Arc<Mutex<String>>,
+    since_report: f64,
+}
+
+/*None when the port is taken, the server still runs without it.*/
+pub fn new_status_endpoint(port: u16) -> Option<StatusEndpoint> {
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Status endpoint disabled, can not bind port {}: {}", port, e);
+            return None;
+        }
+    };
+    println!("Status on http://{}", listener.local_addr().map_or(port.to_string(), |addr| addr.to_string()));
+    let report = Arc::new(Mutex::new("{}".to_string()));
+    let served = report.clone();
+    std::thread::Builder::new()
+        .name("status endpoint".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let body = served.lock().unwrap().clone();
+                        if let Err(e) = answer(stream, &body) {
+                            debug!("Status request failed: {}", e);
+                        }
+                    }
+                    Err(e) => debug!("Status connection failed: {}", e),
+                }
+            }
+        })
+        .expect("can not start the status endpoint thread");
+    Some(StatusEndpoint { report, since_report: REPORT_INTERVAL })
+}
+
+fn answer(mut stream: TcpStream, body: &str) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+    // The request itself does not matter, read its head so the client is not reset while sending it.
+    let mut request = [0u8; 1024];
+    match stream.read(&mut request) {
+        Ok(_) => {}
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+        Err(e) => return Err(e),
+    }
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+pub fn server_status_system(
+    time: Res<Time>,
+    tick_rate: Res<TickRate>,
+    net_config: Res<NetConfig>,
+    lobby: Res<ServerLobby>,
+    server: Res<RenetServer>,
+    session: Res<RaceSession>,
+    discovery: Res<DiscoveryInfo>,
+    mut timings: ResMut<TickTimings>,
+    mut endpoint: ResMut<StatusEndpoint>,
+) {
+    endpoint.since_report += time.delta_seconds_f64();
+    if endpoint.since_report < REPORT_INTERVAL {
+        return;
+    }
+    let seconds = std::mem::take(&mut endpoint.since_report);
+    let now = time.elapsed_seconds_f64();
+
+    let mut ids: Vec<&u64> = lobby.profiles.keys().collect();
+    ids.sort();
+    let players = ids
+        .into_iter()
+        .map(|id| {
+            let profile = &lobby.profiles[id];
+            let standing = session.standing(*id);
+            let network_info = server.network_info(*id).ok();
+            PlayerStatus {
+                id: *id,
+                name: profile.name.clone(),
+                spectator: profile.spectator,
+                reconnecting: lobby.suspended.contains_key(id),
+                ready: session.ready.contains(id),
+                lap: standing.map(|standing| standing.lap),
+                place: standing.map(|standing| standing.place),
+                rtt: network_info.as_ref().map(|info| info.rtt),
+                packet_loss: network_info.as_ref().map(|info| info.packet_loss),
+                bytes_sent_per_second: network_info.as_ref().map(|info| info.bytes_sent_per_second),
+                bytes_received_per_second: network_info.as_ref().map(|info| info.bytes_received_per_second),
+            }
+        })
+        .collect();
+
+    let report = StatusReport {
+        name: discovery.name.clone(),
+        track: discovery.track.clone(),
+        uptime_seconds: now,
+        race: RaceStatus {
+            phase: session.phase,
+            phase_elapsed: session.elapsed(now),
+            phase_duration: session.phase_duration,
+            laps: session.laps,
+        },
+        max_players: net_config.max_clients,
+        players,
+        ticks: timings.take(tick_rate.0, seconds),
+    };
+    match serde_json::to_string(&report) {
+        Ok(json) => *endpoint.report.lock().unwrap() = json,
+        Err(e) => error!("Can not encode the status report: {}", e),
+    }
+}
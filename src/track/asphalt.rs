@@ -1,9 +1,9 @@
-use super::{AsphaltPbr, MaterialHandle, Track, TrackRoad};
-use bevy::{
-    pbr::NotShadowCaster,
-    prelude::*,
-    render::{mesh::*, primitives::Aabb},
-};
+use super::{Track, TrackBounds, TrackRoad};
+#[cfg(feature = "graphics")]
+use super::{AsphaltPbr, MaterialHandle};
+use bevy::prelude::*;
+#[cfg(feature = "graphics")]
+use bevy::{pbr::NotShadowCaster, render::mesh::*};
 use crate::collision::STATIC_GROUP;
 use bevy_rapier3d::{na::Point3, prelude::*, rapier::prelude::ColliderShape};
 
@@ -12,6 +12,7 @@ pub struct AsphaltCell {
     pub is_color: bool,
 }
 
+#[cfg(feature = "graphics")]
 #[derive(Debug)]
 pub struct AsphaltBlock {
     vertices: Vec<[f32; 3]>,
@@ -20,14 +21,16 @@ pub struct AsphaltBlock {
     uvs: Vec<[f32; 2]>,
 }
 
+#[cfg(feature = "graphics")]
 const BLOCK_SPAN: usize = 1;
 
+#[cfg(feature = "graphics")]
 pub fn spawn_road(
     handled_materials: &Res<MaterialHandle>,
     cmd: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     track: &Track,
-) -> Aabb {
+) -> TrackBounds {
     let mut blocks_indexes: Vec<Vec<usize>> = vec![];
     for i in 0..track.points.len() {
         let block_i = i % BLOCK_SPAN;
@@ -123,7 +126,7 @@ pub fn spawn_road(
     let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
     mesh.insert_attribute(
         Mesh::ATTRIBUTE_POSITION,
-        VertexAttributeValues::from(track_vertices.clone()),
+        VertexAttributeValues::from(track_vertices),
     );
     mesh.insert_attribute(
         Mesh::ATTRIBUTE_NORMAL,
@@ -132,7 +135,14 @@ pub fn spawn_road(
     mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
     mesh.set_indices(Some(Indices::U32(track.indices.clone())));
     mesh.generate_tangents().unwrap();
-    let aabb = mesh.compute_aabb().unwrap();
+
+    spawn_road_collider(cmd, track)
+}
+
+/*Road collider alone, also used by the headless server. Returns the road bounds.*/
+pub fn spawn_road_collider(cmd: &mut Commands, track: &Track) -> TrackBounds {
+    let (track_vertices, _) = track.road();
+    let bounds = TrackBounds::enclosing(track_vertices.iter().map(|v| Vec3::from(*v))).unwrap();
 
     cmd.spawn((
         TrackRoad,
@@ -152,5 +162,5 @@ pub fn spawn_road(
         },
         Restitution::coefficient(0.),
     ));
-    aabb
+    bounds
 }
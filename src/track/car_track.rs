@@ -1,5 +1,6 @@
 use bevy::prelude::*;
-use crate::car::spawn_car;
+use crate::car::{spawn_car_with_spec, CarArchetype};
+use crate::game_asset::GameAssets;
 
 #[derive(Debug, Event)]
 pub struct SpawnCarOnTrackEvent {
@@ -19,6 +20,10 @@ pub struct CarTrack {
     pub line_pos: Vec3,
     pub place: usize,
 }
+/*CarTrack written from server standings, progress_system leaves it alone.*/
+#[derive(Component)]
+pub struct ReplicatedTrack;
+
 impl Default for CarTrack {
     fn default() -> Self {
         Self {
@@ -36,14 +41,15 @@ impl Default for CarTrack {
 
 pub fn spawn_car_on_track(
     cmd: &mut Commands,
-    car_gl: &Handle<Scene>,
-    wheel_gl: &Handle<Scene>,
+    game_asset: &GameAssets,
+    archetype: &CarArchetype,
     player: bool,
     transform: Transform,
     index: usize,
     start_shift: f32,
 ) -> Entity {
-    let car_id = spawn_car(cmd, car_gl, wheel_gl, player, transform);
+    let scenes = archetype.scenes(Some(game_asset));
+    let car_id = spawn_car_with_spec(cmd, scenes.as_ref().map(|(body, wheel)| (body, wheel)), player, transform, (archetype.spec)());
     cmd.entity(car_id).insert(CarTrack {
         index,
         start_shift,
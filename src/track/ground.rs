@@ -1,6 +1,11 @@
+use super::TrackBounds;
+#[cfg(feature = "graphics")]
 use super::{GroundPbr, MaterialHandle};
+#[cfg(feature = "graphics")]
 use crate::track::mesh::QuadPlane;
-use bevy::{math::Vec3Swizzles, pbr::NotShadowCaster, prelude::*, render::primitives::Aabb};
+use bevy::{math::Vec3Swizzles, prelude::*};
+#[cfg(feature = "graphics")]
+use bevy::pbr::NotShadowCaster;
 use crate::collision::STATIC_GROUP;
 use bevy_rapier3d::prelude::*;
 
@@ -9,16 +14,16 @@ pub struct GroundCell {
     pub is_color: bool,
 }
 
+#[cfg(feature = "graphics")]
 pub fn spawn_ground_heightfield(
     cmd: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     handled_materials: &Res<MaterialHandle>,
-    aabb: &Aabb,
+    bounds: &TrackBounds,
     padding: f32,
 ) {
-    let aabb_center: Vec3 = aabb.center.into();
-    let size: Vec2 = 2. * (aabb.half_extents.xz() + padding * Vec2::ONE);
-    let (cols, rows) = (10, 10);
+    let aabb_center: Vec3 = bounds.center;
+    let size: Vec2 = 2. * (bounds.half_extents.xz() + padding * Vec2::ONE);
 
     let meshes_n_half = 10;
     let size_s = size / (2 * meshes_n_half) as f32;
@@ -48,6 +53,14 @@ pub fn spawn_ground_heightfield(
         }
     }
 
+    spawn_ground_collider(cmd, bounds, padding);
+}
+
+pub fn spawn_ground_collider(cmd: &mut Commands, bounds: &TrackBounds, padding: f32) {
+    let aabb_center: Vec3 = bounds.center;
+    let size: Vec2 = 2. * (bounds.half_extents.xz() + padding * Vec2::ONE);
+    let (cols, rows) = (10, 10);
+
     cmd.spawn((
         Name::new("ground-heightfield"),
         RigidBody::Fixed,
@@ -1,22 +1,21 @@
 use super::track::Track;
+#[cfg(feature = "graphics")]
 use crate::track::material::MaterialHandle;
-use bevy::{pbr::NotShadowCaster, prelude::*, render::mesh::*};
+use bevy::prelude::*;
+#[cfg(feature = "graphics")]
+use bevy::{pbr::NotShadowCaster, render::mesh::*};
 use crate::collision::STATIC_GROUP;
 use bevy_rapier3d::{na::Point3, prelude::*, rapier::prelude::ColliderShape};
 use std::ops::Sub;
 
-pub fn spawn_kerb(
-    cmd: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    handled_materials: &Res<MaterialHandle>,
-    track: &Track,
-) {
-    let kerb_length: f32 = 10.;
-    let kerb_height: f32 = 0.002;
-    let from_center: f32 = 5.;
-    let top_norm = Vec3::Y;
+const KERB_LENGTH: f32 = 10.;
+const KERB_HEIGHT: f32 = 0.002;
+const KERB_FROM_CENTER: f32 = 5.;
 
-    let normals_side = &track.left_norm;
+/*Vertices, normals and uvs of the kerb on one side of the track.*/
+fn kerb_side(track: &Track, left: bool) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<[f32; 2]>) {
+    let top_norm = Vec3::Y;
+    let normals_side = if left { &track.left_norm } else { &track.right_norm };
     let mut vertices: Vec<[f32; 3]> = vec![];
     let mut normals: Vec<[f32; 3]> = Vec::new();
     let mut uvs: Vec<[f32; 2]> = Vec::new();
@@ -24,35 +23,25 @@ pub fn spawn_kerb(
     for (i, p) in track.points.iter().enumerate() {
         let last: bool = i + 1 == track.points.len();
         let i_next: usize = if last { 0 } else { i + 1 };
-        let point: Vec3 = *p + normals_side[i] * from_center;
-        let point_next: Vec3 = track.points[i_next] + normals_side[i_next] * from_center;
-        let (v1, v2) = (point + normals_side[i], point);
+        let point: Vec3 = *p + normals_side[i] * KERB_FROM_CENTER;
+        let point_next: Vec3 = track.points[i_next] + normals_side[i_next] * KERB_FROM_CENTER;
+        // keep the triangles facing up on both sides
+        let (v1, v2) = if left { (point + normals_side[i], point) } else { (point, point + normals_side[i]) };
         vertices.push(v1.into());
         vertices.push(v2.into());
         let diff = point_next.sub(point).length();
-        let uv = len / kerb_length;
+        let uv = len / KERB_LENGTH;
         uvs.push([uv, 0.]);
         uvs.push([uv, 1.]);
         normals.push(top_norm.to_array());
         normals.push(top_norm.to_array());
         len += diff;
     }
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-    mesh.insert_attribute(
-        Mesh::ATTRIBUTE_POSITION,
-        VertexAttributeValues::from(vertices.clone()),
-    );
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, VertexAttributeValues::from(normals));
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::from(uvs));
-    mesh.set_indices(Some(Indices::U32(track.indices.clone())));
+    (vertices, normals, uvs)
+}
 
-    cmd.spawn((
-        PbrBundle {
-            mesh: meshes.add(mesh),
-            material: handled_materials.kerb.clone(),
-            transform: Transform::from_xyz(0., kerb_height, 0.),
-            ..Default::default()
-        },
+fn kerb_collider(track: &Track, vertices: &[[f32; 3]]) -> impl Bundle {
+    (
         Collider::from(ColliderShape::trimesh(
             vertices
                 .iter()
@@ -65,63 +54,50 @@ pub fn spawn_kerb(
             coefficient: 3.,
             ..default()
         },
-        NotShadowCaster,
         ColliderScale::Absolute(Vec3::ONE),
         CollisionGroups::new(STATIC_GROUP, Group::ALL),
         Restitution::coefficient(0.),
-    ));
+    )
+}
 
-    let normals_side = &track.right_norm;
-    let mut vertices: Vec<[f32; 3]> = vec![];
-    let mut normals: Vec<[f32; 3]> = Vec::new();
-    let mut uvs: Vec<[f32; 2]> = Vec::new();
-    let mut len: f32 = 0.;
-    for (i, p) in track.points.iter().enumerate() {
-        let last: bool = i + 1 == track.points.len();
-        let i_next: usize = if last { 0 } else { i + 1 };
-        let point: Vec3 = *p + normals_side[i] * from_center;
-        let point_next: Vec3 = track.points[i_next] + normals_side[i_next] * from_center;
-        let (v1, v2) = (point, point + normals_side[i]);
-        vertices.push(v1.into());
-        vertices.push(v2.into());
-        let diff = point_next.sub(point).length();
-        uvs.push([len / kerb_length, 0.]);
-        uvs.push([len / kerb_length, 1.]);
-        normals.push(top_norm.to_array());
-        normals.push(top_norm.to_array());
-        len += diff;
+#[cfg(feature = "graphics")]
+pub fn spawn_kerb(
+    cmd: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    handled_materials: &Res<MaterialHandle>,
+    track: &Track,
+) {
+    for left in [true, false] {
+        let (vertices, normals, uvs) = kerb_side(track, left);
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            VertexAttributeValues::from(vertices.clone()),
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, VertexAttributeValues::from(normals));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::from(uvs));
+        mesh.set_indices(Some(Indices::U32(track.indices.clone())));
+
+        cmd.spawn((
+            PbrBundle {
+                mesh: meshes.add(mesh),
+                material: handled_materials.kerb.clone(),
+                transform: Transform::from_xyz(0., KERB_HEIGHT, 0.),
+                ..Default::default()
+            },
+            kerb_collider(track, &vertices),
+            NotShadowCaster,
+        ));
     }
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-    mesh.insert_attribute(
-        Mesh::ATTRIBUTE_POSITION,
-        VertexAttributeValues::from(vertices.clone()),
-    );
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, VertexAttributeValues::from(normals));
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::from(uvs));
-    mesh.set_indices(Some(Indices::U32(track.indices.clone())));
+}
 
-    cmd.spawn((
-        PbrBundle {
-            mesh: meshes.add(mesh),
-            material: handled_materials.kerb.clone(),
-            transform: Transform::from_xyz(0., kerb_height, 0.),
-            ..Default::default()
-        },
-        Collider::from(ColliderShape::trimesh(
-            vertices
-                .iter()
-                .map(|v| Point3::new(v[0], v[1], v[2]))
-                .collect(),
-            track.collider_indices.clone(),
-        )),
-        Friction {
-            combine_rule: CoefficientCombineRule::Average,
-            coefficient: 3.,
-            ..default()
-        },
-        NotShadowCaster,
-        ColliderScale::Absolute(Vec3::ONE),
-        CollisionGroups::new(STATIC_GROUP, Group::ALL),
-        Restitution::coefficient(0.),
-    ));
+/*Kerb colliders alone, for the headless server.*/
+pub fn spawn_kerb_colliders(cmd: &mut Commands, track: &Track) {
+    for left in [true, false] {
+        let (vertices, _, _) = kerb_side(track, left);
+        cmd.spawn((
+            TransformBundle::from_transform(Transform::from_xyz(0., KERB_HEIGHT, 0.)),
+            kerb_collider(track, &vertices),
+        ));
+    }
 }
@@ -1,13 +1,18 @@
 mod asphalt;
 mod car_track;
 mod config;
+#[cfg(feature = "graphics")]
 mod decor;
 mod ground;
 mod kerb;
+#[cfg(feature = "graphics")]
 mod material;
+#[cfg(feature = "graphics")]
 mod mesh;
 mod progress;
+#[cfg(feature = "graphics")]
 mod quality;
+#[cfg(feature = "graphics")]
 mod shader;
 mod track;
 mod wall;
@@ -16,23 +21,31 @@ pub use asphalt::*;
 use crate::{car::CarSet, GameState};
 pub use car_track::*;
 pub use config::*;
+#[cfg(feature = "graphics")]
 pub use decor::*;
 pub use ground::*;
+#[cfg(feature = "graphics")]
 pub use material::*;
 pub use progress::*;
+#[cfg(feature = "graphics")]
 pub use quality::*;
+#[cfg(feature = "graphics")]
 pub use shader::*;
 pub use track::*;
 
 use bevy::prelude::*;
 
+pub use self::track::Track;
+#[cfg(feature = "graphics")]
 pub use self::{
-    asphalt::spawn_road, ground::spawn_ground_heightfield, kerb::spawn_kerb, track::Track,
-    wall::spawn_walls,
+    asphalt::spawn_road, ground::spawn_ground_heightfield, kerb::spawn_kerb, wall::spawn_walls,
 };
+use self::{kerb::spawn_kerb_colliders, wall::spawn_wall_colliders};
 
+#[cfg(feature = "graphics")]
 pub struct TrackPlugin;
 
+#[cfg(feature = "graphics")]
 impl Plugin for TrackPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(TrackConfig::default())
@@ -54,6 +67,42 @@ impl Plugin for TrackPlugin {
     }
 }
 
+/*Track physics and progress only, no meshes or materials, for the headless server.*/
+pub struct TrackPhysicsPlugin;
+
+impl Plugin for TrackPhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TrackConfig::default())
+            .add_systems(
+                OnEnter(GameState::Playing),
+                (track_polyline_start_system, track_colliders_start_system),
+            )
+            .add_systems(Update, progress_system.in_set(CarSet::Input).run_if(in_state(GameState::Playing)));
+    }
+}
+
+/*Wall lines on the left and right of the track.*/
+fn wall_points(track: &Track) -> (Vec<Vec3>, Vec<Vec3>) {
+    let mut left_wall_points: Vec<Vec3> = vec![];
+    let mut right_wall_points: Vec<Vec3> = vec![];
+    for (i, p) in track.points.iter().enumerate() {
+        left_wall_points.push(*p + track.right_norm[i] * 7.5);
+        right_wall_points.push(*p + track.right_norm[i] * -7.5);
+    }
+    (left_wall_points, right_wall_points)
+}
+
+pub fn track_colliders_start_system(mut cmd: Commands) {
+    let track = Track::new();
+    let aabb = spawn_road_collider(&mut cmd, &track);
+    spawn_ground_collider(&mut cmd, &aabb, 100.);
+    spawn_kerb_colliders(&mut cmd, &track);
+    let (left_wall_points, right_wall_points) = wall_points(&track);
+    spawn_wall_colliders(&mut cmd, &track.indices, &left_wall_points, &track.right_norm);
+    spawn_wall_colliders(&mut cmd, &track.indices, &right_wall_points, &track.right_norm);
+}
+
+#[cfg(feature = "graphics")]
 pub fn track_start_system(
     handled_materials: Res<MaterialHandle>,
     mut cmd: Commands,
@@ -64,12 +113,7 @@ pub fn track_start_system(
     spawn_ground_heightfield(&mut cmd, &mut meshes, &handled_materials, &aabb, 100.);
 
     spawn_kerb(&mut cmd, &mut meshes, &handled_materials, &track);
-    let mut left_wall_points: Vec<Vec3> = vec![];
-    let mut right_wall_points: Vec<Vec3> = vec![];
-    for (i, p) in track.points.iter().enumerate() {
-        left_wall_points.push(*p + track.right_norm[i] * 7.5);
-        right_wall_points.push(*p + track.right_norm[i] * -7.5);
-    }
+    let (left_wall_points, right_wall_points) = wall_points(&track);
     spawn_walls(
         &mut cmd,
         &mut meshes,
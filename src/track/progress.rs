@@ -1,4 +1,4 @@
-use crate::track::car_track::CarTrack;
+use crate::track::car_track::{CarTrack, ReplicatedTrack};
 use crate::track::{TrackConfig, TRACK_POSITIONS};
 use bevy::prelude::*;
 use crate::collision::{CAR_TRAINING_GROUP, STATIC_GROUP};
@@ -59,8 +59,7 @@ pub fn track_polyline_start_system(mut cmd: Commands, mut track_config: ResMut<T
 
 pub fn progress_system(
     track_config: Res<TrackConfig>,
-    mut cars: Query<(&Transform, &mut CarTrack, Entity)>,
-    mut gizmos: Gizmos,
+    mut cars: Query<(&Transform, &mut CarTrack, Entity), Without<ReplicatedTrack>>,
 ) {
     let polyline = track_config.polyline.as_ref().unwrap();
     let mut board: Vec<(Entity, f32)> = Vec::new();
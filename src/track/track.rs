@@ -7,6 +7,25 @@ use std::f32::consts::FRAC_PI_2;
 #[derive(Component, Debug)]
 pub struct TrackRoad;
 
+/*Box around the road, the ground is sized from it.*/
+#[derive(Debug, Clone, Copy)]
+pub struct TrackBounds {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+}
+
+impl TrackBounds {
+    pub fn enclosing(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        let (min, max) = points.fold((first, first), |(min, max), p| (min.min(p), max.max(p)));
+        Some(Self {
+            center: (min + max) / 2.,
+            half_extents: (max - min) / 2.,
+        })
+    }
+}
+
 #[derive(Component, Debug)]
 pub struct Track {
     width: f32,
@@ -110,6 +129,9 @@ pub const _TRACK_POSITIONS: [(f32, f32, f32, f32); 7] = [
     (0., 0.0, 0., 1.0),
 ];
 
+/*Tracks that can be picked at launch, only the built in one so far.*/
+pub const TRACK_NAMES: &[&str] = &["default"];
+
 pub const TRACK_POSITIONS: [(f32, f32, f32, f32); 365] = [
     (-65.2042, 0.0, 80.13815, 1.0),
     (-115.01793, 0.0, 143.06631, 1.0),
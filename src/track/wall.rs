@@ -1,17 +1,18 @@
+#[cfg(feature = "graphics")]
 use crate::track::material::MaterialHandle;
-use bevy::{prelude::*, render::mesh::*};
+use bevy::prelude::*;
+#[cfg(feature = "graphics")]
+use bevy::render::mesh::*;
 use crate::collision::STATIC_GROUP;
 use bevy_rapier3d::{na::Point3, prelude::*, rapier::prelude::ColliderShape};
 use std::ops::{Mul, Sub};
 
-pub fn spawn_walls(
-    cmd: &mut Commands,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    handled_materials: &Res<MaterialHandle>,
+/*Vertices, normals, uvs and indices of a wall following points.*/
+fn wall_geometry(
     indices_input: &Vec<u32>,
     points: &Vec<Vec3>,
     normals_input: &Vec<Vec3>,
-) {
+) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<[f32; 2]>, Vec<u32>) {
     let points_len = points.len() as u32;
     let material_lengh = 20.;
     let width: f32 = 0.1;
@@ -81,7 +82,10 @@ pub fn spawn_walls(
     indices.extend(indices_input.clone());
     indices.extend(indices_input.iter().map(|ind| ind + points_len * 2));
     indices.extend(indices_input.iter().map(|ind| ind + points_len * 4));
+    (vertices, normals, uvs, indices)
+}
 
+fn wall_collider(vertices: &[[f32; 3]], indices: &[u32]) -> impl Bundle {
     let collider_vertices: Vec<Point3<Real>> = vertices
         .iter()
         .map(|v| Point3::new(v[0], v[1], v[2]))
@@ -89,10 +93,35 @@ pub fn spawn_walls(
 
     let collider_indices: Vec<[u32; 3]> = indices.chunks(3).map(|i| [i[0], i[1], i[2]]).collect();
 
+    (
+        Friction {
+            combine_rule: CoefficientCombineRule::Min,
+            coefficient: 0.1,
+            ..default()
+        },
+        Collider::from(ColliderShape::trimesh(collider_vertices, collider_indices)),
+        ColliderScale::Absolute(Vec3::ONE),
+        CollisionGroups::new(STATIC_GROUP, Group::ALL),
+        Restitution::coefficient(0.),
+    )
+}
+
+#[cfg(feature = "graphics")]
+pub fn spawn_walls(
+    cmd: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    handled_materials: &Res<MaterialHandle>,
+    indices_input: &Vec<u32>,
+    points: &Vec<Vec3>,
+    normals_input: &Vec<Vec3>,
+) {
+    let (vertices, normals, uvs, indices) = wall_geometry(indices_input, points, normals_input);
+    let collider = wall_collider(&vertices, &indices);
+
     let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
     mesh.insert_attribute(
         Mesh::ATTRIBUTE_POSITION,
-        VertexAttributeValues::from(vertices.clone()),
+        VertexAttributeValues::from(vertices),
     );
     mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, VertexAttributeValues::from(normals));
     mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::from(uvs));
@@ -105,14 +134,17 @@ pub fn spawn_walls(
             transform: Transform::from_xyz(0., 0., 0.),
             ..Default::default()
         },
-        Friction {
-            combine_rule: CoefficientCombineRule::Min,
-            coefficient: 0.1,
-            ..default()
-        },
-        Collider::from(ColliderShape::trimesh(collider_vertices, collider_indices)),
-        ColliderScale::Absolute(Vec3::ONE),
-        CollisionGroups::new(STATIC_GROUP, Group::ALL),
-        Restitution::coefficient(0.),
+        collider,
     ));
 }
+
+/*Wall collider alone, for the headless server.*/
+pub fn spawn_wall_colliders(
+    cmd: &mut Commands,
+    indices_input: &Vec<u32>,
+    points: &Vec<Vec3>,
+    normals_input: &Vec<Vec3>,
+) {
+    let (vertices, _, _, indices) = wall_geometry(indices_input, points, normals_input);
+    cmd.spawn((TransformBundle::default(), wall_collider(&vertices, &indices)));
+}
@@ -0,0 +1,232 @@
+/*Headless server and clients in one process, talking over loopback UDP.*/
+// every test file uses its own part of the harness
+#![allow(dead_code)]
+
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_racing_redux::launch::LaunchConfig;
+use bevy_racing_redux::renet::client::{ClientIdentity, ClientLobby, NetworkMapping};
+use bevy_racing_redux::renet::clock::ServerClock;
+use bevy_racing_redux::renet::error::NetworkError;
+use bevy_racing_redux::renet::handshake::ServerHandshake;
+use bevy_racing_redux::renet::profile::PlayerProfile;
+use bevy_racing_redux::renet::server::ServerLobby;
+use bevy_racing_redux::renet::token::{issue_connect_token, token_to_string};
+use bevy_racing_redux::renet::NetworkMode;
+use bevy_racing_redux::{add_headless_plugins, car_app, TickRate};
+use bevy_renet::renet::transport::{NetcodeClientTransport, NETCODE_KEY_BYTES};
+
+/*Client ids of the harness start here, 0 is the host player.*/
+pub const FIRST_CLIENT_ID: u64 = 1000;
+/*Seconds the server keeps the car of a client that left, short so tests do not wait.*/
+pub const RESUME_GRACE: f32 = 1.;
+/*Server key of Harness::start_secure.*/
+pub const SECURE_KEY: [u8; NETCODE_KEY_BYTES] = [7; NETCODE_KEY_BYTES];
+
+pub struct Harness {
+    pub server: App,
+    pub clients: Vec<App>,
+    port: u16,
+    next_client_id: u64,
+}
+
+impl Harness {
+    /*A headless server on a free loopback port, without clients yet.*/
+    pub fn start() -> Self {
+        std::env::set_var("RENET_UNSECURE", "1");
+        Self::serve()
+    }
+
+    /*
+    Like start, but clients need a connect token signed with SECURE_KEY. The environment is the
+    same for every test of a binary, so secure tests live in a test file of their own.
+    */
+    pub fn start_secure() -> Self {
+        std::env::remove_var("RENET_UNSECURE");
+        std::env::set_var("RENET_PRIVATE_KEY", SECURE_KEY.iter().map(|byte| format!("{:02x}", byte)).collect::<String>());
+        Self::serve()
+    }
+
+    fn serve() -> Self {
+        // another test may take the free port before the server binds it, then try the next one
+        for _ in 0..10 {
+            let port = free_port();
+            let mut launch = LaunchConfig::new(NetworkMode::Server);
+            launch.net.port = port;
+            launch.net.discovery_port = 0;
+            launch.net.resume_grace = RESUME_GRACE;
+            match build(App::new(), launch) {
+                Ok(server) => {
+                    return Self {
+                        server,
+                        clients: vec![],
+                        port,
+                        next_client_id: FIRST_CLIENT_ID,
+                    }
+                }
+                Err(NetworkError::Socket(e)) if e.kind() == ErrorKind::AddrInUse => continue,
+                Err(e) => panic!("the harness server does not start: {}", e),
+            }
+        }
+        panic!("no free port for the harness server");
+    }
+
+    /*Connects one more client and returns its client id.*/
+    pub fn add_client(&mut self, name: &str) -> u64 {
+        let client_id = self.next_client_id;
+        self.next_client_id += 1;
+        self.rejoin(name, client_id);
+        client_id
+    }
+
+    /*
+    Connects one more client of a secure harness, with its token in RENET_CONNECT_TOKEN like a
+    real client. The client reads it again on every reconnect, so one such client per test.
+    */
+    pub fn add_client_with_token(&mut self, name: &str) -> u64 {
+        let client_id = self.next_client_id;
+        self.next_client_id += 1;
+        let mut profile = LaunchConfig::new(NetworkMode::Client).net.profile;
+        profile.name = name.to_string();
+        let user_data = profile.to_user_data().unwrap();
+        let server_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, self.port));
+        let token = issue_connect_token(client_id, vec![server_addr], Some(&user_data), &SECURE_KEY).unwrap();
+        std::env::set_var("RENET_CONNECT_TOKEN", token_to_string(&token));
+        self.join(name, client_id, |_| {});
+        client_id
+    }
+
+    /*Connects one more client that only watches and returns its client id.*/
+    pub fn add_spectator(&mut self, name: &str) -> u64 {
+        let client_id = self.next_client_id;
+        self.next_client_id += 1;
+        self.join(name, client_id, |profile| profile.spectator = true);
+        client_id
+    }
+
+    /*Connects a client with the id of one that left, like a restarted client with the same connect token.*/
+    pub fn rejoin(&mut self, name: &str, client_id: u64) {
+        self.join(name, client_id, |_| {});
+    }
+
+    /*Connects one more client with a profile changed by edit, past what the launch settings would let through.*/
+    pub fn add_client_with(&mut self, name: &str, edit: impl FnOnce(&mut PlayerProfile)) -> u64 {
+        let client_id = self.next_client_id;
+        self.next_client_id += 1;
+        self.join(name, client_id, edit);
+        client_id
+    }
+
+    fn join(&mut self, name: &str, client_id: u64, edit: impl FnOnce(&mut PlayerProfile)) {
+        let mut launch = LaunchConfig::new(NetworkMode::Client);
+        launch.headless = true;
+        launch.net.port = self.port;
+        launch.net.discovery_port = 0;
+        launch.net.profile.name = name.to_string();
+        edit(&mut launch.net.profile);
+
+        let mut app = App::new();
+        app.insert_resource(ClientIdentity { client_id, profile: launch.net.profile.clone(), private_key: None });
+        self.clients.push(build(app, launch).expect("a harness client starts"));
+    }
+
+    /*Disconnects the client cleanly and drops its app.*/
+    pub fn remove_client(&mut self, index: usize) {
+        let mut client = self.clients.remove(index);
+        client.world.resource_mut::<NetcodeClientTransport>().disconnect();
+    }
+
+    /*One tick of the server, then one of every client.*/
+    pub fn step(&mut self) {
+        self.server.update();
+        for client in self.clients.iter_mut() {
+            client.update();
+        }
+    }
+
+    /*Steps until done holds, false when it did not within max_steps.*/
+    pub fn run_until(&mut self, max_steps: usize, mut done: impl FnMut(&Harness) -> bool) -> bool {
+        for _ in 0..max_steps {
+            self.step();
+            if done(self) {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn run(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.step();
+        }
+    }
+
+    pub fn server_lobby(&self) -> &ServerLobby {
+        self.server.world.resource::<ServerLobby>()
+    }
+
+    pub fn client_lobby(&self, index: usize) -> &ClientLobby {
+        self.clients[index].world.resource::<ClientLobby>()
+    }
+
+    pub fn network_mapping(&self, index: usize) -> &NetworkMapping {
+        self.clients[index].world.resource::<NetworkMapping>()
+    }
+
+    pub fn server_clock(&self, index: usize) -> &ServerClock {
+        self.clients[index].world.resource::<ServerClock>()
+    }
+
+    pub fn server_handshake(&self, index: usize) -> &ServerHandshake {
+        self.clients[index].world.resource::<ServerHandshake>()
+    }
+
+    pub fn server_time(&self) -> f64 {
+        self.server.world.resource::<Time>().elapsed_seconds_f64()
+    }
+
+    pub fn client_time(&self, index: usize) -> f64 {
+        self.clients[index].world.resource::<Time>().elapsed_seconds_f64()
+    }
+
+    /*True once the server and every client know every connected player.*/
+    pub fn everyone_connected(&self, client_ids: &[u64]) -> bool {
+        client_ids.iter().all(|id| self.server_lobby().players.contains_key(id))
+            && (0..self.clients.len()).all(|index| {
+                client_ids.iter().all(|id| self.client_lobby(index).players.contains_key(id))
+            })
+    }
+
+    /*Transform of the car the server simulates for client_id.*/
+    pub fn server_car(&self, client_id: u64) -> Option<Transform> {
+        let entity = *self.server_lobby().players.get(&client_id)?;
+        self.server.world.get::<Transform>(entity).copied()
+    }
+
+    /*Transform of the proxy a client shows for client_id.*/
+    pub fn client_car(&self, index: usize, client_id: u64) -> Option<Transform> {
+        let entity = self.client_lobby(index).players.get(&client_id)?.client_entity;
+        self.clients[index].world.get::<Transform>(entity).copied()
+    }
+}
+
+fn build(mut app: App, launch: LaunchConfig) -> Result<App, NetworkError> {
+    let tick_rate: TickRate = launch.tick_rate;
+    add_headless_plugins(&mut app, tick_rate);
+    car_app(&mut app, launch)?;
+    // every update advances the clock by exactly one tick, however long it took
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(1. / tick_rate.0)));
+    app.finish();
+    app.cleanup();
+    Ok(app)
+}
+
+/*A port nobody listens on yet, so tests running in parallel get their own server.*/
+fn free_port() -> u16 {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    socket.local_addr().unwrap().port()
+}
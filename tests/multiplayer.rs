@@ -0,0 +1,188 @@
+mod common;
+
+use bevy_racing_redux::renet::handshake::{Features, BUILD_VERSION};
+use bevy_racing_redux::renet::reconnect::{ConnectionStatus, ReconnectConfig};
+use bevy_racing_redux::renet::NetConfig;
+use bevy_renet::renet::RenetServer;
+use common::{Harness, RESUME_GRACE};
+
+/*Ten seconds of ticks, plenty for a loopback handshake.*/
+const CONNECT_STEPS: usize = 600;
+
+#[test]
+fn client_joins_the_lobby() {
+    let mut harness = Harness::start();
+    let id = harness.add_client("Alice");
+
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.everyone_connected(&[id])));
+    assert_eq!(harness.server_lobby().profiles[&id].name, "Alice");
+
+    // the client maps the server car to its own proxy
+    let server_entity = harness.server_lobby().players[&id];
+    let player_info = &harness.client_lobby(0).players[&id];
+    assert_eq!(player_info.server_entity, server_entity);
+    assert_eq!(harness.network_mapping(0).0.get(&server_entity), Some(&player_info.client_entity));
+}
+
+#[test]
+fn clients_see_each_other() {
+    let mut harness = Harness::start();
+    let alice = harness.add_client("Alice");
+    let bob = harness.add_client("Bob");
+
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.everyone_connected(&[alice, bob])));
+    for index in 0..2 {
+        assert_eq!(harness.client_lobby(index).players.len(), 2);
+        assert_eq!(harness.network_mapping(index).0.len(), 2);
+    }
+    assert_eq!(harness.client_lobby(0).players[&bob].profile.name, "Bob");
+    assert_eq!(harness.client_lobby(1).players[&alice].profile.name, "Alice");
+}
+
+#[test]
+fn remote_cars_follow_the_server() {
+    let mut harness = Harness::start();
+    let alice = harness.add_client("Alice");
+    let bob = harness.add_client("Bob");
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.everyone_connected(&[alice, bob])));
+
+    // let the cars drop on the track and the interpolation catch up
+    harness.run(180);
+    let server_car = harness.server_car(bob).unwrap();
+    let remote_car = harness.client_car(0, bob).unwrap();
+    let distance = server_car.translation.distance(remote_car.translation);
+    assert!(distance < 0.25, "Bob is {} away from the server car on Alice's client", distance);
+    assert!(server_car.rotation.angle_between(remote_car.rotation) < 0.05);
+}
+
+#[test]
+fn disconnect_removes_the_player() {
+    let mut harness = Harness::start();
+    let alice = harness.add_client("Alice");
+    let bob = harness.add_client("Bob");
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.everyone_connected(&[alice, bob])));
+
+    harness.remove_client(1);
+    // the car waits for Bob to come back for a while
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.server_lobby().suspended.contains_key(&bob)));
+    assert!(harness.client_lobby(0).players.contains_key(&bob));
+
+    let grace_steps = (RESUME_GRACE * 60.) as usize;
+    assert!(harness.run_until(grace_steps + CONNECT_STEPS, |h| {
+        !h.server_lobby().players.contains_key(&bob) && !h.client_lobby(0).players.contains_key(&bob)
+    }));
+    assert!(harness.server_lobby().suspended.is_empty());
+    assert!(harness.server_lobby().players.contains_key(&alice));
+    assert_eq!(harness.network_mapping(0).0.len(), 1);
+}
+
+#[test]
+fn returning_client_resumes_its_car() {
+    let mut harness = Harness::start();
+    let alice = harness.add_client("Alice");
+    let bob = harness.add_client("Bob");
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.everyone_connected(&[alice, bob])));
+    let server_entity = harness.server_lobby().players[&bob];
+
+    harness.remove_client(1);
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.server_lobby().suspended.contains_key(&bob)));
+    harness.rejoin("Bob", bob);
+    assert!(harness.run_until(CONNECT_STEPS, |h| {
+        h.everyone_connected(&[alice, bob]) && h.server_lobby().suspended.is_empty()
+    }));
+
+    // the same car on the server, Alice never saw it leave
+    assert_eq!(harness.server_lobby().players[&bob], server_entity);
+    assert_eq!(harness.client_lobby(0).players[&bob].server_entity, server_entity);
+    assert_eq!(harness.client_lobby(1).players[&bob].server_entity, server_entity);
+    // and stays past the grace period
+    harness.run((RESUME_GRACE * 60.) as usize + 30);
+    assert_eq!(harness.server_lobby().players.get(&bob), Some(&server_entity));
+}
+
+#[test]
+fn reconnected_spectator_forgets_who_left() {
+    let mut harness = Harness::start();
+    let alice = harness.add_client("Alice");
+    let bob = harness.add_client("Bob");
+    let carol = harness.add_spectator("Carol");
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.everyone_connected(&[alice, bob])));
+    assert_eq!(harness.client_lobby(2).players.len(), 2);
+
+    // Carol drops out and only comes back once Bob's car is gone, she never hears it leave
+    harness.clients[2].world.resource_mut::<ReconnectConfig>().base_delay = RESUME_GRACE as f64 * 3.;
+    harness.server.world.resource_mut::<RenetServer>().disconnect(carol);
+    harness.remove_client(1);
+    assert!(harness.run_until(CONNECT_STEPS, |h| {
+        matches!(*h.clients[1].world.resource::<ConnectionStatus>(), ConnectionStatus::Reconnecting { .. })
+    }));
+    assert!(harness.run_until(CONNECT_STEPS, |h| {
+        !h.server_lobby().players.contains_key(&bob)
+            && *h.clients[1].world.resource::<ConnectionStatus>() == ConnectionStatus::Connected
+            && h.server_handshake(1).is_accepted()
+    }));
+    assert!(harness.run_until(CONNECT_STEPS, |h| !h.client_lobby(1).players.contains_key(&bob)));
+
+    // Alice is announced again on the new connection
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.client_lobby(1).players.contains_key(&alice)));
+    let server_entity = harness.server_lobby().players[&alice];
+    assert_eq!(harness.client_lobby(1).players.len(), 1);
+    assert_eq!(harness.client_lobby(1).players[&alice].server_entity, server_entity);
+    assert_eq!(harness.network_mapping(1).0.len(), 1);
+}
+
+#[test]
+fn client_clock_follows_the_server() {
+    let mut harness = Harness::start();
+    // the client starts its clock well after the server did
+    harness.run(90);
+    let alice = harness.add_client("Alice");
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.everyone_connected(&[alice]) && h.server_clock(0).is_synced()));
+
+    // past the warmup pings
+    harness.run(60);
+    let clock = harness.server_clock(0);
+    let error = (clock.server_time(harness.client_time(0)) - harness.server_time()).abs();
+    assert!(clock.offset() > 1., "offset {} misses the head start of the server", clock.offset());
+    assert!(error <= clock.rtt() / 2. + 2. / 60., "server time is {} off, rtt {}", error, clock.rtt());
+}
+
+#[test]
+fn handshake_agrees_on_features() {
+    let mut harness = Harness::start();
+    let alice = harness.add_client("Alice");
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.everyone_connected(&[alice])));
+
+    let (build, features) = harness.server_handshake(0).accepted.clone().expect("the server took the client in");
+    assert_eq!(build, BUILD_VERSION);
+    assert_eq!(features, Features::SUPPORTED);
+    assert_eq!(harness.server_lobby().features.get(&alice), Some(&Features::SUPPORTED));
+}
+
+#[test]
+fn refused_client_learns_why() {
+    let mut harness = Harness::start();
+    let alice = harness.add_client("Alice");
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.everyone_connected(&[alice])));
+
+    // the limit is lowered while running, the transport still lets Bob connect
+    harness.server.world.resource_mut::<NetConfig>().max_clients = 1;
+    let bob = harness.add_client("Bob");
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.server_handshake(1).refusal.is_some()));
+    assert_eq!(harness.server_handshake(1).refusal.as_deref(), Some("the server is full"));
+    assert!(!harness.server_handshake(1).is_accepted());
+
+    // Bob is gone and Alice plays on
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.server_lobby().refused.is_empty()));
+    assert!(!harness.server_lobby().profiles.contains_key(&bob));
+    assert!(harness.server_lobby().players.contains_key(&alice));
+}
+
+#[test]
+fn unknown_archetype_is_refused() {
+    let mut harness = Harness::start();
+    let bob = harness.add_client_with("Bob", |profile| profile.archetype = 99);
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.server_handshake(0).refusal.is_some()));
+    assert_eq!(harness.server_handshake(0).refusal.as_deref(), Some("unknown car archetype 99"));
+    assert!(!harness.server_lobby().players.contains_key(&bob));
+}
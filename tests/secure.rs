@@ -0,0 +1,29 @@
+mod common;
+
+use bevy_racing_redux::renet::reconnect::ConnectionStatus;
+use bevy_renet::renet::RenetServer;
+use common::Harness;
+
+/*Ten seconds of ticks, plenty for a loopback handshake.*/
+const CONNECT_STEPS: usize = 600;
+
+#[test]
+fn client_resumes_with_the_same_token() {
+    let mut harness = Harness::start_secure();
+    let alice = harness.add_client_with_token("Alice");
+    assert!(harness.run_until(CONNECT_STEPS, |h| h.everyone_connected(&[alice])));
+    let server_entity = harness.server_lobby().players[&alice];
+
+    // the server drops Alice, she reconnects with the token she already used
+    harness.server.world.resource_mut::<RenetServer>().disconnect(alice);
+    assert!(harness.run_until(CONNECT_STEPS, |h| {
+        matches!(*h.clients[0].world.resource::<ConnectionStatus>(), ConnectionStatus::Reconnecting { .. })
+    }));
+    assert!(harness.run_until(CONNECT_STEPS, |h| {
+        *h.clients[0].world.resource::<ConnectionStatus>() == ConnectionStatus::Connected
+            && h.server_handshake(0).is_accepted()
+            && h.server_lobby().suspended.is_empty()
+            && h.everyone_connected(&[alice])
+    }));
+    assert_eq!(harness.server_lobby().players[&alice], server_entity);
+}